@@ -2,6 +2,7 @@ import { computed, onBeforeUnmount, onMounted, ref, watch } from "vue";
 
 const STATUS_URL = "https://m.ratf.cn/status";
 const SCHEDULE_URL = "https://m.ratf.cn/schedule";
+const SCHEDULE_ICS_URL = `${SCHEDULE_URL}.ics`;
 const BLOG_URL = "https://m.ratf.cn/blog";
 const VISITOR_URL = "https://m.ratf.cn/visitor";
 const VISITOR_VISIT_URL = "https://m.ratf.cn/visitor/visit";
@@ -35,6 +36,9 @@ export function useHomepageState() {
   const nextQuoteAt = ref(0);
 
   const statusList = ref([]);
+  const statusActivity = ref(null);
+  const scheduleStatus = ref(null);
+  const nowMs = ref(Date.now());
   const statusError = ref(false);
   const statusLoading = ref(false);
   const statusUpdatedAt = ref(0);
@@ -92,6 +96,8 @@ export function useHomepageState() {
   );
   const statusSummaryText = computed(() => {
     if (statusLoading.value && statusList.value.length === 0) return "加载中";
+    if (scheduleStatus.value?.effect === "busy") return `忙碌中 · ${scheduleStatus.value.text}`;
+    if (scheduleStatus.value?.effect === "offline") return scheduleStatus.value.text;
     if (hasOnlineDevice.value) return "营业中";
     if (allDevicesOffline.value) return "在忙/睡觉";
     return "暂时无法获取";
@@ -103,6 +109,57 @@ export function useHomepageState() {
     return isNight.value ? "text-meow-night-soft" : "text-meow-soft";
   });
 
+  const networkLabels = {
+    wifi: "Wi-Fi",
+    ethernet: "有线",
+    cellular: "蜂窝",
+    vpn: "VPN",
+    offline: "断网"
+  };
+  const deviceTelemetryText = (item) => {
+    const telemetry = item?.telemetry;
+    if (!telemetry) return "";
+    const parts = [];
+    if (typeof telemetry.battery_level === "number") {
+      parts.push(`🔋 ${telemetry.battery_level}%${telemetry.battery_charging ? " 充电中" : ""}`);
+    }
+    if (telemetry.network_type) {
+      parts.push(networkLabels[telemetry.network_type] || telemetry.network_type);
+    }
+    return parts.join(" · ");
+  };
+
+  const deviceKindIcons = {
+    pc: "💻",
+    phone: "📱",
+    server: "🖥️",
+    tablet: "📲"
+  };
+  const deviceIconUrl = (item) => (/^https?:\/\//i.test(item?.icon || "") ? item.icon : "");
+  const deviceIconText = (item) =>
+    deviceIconUrl(item) ? "" : item?.icon || deviceKindIcons[item?.kind] || "";
+
+  const musicArtworkUrl = (item) => {
+    if (!item?.music_artwork_url) return "";
+    try {
+      return new URL(item.music_artwork_url, STATUS_URL).href;
+    } catch {
+      return "";
+    }
+  };
+  const musicProgress = (item) => {
+    const duration = item?.music_duration_ms;
+    if (!duration || typeof item.music_position_ms !== "number") return null;
+    const elapsed =
+      item.music_playing && item.music_position_at ? Math.max(0, nowMs.value - item.music_position_at) : 0;
+    return Math.min(100, ((item.music_position_ms + elapsed) / duration) * 100);
+  };
+  const scheduleKey = (item) => `${item.id}@${item.recurrence_id || ""}`;
+  const scheduleActive = (item) =>
+    Boolean(item?.start_ts && item?.end_ts) &&
+    item.start_ts * 1000 <= nowMs.value &&
+    nowMs.value < item.end_ts * 1000;
+
   const splitTags = (tagValue) => {
     if (!tagValue || typeof tagValue !== "string") return [];
     return tagValue
@@ -113,6 +170,7 @@ export function useHomepageState() {
 
   const updateClock = () => {
     const now = new Date();
+    nowMs.value = now.getTime();
     time.value = now.toLocaleTimeString("zh-CN", {
       hour: "2-digit",
       minute: "2-digit",
@@ -186,11 +244,13 @@ export function useHomepageState() {
     statusNextAt.value = Date.now() + statusCooldownMs;
     statusLoading.value = true;
     try {
-      const res = await fetch(STATUS_URL);
+      const res = await fetch(`${STATUS_URL}?with=activity,schedule`);
       if (!res.ok) throw new Error("status fetch failed");
       const data = await res.json();
-      if (Array.isArray(data)) {
-        statusList.value = data;
+      if (Array.isArray(data?.devices)) {
+        statusList.value = data.devices;
+        statusActivity.value = data.activity || null;
+        scheduleStatus.value = data.schedule || null;
         statusError.value = false;
         statusUpdatedAt.value = Date.now();
       }
@@ -206,7 +266,7 @@ export function useHomepageState() {
     scheduleNextAt.value = Date.now() + scheduleCooldownMs;
     scheduleLoading.value = true;
     try {
-      const res = await fetch(SCHEDULE_URL);
+      const res = await fetch(`${SCHEDULE_URL}?from=${Math.floor(Date.now() / 60000) * 60}`);
       if (!res.ok) throw new Error("schedule fetch failed");
       const data = await res.json();
       if (Array.isArray(data)) {
@@ -408,10 +468,12 @@ export function useHomepageState() {
     quoteError,
     quoteLoading,
     statusList,
+    statusActivity,
     statusError,
     statusLoading,
     statusUpdatedAt,
     scheduleList,
+    scheduleIcsUrl: SCHEDULE_ICS_URL,
     scheduleError,
     scheduleLoading,
     scheduleUpdatedAt,
@@ -429,6 +491,13 @@ export function useHomepageState() {
     allDevicesOffline,
     statusSummaryText,
     statusSummaryClass,
+    deviceTelemetryText,
+    deviceIconUrl,
+    deviceIconText,
+    musicArtworkUrl,
+    musicProgress,
+    scheduleKey,
+    scheduleActive,
     splitTags,
     toggleTheme,
     canFetchQuote,
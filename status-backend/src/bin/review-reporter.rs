@@ -339,7 +339,7 @@ async fn evaluate_application(
         reasons.push("命中垃圾关键词".to_string());
     }
     let desc_len = app.description.clone().unwrap_or_default().chars().count();
-    if desc_len >= 8 && desc_len <= 180 {
+    if (8..=180).contains(&desc_len) {
         score += 12;
     } else {
         score -= 10;
@@ -0,0 +1,113 @@
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Mutex};
+
+#[derive(Clone)]
+pub struct Snapshot {
+    body: Bytes,
+    content_type: &'static str,
+    etag: String,
+    last_modified: i64,
+}
+
+/// Serialized JSON bodies of public read endpoints, keyed by resource.
+///
+/// Keys are either a bare resource name (`schedule`) or `resource:detail`
+/// (`blog:hello-world`); invalidating a resource drops both forms. Entries
+/// must be stored while the DB lock is held so a concurrent write cannot
+/// slip in between reading the rows and caching them.
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, Snapshot>>,
+}
+
+impl ResponseCache {
+    pub fn get(&self, key: &str) -> Option<Snapshot> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn store<T: Serialize>(&self, key: &str, value: &T, last_modified: i64) -> Snapshot {
+        let body = serde_json::to_vec(value).expect("serialize cached response");
+        self.store_raw(key, body, "application/json", last_modified)
+    }
+
+    pub fn store_raw(
+        &self,
+        key: &str,
+        body: Vec<u8>,
+        content_type: &'static str,
+        last_modified: i64,
+    ) -> Snapshot {
+        let digest = Sha256::digest(&body);
+        let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        let snapshot = Snapshot {
+            body: Bytes::from(body),
+            content_type,
+            etag: format!("\"{:x}-{}\"", last_modified.max(0), hash),
+            last_modified,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), snapshot.clone());
+        snapshot
+    }
+
+    pub fn invalidate(&self, resource: &str) {
+        self.entries.lock().unwrap().retain(|key, _| {
+            key != resource
+                && !key
+                    .strip_prefix(resource)
+                    .is_some_and(|rest| rest.starts_with(':'))
+        });
+    }
+}
+
+pub fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+        })
+}
+
+pub fn respond(headers: &HeaderMap, snapshot: &Snapshot, cache_control: &str) -> Response {
+    let mut resp = if etag_matches(headers, &snapshot.etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        (
+            [(header::CONTENT_TYPE, snapshot.content_type)],
+            snapshot.body.clone(),
+        )
+            .into_response()
+    };
+    let out = resp.headers_mut();
+    if let Ok(value) = snapshot.etag.parse() {
+        out.insert(header::ETAG, value);
+    }
+    if let Ok(value) = cache_control.parse() {
+        out.insert(header::CACHE_CONTROL, value);
+    }
+    if let Some(value) = http_date(snapshot.last_modified).and_then(|v| v.parse().ok()) {
+        out.insert(header::LAST_MODIFIED, value);
+    }
+    resp
+}
+
+fn http_date(ts: i64) -> Option<String> {
+    if ts <= 0 {
+        return None;
+    }
+    Utc.timestamp_opt(ts, 0)
+        .single()
+        .map(|dt| dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
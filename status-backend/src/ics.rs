@@ -0,0 +1,399 @@
+use chrono::{DateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Builds an iCalendar stream with CRLF line endings and 75-octet folding.
+#[derive(Default)]
+pub struct Writer {
+    out: String,
+}
+
+impl Writer {
+    pub fn line(&mut self, name: &str, value: &str) {
+        let line = format!("{}:{}", name, value);
+        let mut width = 0;
+        for ch in line.chars() {
+            let len = ch.len_utf8();
+            if width + len > MAX_LINE_OCTETS {
+                self.out.push_str("\r\n ");
+                width = 1;
+            }
+            self.out.push(ch);
+            width += len;
+        }
+        self.out.push_str("\r\n");
+    }
+
+    pub fn text(&mut self, name: &str, value: &str) {
+        self.line(name, &escape_text(value));
+    }
+
+    pub fn extend(&mut self, other: Writer) {
+        self.out.push_str(&other.out);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+pub fn escape_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+pub fn utc_stamp(ts: i64) -> String {
+    DateTime::from_timestamp(ts, 0)
+        .unwrap_or_default()
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// Writes a VTIMEZONE for `tz` with one observance per UTC offset change
+/// between `from_year` and `to_year` (inclusive), found by scanning day by day.
+pub fn write_vtimezone(w: &mut Writer, tz: Tz, from_year: i32, to_year: i32) {
+    let (Some(start), Some(end)) = (
+        Utc.with_ymd_and_hms(from_year, 1, 1, 0, 0, 0).single(),
+        Utc.with_ymd_and_hms(to_year + 1, 1, 1, 0, 0, 0).single(),
+    ) else {
+        return;
+    };
+    let (start, end) = (start.timestamp(), end.timestamp());
+    w.line("BEGIN", "VTIMEZONE");
+    w.line("TZID", tz.name());
+    let initial = utc_offset(tz, start);
+    write_observance(w, tz, start, initial);
+    let mut ts = start;
+    while ts < end {
+        let next = ts + 86400;
+        let before = utc_offset(tz, ts);
+        if utc_offset(tz, next) != before {
+            let (mut lo, mut hi) = (ts, next);
+            while hi - lo > 60 {
+                let mid = lo + (hi - lo) / 2;
+                if utc_offset(tz, mid) == before {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            let mut at = hi;
+            while utc_offset(tz, at - 1) != before {
+                at -= 1;
+            }
+            write_observance(w, tz, at, before);
+        }
+        ts = next;
+    }
+    w.line("END", "VTIMEZONE");
+}
+
+fn write_observance(w: &mut Writer, tz: Tz, at: i64, offset_from: i32) {
+    let Some(instant) = DateTime::from_timestamp(at, 0) else {
+        return;
+    };
+    let offset = tz.offset_from_utc_datetime(&instant.naive_utc());
+    let kind = if offset.dst_offset().num_seconds() != 0 {
+        "DAYLIGHT"
+    } else {
+        "STANDARD"
+    };
+    let local = instant.naive_utc() + chrono::Duration::seconds(offset_from as i64);
+    w.line("BEGIN", kind);
+    w.line("DTSTART", &local.format("%Y%m%dT%H%M%S").to_string());
+    w.line("TZOFFSETFROM", &format_offset(offset_from));
+    w.line("TZOFFSETTO", &format_offset(offset.fix().local_minus_utc()));
+    w.text("TZNAME", offset.abbreviation());
+    w.line("END", kind);
+}
+
+fn utc_offset(tz: Tz, ts: i64) -> i32 {
+    DateTime::from_timestamp(ts, 0)
+        .map(|dt| {
+            tz.offset_from_utc_datetime(&dt.naive_utc())
+                .fix()
+                .local_minus_utc()
+        })
+        .unwrap_or(0)
+}
+
+fn format_offset(secs: i32) -> String {
+    let sign = if secs < 0 { '-' } else { '+' };
+    let secs = secs.abs();
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if seconds == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, seconds)
+    }
+}
+
+pub struct Property {
+    pub name: String,
+    params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Component {
+    pub props: Vec<Property>,
+}
+
+impl Component {
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.props.iter().find(|prop| prop.name == name)
+    }
+
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> + 'a {
+        self.props.iter().filter(move |prop| prop.name == name)
+    }
+
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.get(name)
+            .map(|prop| unescape_text(&prop.value))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    }
+}
+
+/// Collects every `kind` component (e.g. `VEVENT`) with its own properties;
+/// properties of nested components such as `VALARM` are dropped.
+pub fn parse_components(input: &str, kind: &str) -> Vec<Component> {
+    let mut out = Vec::new();
+    let mut current: Option<Component> = None;
+    let mut nested = 0usize;
+    for line in unfold(input) {
+        let Some(prop) = parse_line(&line) else {
+            continue;
+        };
+        let value = prop.value.trim().to_ascii_uppercase();
+        match (prop.name.as_str(), current.as_mut()) {
+            ("BEGIN", None) if value == kind => current = Some(Component { props: Vec::new() }),
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if value == kind => out.extend(current.take()),
+            (_, Some(component)) if nested == 0 => component.props.push(prop),
+            _ => {}
+        }
+    }
+    out
+}
+
+pub fn unescape_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Splits a multi-valued TEXT property (such as CATEGORIES) on unescaped commas.
+pub fn split_text_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for ch in value.chars() {
+        if escaped {
+            current.push('\\');
+            current.push(ch);
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if ch == ',' {
+            items.push(unescape_text(&current));
+            current.clear();
+        } else {
+            current.push(ch);
+        }
+    }
+    items.push(unescape_text(&current));
+    items
+        .into_iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn unfold(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in input.split('\n') {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        match (raw.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(raw.to_string()),
+        }
+    }
+    lines
+}
+
+fn parse_line(line: &str) -> Option<Property> {
+    let mut in_quotes = false;
+    let mut split = None;
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => {
+                split = Some(idx);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let split = split?;
+    let (head, value) = (&line[..split], &line[split + 1..]);
+    let mut parts = Vec::new();
+    let mut start = 0;
+    in_quotes = false;
+    for (idx, ch) in head.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                parts.push(&head[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&head[start..]);
+    let name = parts.first()?.trim().to_ascii_uppercase();
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts[1..]
+        .iter()
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| {
+            (
+                key.trim().to_ascii_uppercase(),
+                value.trim().trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+    Some(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_at_75_octets_without_splitting_characters() {
+        let mut w = Writer::default();
+        let value = "日程".repeat(40);
+        w.text("SUMMARY", &value);
+        let out = w.finish();
+        assert!(out.ends_with("\r\n"));
+        let lines: Vec<&str> = out.trim_end_matches("\r\n").split("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_OCTETS));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        let events = parse_components(&format!("BEGIN:VEVENT\r\n{}END:VEVENT\r\n", out), "VEVENT");
+        assert_eq!(events[0].text("SUMMARY").as_deref(), Some(value.as_str()));
+    }
+
+    #[test]
+    fn unfolds_and_parses_quoted_params() {
+        let input = "BEGIN:VCALENDAR\nBEGIN:VEVENT\r\n\
+                     DTSTART;TZID=\"America/New_York\";X-NOTE=\"a;b:c\":20260105T\r\n \
+                     090000\r\n\
+                     summary:Line\\, one\\nnext\r\n\
+                     BEGIN:VALARM\r\nSUMMARY:alarm\r\nEND:VALARM\r\n\
+                     CATEGORIES:work,a\\,b, \r\n\
+                     END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let events = parse_components(input, "VEVENT");
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        let dtstart = event.get("DTSTART").unwrap();
+        assert_eq!(dtstart.value, "20260105T090000");
+        assert_eq!(dtstart.param("tzid"), Some("America/New_York"));
+        assert_eq!(dtstart.param("X-NOTE"), Some("a;b:c"));
+        assert_eq!(event.text("SUMMARY").as_deref(), Some("Line, one\nnext"));
+        assert_eq!(event.all("SUMMARY").count(), 1);
+        let categories = split_text_list(&event.get("CATEGORIES").unwrap().value);
+        assert_eq!(categories, ["work", "a,b"]);
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        let value = "a\\b;c,d\ne";
+        assert_eq!(escape_text(value), r"a\\b\;c\,d\ne");
+        assert_eq!(unescape_text(&escape_text(value)), value);
+    }
+
+    #[test]
+    fn vtimezone_lists_dst_transitions() {
+        let mut w = Writer::default();
+        write_vtimezone(&mut w, chrono_tz::Europe::Berlin, 2026, 2026);
+        let out = w.finish();
+        let lines: Vec<&str> = out.split("\r\n").collect();
+        let expected = [
+            "BEGIN:DAYLIGHT",
+            "DTSTART:20260329T020000",
+            "TZOFFSETFROM:+0100",
+            "TZOFFSETTO:+0200",
+            "TZNAME:CEST",
+            "END:DAYLIGHT",
+            "BEGIN:STANDARD",
+            "DTSTART:20261025T030000",
+            "TZOFFSETFROM:+0200",
+            "TZOFFSETTO:+0100",
+            "TZNAME:CET",
+            "END:STANDARD",
+        ];
+        assert_eq!(&lines[..2], ["BEGIN:VTIMEZONE", "TZID:Europe/Berlin"]);
+        assert!(lines
+            .windows(expected.len())
+            .any(|window| window == expected));
+        assert_eq!(lines.iter().filter(|l| l.starts_with("BEGIN:")).count(), 4);
+    }
+
+    #[test]
+    fn vtimezone_without_dst_has_one_observance() {
+        let mut w = Writer::default();
+        write_vtimezone(&mut w, chrono_tz::Asia::Shanghai, 2026, 2027);
+        let out = w.finish();
+        assert!(out.contains("BEGIN:STANDARD\r\nDTSTART:20260101T080000\r\n"));
+        assert!(out.contains("TZOFFSETTO:+0800\r\n"));
+        assert_eq!(out.matches("BEGIN:STANDARD").count(), 1);
+        assert!(!out.contains("DAYLIGHT"));
+    }
+
+    #[test]
+    fn offsets_keep_seconds_when_present() {
+        assert_eq!(format_offset(-18000), "-0500");
+        assert_eq!(format_offset(19800), "+0530");
+        assert_eq!(format_offset(-1258), "-002058");
+    }
+}
@@ -1,26 +1,42 @@
 mod admin_pages;
+mod cache;
+mod ics;
+mod metrics;
+mod rrule;
 use axum::{
+    body::Bytes,
     extract::{Query, State},
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, patch, post, put},
     Json, Router,
 };
-use chrono::{Datelike, Utc};
+use cache::ResponseCache;
+use chrono::{Datelike, NaiveTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
 use lettre::{
     message::{header::ContentType, Mailbox, MultiPart, SinglePart},
     transport::smtp::authentication::Credentials,
     AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
+use metrics::{TimedMutex, METRICS};
 use reqwest::Url;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
     net::{IpAddr, SocketAddr},
     sync::{Arc, Mutex},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::sync::{broadcast, Notify};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use trust_dns_resolver::{
     config::{ResolverConfig, ResolverOpts},
     lookup::TxtLookup,
@@ -30,15 +46,96 @@ use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber::EnvFilter;
 
 const VERIFY_EXPIRED_CLEANUP_GRACE_SECS: i64 = 12 * 60 * 60;
+const DEFAULT_DEVICE_STALE_SECS: i64 = 300;
+const MIN_DEVICE_STALE_SECS: i64 = 30;
+const MAX_DEVICE_STALE_SECS: i64 = 7 * 24 * 3600;
+const STATUS_STALE_CHECK_INTERVAL_SECS: u64 = 10;
+const PRESENCE_HISTORY_MAX_RANGE_SECS: i64 = 92 * 24 * 3600;
+/// A paused or stalled play of the same track that resumes within this window
+/// continues the previous play instead of starting a new one.
+const MUSIC_RESUME_GRACE_SECS: i64 = 600;
+const TELEMETRY_VERSION: u32 = 1;
+const BACKFILL_MAX_FUTURE_SKEW_SECS: i64 = 60;
+const DEVICE_KINDS: [&str; 4] = ["pc", "phone", "server", "tablet"];
+const MUSIC_ARTWORK_MAX_BYTES: usize = 1024 * 1024;
+const MUSIC_ARTWORK_RETENTION_SECS: i64 = 7 * 24 * 3600;
+const WEBHOOK_EVENTS: [&str; 5] = [
+    "device.online",
+    "device.offline",
+    "music.start",
+    "music.stop",
+    "manual_offline.toggled",
+];
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const WEBHOOK_POLL_INTERVAL_SECS: u64 = 5;
+const WEBHOOK_RETRY_BASE_SECS: i64 = 30;
+const WEBHOOK_RETRY_MAX_SECS: i64 = 3600;
+const WEBHOOK_DELIVERY_RETENTION_SECS: i64 = 14 * 24 * 3600;
+const TELEGRAM_POLL_TIMEOUT_SECS: u64 = 25;
+const SUMMARY_STATES: [&str; 6] = ["dnd", "busy", "listening", "active", "idle", "offline"];
+const DEFAULT_STATUS_TOKEN: &str = "KFCVME50";
+const ENROLLMENT_CODE_DEFAULT_TTL_SECS: i64 = 30 * 60;
+const ENROLL_REDEEM_WINDOW_SECS: i64 = 15 * 60;
+const ENROLL_REDEEM_IP_MAX_FAILURES: i64 = 10;
+const ENROLL_REDEEM_MAX_FAILURES: i64 = 100;
+const SCHEDULE_DEFAULT_WINDOW_SECS: i64 = 30 * 24 * 3600;
+/// Schedule status effects, strongest first; when several items are in
+/// progress the strongest effect wins.
+const SCHEDULE_STATUS_EFFECTS: [&str; 3] = ["offline", "busy", "activity"];
+const SCHEDULE_MAX_WINDOW_SECS: i64 = 366 * 24 * 3600;
+const SCHEDULE_MAX_OCCURRENCES: usize = 1000;
+const SCHEDULE_REMINDER_MAX_OFFSET_MINUTES: i64 = 7 * 24 * 60;
+const SCHEDULE_REMINDER_INTERVAL_SECS: u64 = 30;
+/// Reminders that fell due longer ago than this (e.g. while the backend was
+/// down) are dropped instead of being sent late.
+const SCHEDULE_REMINDER_GRACE_SECS: i64 = 10 * 60;
+const SCHEDULE_REMINDER_RETENTION_SECS: i64 = 30 * 24 * 3600;
 
 #[derive(Clone)]
 struct AppState {
-    db: Arc<Mutex<Connection>>,
+    db: Arc<TimedMutex<Connection>>,
     token: String,
+    legacy_device_token: bool,
+    require_signature: bool,
+    signature_max_skew_secs: i64,
+    webhook_max_attempts: i64,
+    metrics_token: Option<String>,
+    badge_max_age: i64,
+    backfill_max_samples: usize,
+    backfill_max_age_secs: i64,
+    device_prune_days: i64,
+    live_cache_control: String,
+    content_cache_control: String,
+    cache: Arc<ResponseCache>,
+    schedule_timezone: chrono_tz::Tz,
     review_report_token: String,
     notifier: Arc<Notifier>,
     auto_review: Arc<AutoReviewConfig>,
     anti_abuse: Arc<AntiAbuseConfig>,
+    presence: Arc<PresenceConfig>,
+    hub: Arc<StatusHub>,
+}
+
+struct StatusHub {
+    tx: broadcast::Sender<StatusEvent>,
+    cache: Arc<ResponseCache>,
+    last: Mutex<HashMap<String, DeviceStatus>>,
+    last_manual_offline: Mutex<bool>,
+    last_activity: Mutex<Option<StatusActivity>>,
+    last_schedule: Mutex<Option<ScheduleStatus>>,
+    webhook_wake: Notify,
+}
+
+#[derive(Clone)]
+enum StatusEvent {
+    Device(Box<DeviceStatus>),
+    Removed(String),
+    Activity(Option<StatusActivity>),
+}
+
+#[derive(Serialize)]
+struct DeviceRemovedEvent {
+    device_id: String,
 }
 
 #[derive(Deserialize)]
@@ -51,9 +148,54 @@ struct Heartbeat {
     music_title: Option<String>,
     music_artist: Option<String>,
     music_source: Option<String>,
+    music_album: Option<String>,
+    music_duration_ms: Option<i64>,
+    music_position_ms: Option<i64>,
+    music_position_at: Option<i64>,
+    music_artwork: Option<String>,
+    telemetry: Option<DeviceTelemetry>,
+}
+
+#[derive(Deserialize)]
+struct HeartbeatBackfill {
+    device_id: String,
+    device_name: String,
+    samples: Vec<HeartbeatSample>,
+}
+
+#[derive(Deserialize)]
+struct HeartbeatSample {
+    ts: i64,
+    online: bool,
+    idle_seconds: Option<u64>,
+    music_playing: Option<bool>,
+    music_title: Option<String>,
+    music_artist: Option<String>,
+    music_source: Option<String>,
 }
 
 #[derive(Serialize)]
+struct HeartbeatBackfillResult {
+    accepted: usize,
+    skipped: usize,
+    last_seen: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+struct DeviceTelemetry {
+    #[serde(default = "default_telemetry_version")]
+    version: u32,
+    battery_level: Option<i64>,
+    battery_charging: Option<bool>,
+    network_type: Option<String>,
+    os: Option<String>,
+    platform: Option<String>,
+    client_version: Option<String>,
+    #[serde(default, skip_deserializing)]
+    updated_at: Option<i64>,
+}
+
+#[derive(Serialize, Clone, PartialEq)]
 struct DeviceStatus {
     device_id: String,
     device_name: String,
@@ -66,10 +208,71 @@ struct DeviceStatus {
     music_title: Option<String>,
     music_artist: Option<String>,
     music_source: Option<String>,
+    music_source_icon: Option<String>,
+    music_album: Option<String>,
+    music_duration_ms: Option<i64>,
+    music_position_ms: Option<i64>,
+    music_position_at: Option<i64>,
+    music_artwork_url: Option<String>,
     music_updated_at: Option<i64>,
+    stale_after_secs: Option<i64>,
+    idle_away_secs: Option<i64>,
+    kind: Option<String>,
+    icon: Option<String>,
+    sort_order: i64,
+    #[serde(skip)]
+    hidden: bool,
+    telemetry: Option<DeviceTelemetry>,
+    schedule: Option<ScheduleStatus>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Serialize)]
+struct DeviceAdminItem {
+    #[serde(flatten)]
+    status: DeviceStatus,
+    reported_name: String,
+    display_name: Option<String>,
+    hidden: bool,
+}
+
+#[derive(Deserialize)]
+struct DevicePatchPayload {
+    display_name: Option<String>,
+    kind: Option<String>,
+    icon: Option<String>,
+    sort_order: Option<i64>,
+    hidden: Option<bool>,
+}
+
+#[derive(Serialize, Clone, PartialEq)]
+struct StatusActivity {
+    text: String,
+    emoji: Option<String>,
+    link: Option<String>,
+    expires_at: Option<i64>,
+    updated_at: i64,
+}
+
+/// The schedule item whose status effect currently applies.
+#[derive(Serialize, Clone, PartialEq)]
+struct ScheduleStatus {
+    item_id: String,
+    effect: String,
+    text: String,
+    started_at: i64,
+    ends_at: i64,
+}
+
+#[derive(Deserialize)]
+struct StatusActivityPayload {
+    text: Option<String>,
+    emoji: Option<String>,
+    link: Option<String>,
+    expires_at: Option<i64>,
+    duration_secs: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
 struct ScheduleItem {
     id: String,
     title: String,
@@ -77,8 +280,26 @@ struct ScheduleItem {
     note: Option<String>,
     location: Option<String>,
     tag: Option<String>,
+    start_at: Option<String>,
+    end_at: Option<String>,
+    timezone: Option<String>,
+    #[serde(default)]
+    all_day: bool,
+    rrule: Option<String>,
+    #[serde(default)]
+    exdates: Vec<String>,
+    source: Option<String>,
+    status_effect: Option<String>,
+    status_text: Option<String>,
+    /// Minutes before the start at which a reminder is sent.
+    #[serde(default)]
+    reminders: Vec<i64>,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+    recurrence_id: Option<String>,
     sort_order: i64,
     updated_at: i64,
+    version: i64,
 }
 
 #[derive(Deserialize)]
@@ -90,11 +311,118 @@ struct SchedulePayload {
 struct ScheduleItemInput {
     id: Option<String>,
     title: String,
-    time: String,
+    time: Option<String>,
+    note: Option<String>,
+    location: Option<String>,
+    tag: Option<String>,
+    start_at: Option<String>,
+    end_at: Option<String>,
+    timezone: Option<String>,
+    all_day: Option<bool>,
+    rrule: Option<String>,
+    exdates: Option<Vec<String>>,
+    source: Option<String>,
+    status_effect: Option<String>,
+    status_text: Option<String>,
+    reminders: Option<Vec<i64>>,
+    sort_order: Option<i64>,
+    /// Expected current version for `PUT /schedule/:id`; ignored by the bulk endpoint.
+    version: Option<i64>,
+}
+
+/// Partial update; omitted fields are kept and empty strings clear optional ones.
+#[derive(Deserialize)]
+struct SchedulePatchPayload {
+    title: Option<String>,
+    time: Option<String>,
     note: Option<String>,
     location: Option<String>,
     tag: Option<String>,
+    start_at: Option<String>,
+    end_at: Option<String>,
+    timezone: Option<String>,
+    all_day: Option<bool>,
+    rrule: Option<String>,
+    exdates: Option<Vec<String>>,
+    source: Option<String>,
+    status_effect: Option<String>,
+    status_text: Option<String>,
+    reminders: Option<Vec<i64>>,
     sort_order: Option<i64>,
+    version: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ScheduleReminderQuery {
+    item_id: Option<String>,
+    status: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct ScheduleReminderItem {
+    item_id: String,
+    title: String,
+    occurrence_start: i64,
+    offset_minutes: i64,
+    status: String,
+    channels: Vec<String>,
+    last_error: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Deserialize)]
+struct VersionQuery {
+    version: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct VersionConflict<T> {
+    message: String,
+    current: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct ScheduleImportQuery {
+    source: Option<String>,
+    mode: Option<String>,
+    dry_run: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ScheduleImportResult {
+    dry_run: bool,
+    mode: String,
+    source: String,
+    created: usize,
+    updated: usize,
+    removed: usize,
+    skipped: Vec<ScheduleImportSkip>,
+    items: Vec<ScheduleItem>,
+}
+
+#[derive(Serialize)]
+struct ScheduleImportSkip {
+    uid: String,
+    summary: String,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct ScheduleQuery {
+    from: Option<String>,
+    to: Option<String>,
+    now: Option<String>,
+}
+
+struct ScheduleTiming {
+    start: chrono::NaiveDateTime,
+    end: Option<chrono::NaiveDateTime>,
+    tz: chrono_tz::Tz,
+    all_day: bool,
+    rule: Option<rrule::Rule>,
+    exdates: Vec<chrono::NaiveDateTime>,
 }
 
 #[derive(Deserialize)]
@@ -104,13 +432,48 @@ struct VisitPayload {
 
 #[derive(Deserialize)]
 struct ManualStatusPayload {
-    enabled: bool,
+    enabled: Option<bool>,
+    offline_until: Option<i64>,
+    quiet_hours: Option<Vec<QuietHoursInput>>,
 }
 
 #[derive(Serialize)]
 struct ManualStatusResponse {
     enabled: bool,
     updated_at: i64,
+    offline_until: Option<i64>,
+    quiet_hours: Vec<QuietHoursRule>,
+    active: Option<ManualOfflineActive>,
+}
+
+#[derive(Deserialize)]
+struct QuietHoursInput {
+    label: Option<String>,
+    weekdays: Vec<u32>,
+    start: String,
+    end: String,
+    timezone: String,
+    enabled: Option<bool>,
+}
+
+#[derive(Serialize, Clone)]
+struct QuietHoursRule {
+    id: i64,
+    label: Option<String>,
+    weekdays: Vec<u32>,
+    start: String,
+    end: String,
+    timezone: String,
+    enabled: bool,
+}
+
+#[derive(Serialize, Clone)]
+struct ManualOfflineActive {
+    source: String,
+    rule_id: Option<i64>,
+    label: Option<String>,
+    since: Option<i64>,
+    ends_at: Option<i64>,
 }
 
 #[derive(Deserialize)]
@@ -125,7 +488,242 @@ struct DeviceStatusUpdatePayload {
     music_source: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct StatusQuery {
+    with: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PresenceHistoryQuery {
+    device_id: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct SvgCardQuery {
+    device_id: Option<String>,
+    theme: Option<String>,
+    width: Option<u32>,
+}
+
+struct SvgTheme {
+    background: &'static str,
+    border: &'static str,
+    label: &'static str,
+    text: &'static str,
+    muted: &'static str,
+}
+
+#[derive(Serialize)]
+struct DevicePresenceHistory {
+    device_id: String,
+    from: i64,
+    to: i64,
+    sessions: Vec<PresenceSession>,
+    totals: PresenceTotals,
+}
+
+#[derive(Serialize)]
+struct PresenceSession {
+    state: String,
+    started_at: i64,
+    ended_at: i64,
+    duration: i64,
+}
+
+#[derive(Serialize, Default)]
+struct PresenceTotals {
+    online: i64,
+    idle: i64,
+    offline: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SummaryRules {
+    #[serde(default)]
+    state_priority: Vec<String>,
+    #[serde(default)]
+    device_priority: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StatusSummary {
+    state: String,
+    device_id: Option<String>,
+    device_name: Option<String>,
+    since: Option<i64>,
+    activity: Option<StatusActivity>,
+    schedule: Option<ScheduleStatus>,
+    devices: Vec<DeviceSummaryState>,
+}
+
+/// `/status?with=...` wraps the device list so shared fields are sent once.
+#[derive(Serialize)]
+struct StatusWithExtras {
+    devices: Vec<DeviceStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    activity: Option<Option<StatusActivity>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<Option<ScheduleStatus>>,
+}
+
+#[derive(Serialize)]
+struct DeviceSummaryState {
+    device_id: String,
+    device_name: String,
+    state: String,
+    since: Option<i64>,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
+struct MusicRule {
+    id: Option<i64>,
+    kind: String,
+    device_id: Option<String>,
+    source_pattern: Option<String>,
+    title_pattern: Option<String>,
+    display_name: Option<String>,
+    icon: Option<String>,
+    sort_order: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct MusicRuleDeletePayload {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct WebhookPayload {
+    id: Option<i64>,
+    url: String,
+    secret: Option<String>,
+    events: Option<Vec<String>>,
+    enabled: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct WebhookItem {
+    id: i64,
+    url: String,
+    secret: String,
+    events: Vec<String>,
+    enabled: bool,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Deserialize)]
+struct WebhookIdPayload {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct WebhookDeliveryQuery {
+    webhook_id: Option<i64>,
+    status: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct WebhookDeliveryItem {
+    id: i64,
+    webhook_id: i64,
+    event: String,
+    status: String,
+    attempts: i64,
+    next_attempt_at: Option<i64>,
+    response_status: Option<i64>,
+    last_error: Option<String>,
+    payload: serde_json::Value,
+    created_at: i64,
+    updated_at: i64,
+}
+
+struct PendingWebhookDelivery {
+    id: i64,
+    event: String,
+    url: String,
+    secret: String,
+    payload: String,
+    attempts: i64,
+}
+
+struct MusicFields {
+    playing: bool,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    artwork: Option<String>,
+    source: Option<String>,
+    source_icon: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MusicArtworkQuery {
+    device_id: String,
+}
+
+#[derive(Serialize)]
+struct MusicArtworkUploaded {
+    hash: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct MusicHistoryQuery {
+    device_id: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct MusicPlay {
+    id: i64,
+    device_id: String,
+    title: Option<String>,
+    artist: Option<String>,
+    source: Option<String>,
+    started_at: i64,
+    ended_at: Option<i64>,
+    listened_secs: i64,
+}
+
+#[derive(Deserialize)]
+struct MusicStatsQuery {
+    period: Option<String>,
+    device_id: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct MusicStats {
+    period: String,
+    from: i64,
+    to: i64,
+    total_listened_secs: i64,
+    play_count: i64,
+    top_tracks: Vec<MusicTrackStat>,
+    top_artists: Vec<MusicArtistStat>,
+}
+
+#[derive(Serialize)]
+struct MusicTrackStat {
+    title: Option<String>,
+    artist: Option<String>,
+    plays: i64,
+    listened_secs: i64,
+}
+
+#[derive(Serialize)]
+struct MusicArtistStat {
+    artist: String,
+    plays: i64,
+    listened_secs: i64,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
 struct BlogPost {
     slug: String,
     title: String,
@@ -136,6 +734,7 @@ struct BlogPost {
     content_md: String,
     sort_order: i64,
     updated_at: i64,
+    version: i64,
 }
 
 #[derive(Serialize)]
@@ -147,6 +746,7 @@ struct BlogPostSummary {
     excerpt: String,
     sort_order: i64,
     updated_at: i64,
+    version: i64,
 }
 
 #[derive(Deserialize)]
@@ -164,6 +764,21 @@ struct BlogPostInput {
     content: Option<Vec<String>>,
     content_md: Option<String>,
     sort_order: Option<i64>,
+    /// Expected current version for `PUT /blog/:slug`; ignored by the bulk endpoint.
+    version: Option<i64>,
+}
+
+/// Partial update; omitted fields are kept and an empty `tag` clears it.
+#[derive(Deserialize)]
+struct BlogPatchPayload {
+    title: Option<String>,
+    date: Option<String>,
+    tag: Option<String>,
+    excerpt: Option<String>,
+    content: Option<Vec<String>>,
+    content_md: Option<String>,
+    sort_order: Option<i64>,
+    version: Option<i64>,
 }
 
 #[derive(Serialize)]
@@ -400,6 +1015,46 @@ struct SmtpTestPayload {
     recipient: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct TelegramResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TelegramUpdate {
+    update_id: i64,
+    callback_query: Option<TelegramCallbackQuery>,
+}
+
+#[derive(Deserialize)]
+struct TelegramCallbackQuery {
+    id: String,
+    from: TelegramUser,
+    message: Option<TelegramMessage>,
+    data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TelegramUser {
+    id: i64,
+    username: Option<String>,
+    first_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TelegramMessage {
+    message_id: i64,
+    chat: TelegramChat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TelegramChat {
+    id: i64,
+}
+
 #[derive(Deserialize)]
 struct ReviewDecisionReportPayload {
     application_id: i64,
@@ -470,7 +1125,10 @@ struct SmtpConfig {
 struct Notifier {
     tg_bot_token: Option<String>,
     tg_chat_id: Option<String>,
+    tg_api_base: String,
     smtp: Option<SmtpConfig>,
+    /// Shared by all Telegram sends so connections are pooled.
+    http: reqwest::Client,
 }
 
 #[derive(Clone)]
@@ -485,6 +1143,17 @@ struct AutoReviewConfig {
     backlink_window_secs: i64,
 }
 
+#[derive(Clone)]
+struct PresenceConfig {
+    stale_after_secs: i64,
+    idle_away_secs: Option<i64>,
+}
+
+struct DevicePresenceRules {
+    stale_after_secs: i64,
+    idle_away_secs: Option<i64>,
+}
+
 #[derive(Clone)]
 struct AntiAbuseConfig {
     captcha: Option<CaptchaConfig>,
@@ -534,21 +1203,331 @@ async fn main() {
         .init();
 
     let db_path = std::env::var("STATUS_DB").unwrap_or_else(|_| "status.db".to_string());
-    let token = std::env::var("STATUS_TOKEN").unwrap_or_else(|_| "KFCVME50".to_string());
+    let token = std::env::var("STATUS_TOKEN").unwrap_or_else(|_| DEFAULT_STATUS_TOKEN.to_string());
+    if token == DEFAULT_STATUS_TOKEN {
+        tracing::warn!("STATUS_TOKEN is not set, using the built-in default token; set it before exposing this service");
+    }
+    let legacy_device_token = std::env::var("STATUS_LEGACY_DEVICE_TOKEN")
+        .ok()
+        .map(|v| matches!(v.as_str(), "1" | "true" | "TRUE" | "True"))
+        .unwrap_or(false);
+    if legacy_device_token {
+        tracing::warn!("STATUS_LEGACY_DEVICE_TOKEN is enabled: unregistered devices may send heartbeats with STATUS_TOKEN; enroll devices and turn it off");
+    }
+    let require_signature = std::env::var("STATUS_REQUIRE_SIGNATURE")
+        .ok()
+        .map(|v| matches!(v.as_str(), "1" | "true" | "TRUE" | "True"))
+        .unwrap_or(false);
+    let signature_max_skew_secs = std::env::var("STATUS_SIGNATURE_MAX_SKEW_SECS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(300)
+        .clamp(30, 3600);
+    let webhook_max_attempts = std::env::var("STATUS_WEBHOOK_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(6)
+        .clamp(1, 20);
+    let badge_max_age = std::env::var("STATUS_BADGE_MAX_AGE")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(60)
+        .clamp(0, 86400);
+    let backfill_max_samples = std::env::var("STATUS_BACKFILL_MAX_SAMPLES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(500)
+        .clamp(1, 10000);
+    let backfill_max_age_secs = std::env::var("STATUS_BACKFILL_MAX_AGE_SECS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(24 * 3600)
+        .clamp(60, 7 * 24 * 3600);
+    let device_prune_days = std::env::var("STATUS_DEVICE_PRUNE_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(0)
+        .clamp(0, 3650);
+    let schedule_timezone = match normalize_env("STATUS_SCHEDULE_TIMEZONE") {
+        Some(v) => v.parse::<chrono_tz::Tz>().unwrap_or_else(|_| {
+            tracing::warn!("invalid STATUS_SCHEDULE_TIMEZONE ignored: {}", v);
+            chrono_tz::UTC
+        }),
+        None => chrono_tz::UTC,
+    };
+    let live_cache_control = cache_control_env("STATUS_CACHE_CONTROL");
+    let content_cache_control = cache_control_env("STATUS_CONTENT_CACHE_CONTROL");
     let review_report_token =
         std::env::var("LINK_REVIEW_REPORT_TOKEN").unwrap_or_else(|_| token.clone());
     let port = std::env::var("STATUS_PORT")
         .ok()
         .and_then(|v| v.parse().ok())
         .unwrap_or(7999);
+    let metrics_addr =
+        normalize_env("STATUS_METRICS_ADDR").and_then(|v| match v.parse::<SocketAddr>() {
+            Ok(addr) => Some(addr),
+            Err(_) => {
+                tracing::warn!("invalid STATUS_METRICS_ADDR ignored: {}", v);
+                None
+            }
+        });
+    // `/metrics` never falls back to the admin token: without a dedicated
+    // token or address it is disabled.
+    let metrics_token = match metrics_addr {
+        Some(_) => None,
+        None => normalize_env("STATUS_METRICS_TOKEN"),
+    };
+    if metrics_addr.is_none() && metrics_token.is_none() {
+        tracing::info!("metrics disabled: set STATUS_METRICS_TOKEN or STATUS_METRICS_ADDR");
+    }
     let build_version =
         std::env::var("STATUS_BUILD").unwrap_or_else(|_| "status-backend v1.2-music".to_string());
     let notifier = Arc::new(Notifier::from_env());
     let auto_review = Arc::new(AutoReviewConfig::from_env());
     let anti_abuse = Arc::new(AntiAbuseConfig::from_env());
+    let presence = Arc::new(PresenceConfig::from_env());
 
     let conn = Connection::open(db_path).expect("open db");
-    conn.execute_batch(
+    init_db(&conn);
+
+    let cache = Arc::new(ResponseCache::default());
+    let hub = Arc::new(StatusHub::new(
+        load_device_statuses(&conn, &presence, now_ts()),
+        is_global_manual_offline(&conn),
+        load_current_activity(&conn, now_ts()),
+        active_schedule_status(&conn, now_ts()),
+        cache.clone(),
+    ));
+
+    let state = AppState {
+        db: Arc::new(TimedMutex::new(conn)),
+        token,
+        legacy_device_token,
+        require_signature,
+        signature_max_skew_secs,
+        webhook_max_attempts,
+        metrics_token,
+        badge_max_age,
+        backfill_max_samples,
+        backfill_max_age_secs,
+        device_prune_days,
+        live_cache_control,
+        content_cache_control,
+        cache,
+        schedule_timezone,
+        review_report_token,
+        notifier,
+        auto_review,
+        anti_abuse,
+        presence,
+        hub,
+    };
+
+    let stale_state = state.clone();
+    tokio::spawn(async move {
+        let mut ticker =
+            tokio::time::interval(Duration::from_secs(STATUS_STALE_CHECK_INTERVAL_SECS));
+        loop {
+            ticker.tick().await;
+            let conn = stale_state.db.lock().unwrap();
+            close_stale_presence_sessions(&conn, &stale_state.presence, now_ts());
+            close_stale_music_plays(&conn, &stale_state.presence, now_ts());
+            clear_expired_activity(&conn, now_ts());
+            if stale_state.device_prune_days > 0 {
+                prune_inactive_devices(&conn, stale_state.device_prune_days, now_ts());
+            }
+            let _ = conn.execute(
+                "DELETE FROM device_request_nonces WHERE seen_at < ?1",
+                params![now_ts() - stale_state.signature_max_skew_secs * 2],
+            );
+            let _ = conn.execute(
+                "DELETE FROM webhook_deliveries WHERE status != 'pending' AND updated_at < ?1",
+                params![now_ts() - WEBHOOK_DELIVERY_RETENTION_SECS],
+            );
+            let _ = conn.execute(
+                "DELETE FROM music_artwork
+                 WHERE created_at < ?1
+                   AND hash NOT IN (
+                     SELECT music_artwork FROM device_status WHERE music_artwork IS NOT NULL
+                   )",
+                params![now_ts() - MUSIC_ARTWORK_RETENTION_SECS],
+            );
+            stale_state
+                .hub
+                .publish_changes(&conn, &stale_state.presence);
+        }
+    });
+
+    tokio::spawn(webhook_delivery_worker(state.clone()));
+    tokio::spawn(schedule_reminder_worker(state.clone()));
+    let telegram_poll = std::env::var("LINK_TG_POLL")
+        .ok()
+        .map(|v| v != "0" && v.to_lowercase() != "false")
+        .unwrap_or(true);
+    if telegram_poll {
+        tokio::spawn(telegram_update_worker(state.clone()));
+    }
+
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    let app = Router::new()
+        .route("/", get(|| async { "ok" }))
+        .route(
+            "/version",
+            get(move || {
+                let build_version = build_version.clone();
+                async move {
+                    Json(VersionInfo {
+                        service: "status-backend".to_string(),
+                        version: build_version,
+                        music_fields: true,
+                    })
+                }
+            }),
+        )
+        .route("/heartbeat", post(heartbeat))
+        .route("/heartbeat/backfill", post(heartbeat_backfill))
+        .route("/devices", get(devices_list))
+        .route("/devices/:id", patch(device_patch).delete(device_delete))
+        .route("/device/status", post(device_status_update))
+        .route(
+            "/device/presence",
+            get(device_presence_defaults).post(device_presence_update),
+        )
+        .route("/device/enroll", post(device_enroll))
+        .route("/device/enroll/redeem", post(device_enroll_redeem))
+        .route("/device/credentials", get(device_credentials_list))
+        .route(
+            "/device/credentials/revoke",
+            post(device_credentials_revoke),
+        )
+        .route("/status", get(status))
+        .route("/status/stream", get(status_stream))
+        .route("/status/history", get(status_history))
+        .route("/status/summary", get(status_summary))
+        .route("/status/badge.svg", get(status_badge_svg))
+        .route("/status/now-playing.svg", get(status_now_playing_svg))
+        .route(
+            "/status/activity",
+            get(get_status_activity).post(set_status_activity),
+        )
+        .route(
+            "/status/summary/rules",
+            get(status_summary_rules_get).post(status_summary_rules_set),
+        )
+        .route("/music/history", get(music_history))
+        .route("/music/stats", get(music_stats))
+        .route(
+            "/music/rules",
+            get(music_rules_list).post(music_rules_upsert),
+        )
+        .route("/music/rules/delete", post(music_rules_delete))
+        .route("/music/artwork", post(music_artwork_upload))
+        .route("/music/artwork/:hash", get(music_artwork_get))
+        .route("/webhooks", get(webhooks_list).post(webhooks_upsert))
+        .route("/webhooks/delete", post(webhooks_delete))
+        .route("/webhooks/test", post(webhooks_test))
+        .route("/webhooks/deliveries", get(webhook_deliveries_list))
+        .route(
+            "/status/manual",
+            get(get_manual_status).post(set_manual_status),
+        )
+        .route("/status/admin", get(admin_pages::status_admin_page))
+        .route("/admin/common.css", get(admin_pages::admin_common_css))
+        .route("/schedule", get(schedule_list).post(schedule_update))
+        .route("/schedule.ics", get(schedule_ics))
+        .route("/schedule/import", post(schedule_import))
+        .route("/schedule/reminders", get(schedule_reminders_list))
+        .route("/schedule/admin", get(admin_pages::schedule_admin_page))
+        .route(
+            "/schedule/:id",
+            put(schedule_put)
+                .patch(schedule_patch)
+                .delete(schedule_delete),
+        )
+        .route("/blog", get(blog_list).post(blog_update))
+        .route(
+            "/blog/:slug",
+            get(blog_detail)
+                .put(blog_put)
+                .patch(blog_patch)
+                .delete(blog_delete),
+        )
+        .route("/blog/admin", get(admin_pages::blog_admin_page))
+        .route("/links", get(links_list))
+        .route("/links/apply", post(links_apply))
+        .route("/links/apply/config", get(links_apply_config))
+        .route("/links/verify/http", post(links_verify_http))
+        .route("/links/verify/email/send", post(links_verify_email_send))
+        .route("/links/verify/email", get(links_verify_email))
+        .route("/links/verify/reset", post(links_verify_reset))
+        .route("/links/verify/release", post(links_verify_release))
+        .route("/links/applications", get(links_applications))
+        .route("/links/review", post(links_review))
+        .route("/links/sort", post(links_sort))
+        .route("/links/update", post(links_update))
+        .route("/links/delete", post(links_delete))
+        .route(
+            "/links/review/stage/cancel",
+            post(links_review_stage_cancel),
+        )
+        .route(
+            "/links/settings",
+            get(links_settings_get).post(links_settings_set),
+        )
+        .route("/links/settings/test-smtp", post(links_settings_test_smtp))
+        .route(
+            "/links/settings/test-telegram",
+            post(links_settings_test_telegram),
+        )
+        .route("/links/review/report/tasks", get(links_review_report_tasks))
+        .route(
+            "/links/review/report/decision",
+            post(links_review_report_decision),
+        )
+        .route(
+            "/links/review/report/manual",
+            post(links_review_report_manual),
+        )
+        .route(
+            "/links/review/report/removal",
+            post(links_review_report_removal),
+        )
+        .route("/links/admin", get(admin_pages::links_admin_page))
+        .route("/visitor", get(visitor_stats))
+        .route("/visitor/visit", post(visitor_visit))
+        .route("/metrics", get(metrics_scrape))
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        .with_state(state.clone())
+        .layer(cors);
+
+    if let Some(metrics_addr) = metrics_addr {
+        let metrics_app = Router::new()
+            .route(
+                "/metrics",
+                get(|State(state): State<AppState>| async move { render_metrics(&state) }),
+            )
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind(metrics_addr).await.unwrap();
+        tracing::info!("metrics listening on {}", metrics_addr);
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, metrics_app).await;
+        });
+    }
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    tracing::info!("listening on {}", addr);
+    axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app)
+        .await
+        .unwrap();
+}
+
+/// Creates missing tables and applies column migrations.
+fn init_db(conn: &Connection) {
+    conn.execute_batch(
         "CREATE TABLE IF NOT EXISTS device_status (
             device_id TEXT PRIMARY KEY,
             device_name TEXT NOT NULL,
@@ -566,6 +1545,82 @@ async fn main() {
             global_manual_offline INTEGER NOT NULL,
             updated_at INTEGER NOT NULL
         );
+        CREATE TABLE IF NOT EXISTS status_quiet_hours (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            label TEXT,
+            weekdays TEXT NOT NULL,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            timezone TEXT NOT NULL,
+            enabled INTEGER NOT NULL DEFAULT 1,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS music_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            device_id TEXT,
+            source_pattern TEXT,
+            title_pattern TEXT,
+            display_name TEXT,
+            icon TEXT,
+            sort_order INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            events TEXT NOT NULL DEFAULT '',
+            enabled INTEGER NOT NULL DEFAULT 1,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            webhook_id INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER,
+            response_status INTEGER,
+            last_error TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due
+            ON webhook_deliveries(status, next_attempt_at);
+        CREATE TABLE IF NOT EXISTS schedule_reminders (
+            item_id TEXT NOT NULL,
+            occurrence_start INTEGER NOT NULL,
+            offset_minutes INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'sending',
+            channels TEXT,
+            last_error TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY (item_id, occurrence_start, offset_minutes)
+        );
+        CREATE TABLE IF NOT EXISTS music_artwork (
+            hash TEXT PRIMARY KEY,
+            content_type TEXT NOT NULL,
+            data BLOB NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS status_summary_rules (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            rules TEXT NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS status_activity (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            text TEXT NOT NULL,
+            emoji TEXT,
+            link TEXT,
+            expires_at INTEGER,
+            updated_at INTEGER NOT NULL
+        );
         CREATE TABLE IF NOT EXISTS schedule_items (
             id TEXT PRIMARY KEY,
             title TEXT NOT NULL,
@@ -633,7 +1688,57 @@ async fn main() {
             application_id INTEGER NOT NULL,
             ip TEXT,
             created_at INTEGER NOT NULL
-        );",
+        );
+        CREATE TABLE IF NOT EXISTS device_presence_sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            state TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            ended_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_device_presence_sessions_device
+            ON device_presence_sessions (device_id, started_at);
+        CREATE TABLE IF NOT EXISTS music_plays (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            title TEXT,
+            artist TEXT,
+            source TEXT,
+            started_at INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            ended_at INTEGER,
+            listened_secs INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS idx_music_plays_started ON music_plays (started_at);
+        CREATE INDEX IF NOT EXISTS idx_music_plays_device ON music_plays (device_id, started_at);
+        CREATE TABLE IF NOT EXISTS device_credentials (
+            device_id TEXT PRIMARY KEY,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL,
+            last_used_at INTEGER,
+            revoked_at INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS device_enrollment_codes (
+            code_hash TEXT PRIMARY KEY,
+            device_id TEXT NOT NULL,
+            device_name TEXT,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            used_at INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS device_request_nonces (
+            device_id TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            seen_at INTEGER NOT NULL,
+            PRIMARY KEY (device_id, nonce)
+        );
+        CREATE TABLE IF NOT EXISTS device_enroll_failures (
+            ip TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_device_enroll_failures_ip
+            ON device_enroll_failures (ip, created_at);",
     )
     .expect("init db");
     let _ = conn.execute(
@@ -696,141 +1801,358 @@ async fn main() {
         "ALTER TABLE friend_links ADD COLUMN unreachable_since INTEGER",
         [],
     );
+    let _ = conn.execute(
+        "ALTER TABLE device_credentials ADD COLUMN signing_secret TEXT",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN stale_after_secs INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN idle_away_secs INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE status_control ADD COLUMN offline_until INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN telemetry_version INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN battery_level INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN battery_charging INTEGER",
+        [],
+    );
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN network_type TEXT", []);
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN os TEXT", []);
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN platform TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN client_version TEXT",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN telemetry_updated_at INTEGER",
+        [],
+    );
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN display_name TEXT", []);
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN kind TEXT", []);
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN icon TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN sort_order INTEGER NOT NULL DEFAULT 0",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN hidden INTEGER NOT NULL DEFAULT 0",
+        [],
+    );
+    let _ = conn.execute("ALTER TABLE device_status ADD COLUMN music_album TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN music_duration_ms INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN music_position_ms INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN music_position_at INTEGER",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE device_status ADD COLUMN music_artwork TEXT",
+        [],
+    );
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN start_at TEXT", []);
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN end_at TEXT", []);
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN timezone TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE schedule_items ADD COLUMN all_day INTEGER NOT NULL DEFAULT 0",
+        [],
+    );
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN rrule TEXT", []);
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN exdates TEXT", []);
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN source TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE schedule_items ADD COLUMN version INTEGER NOT NULL DEFAULT 1",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE blog_posts ADD COLUMN version INTEGER NOT NULL DEFAULT 1",
+        [],
+    );
+    let _ = conn.execute(
+        "ALTER TABLE schedule_items ADD COLUMN status_effect TEXT",
+        [],
+    );
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN status_text TEXT", []);
+    let _ = conn.execute("ALTER TABLE schedule_items ADD COLUMN reminders TEXT", []);
+    let _ = conn.execute("ALTER TABLE music_plays ADD COLUMN end_reason TEXT", []);
+    let _ = conn.execute("ALTER TABLE friend_links ADD COLUMN updated_at INTEGER", []);
+    let _ = conn.execute(
+        "UPDATE friend_links SET updated_at = created_at WHERE updated_at IS NULL",
+        [],
+    );
+    // Summary rules used to live in the friend-link settings table.
+    let _ = conn.execute(
+        "INSERT OR IGNORE INTO status_summary_rules (id, rules, updated_at)
+         SELECT 1, value, updated_at FROM friend_link_settings WHERE key = 'status_summary_rules'",
+        [],
+    );
+    let _ = conn.execute(
+        "DELETE FROM friend_link_settings WHERE key = 'status_summary_rules'",
+        [],
+    );
     let _ = conn.execute(
         "INSERT INTO status_control (id, global_manual_offline, updated_at)
          VALUES (1, 0, ?1)
          ON CONFLICT(id) DO NOTHING",
         params![now_ts()],
     );
+}
 
-    let state = AppState {
-        db: Arc::new(Mutex::new(conn)),
-        token,
-        review_report_token,
-        notifier,
-        auto_review,
-        anti_abuse,
+async fn metrics_scrape(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let Some(token) = state.metrics_token.as_deref() else {
+        return StatusCode::NOT_FOUND.into_response();
     };
+    if !authorized(&headers, token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    render_metrics(&state).into_response()
+}
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    let app = Router::new()
-        .route("/", get(|| async { "ok" }))
-        .route(
-            "/version",
-            get(move || {
-                let build_version = build_version.clone();
-                async move {
-                    Json(VersionInfo {
-                        service: "status-backend".to_string(),
-                        version: build_version,
-                        music_fields: true,
-                    })
-                }
-            }),
-        )
-        .route("/heartbeat", post(heartbeat))
-        .route("/device", get(delete_device))
-        .route("/device/status", post(device_status_update))
-        .route("/status", get(status))
-        .route(
-            "/status/manual",
-            get(get_manual_status).post(set_manual_status),
-        )
-        .route("/status/admin", get(admin_pages::status_admin_page))
-        .route("/admin/common.css", get(admin_pages::admin_common_css))
-        .route("/schedule", get(schedule_list).post(schedule_update))
-        .route("/schedule/admin", get(admin_pages::schedule_admin_page))
-        .route("/blog", get(blog_list).post(blog_update))
-        .route("/blog/:slug", get(blog_detail))
-        .route("/blog/admin", get(admin_pages::blog_admin_page))
-        .route("/links", get(links_list))
-        .route("/links/apply", post(links_apply))
-        .route("/links/apply/config", get(links_apply_config))
-        .route("/links/verify/http", post(links_verify_http))
-        .route("/links/verify/email/send", post(links_verify_email_send))
-        .route("/links/verify/email", get(links_verify_email))
-        .route("/links/verify/reset", post(links_verify_reset))
-        .route("/links/verify/release", post(links_verify_release))
-        .route("/links/applications", get(links_applications))
-        .route("/links/review", post(links_review))
-        .route("/links/sort", post(links_sort))
-        .route("/links/update", post(links_update))
-        .route("/links/delete", post(links_delete))
-        .route(
-            "/links/review/stage/cancel",
-            post(links_review_stage_cancel),
-        )
-        .route(
-            "/links/settings",
-            get(links_settings_get).post(links_settings_set),
-        )
-        .route("/links/settings/test-smtp", post(links_settings_test_smtp))
-        .route("/links/review/report/tasks", get(links_review_report_tasks))
-        .route(
-            "/links/review/report/decision",
-            post(links_review_report_decision),
-        )
-        .route(
-            "/links/review/report/manual",
-            post(links_review_report_manual),
-        )
-        .route(
-            "/links/review/report/removal",
-            post(links_review_report_removal),
-        )
-        .route("/links/admin", get(admin_pages::links_admin_page))
-        .route("/visitor", get(visitor_stats))
-        .route("/visitor/visit", post(visitor_visit))
-        .with_state(state)
-        .layer(cors);
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    tracing::info!("listening on {}", addr);
-    axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app)
-        .await
-        .unwrap();
+fn render_metrics(state: &AppState) -> impl IntoResponse {
+    let mut out = String::new();
+    METRICS.render(&mut out);
+    let conn = state.db.lock().unwrap();
+    let devices = load_device_statuses(&conn, &state.presence, now_ts());
+    let online = devices.iter().filter(|v| v.online).count();
+    metrics::header(
+        &mut out,
+        "status_backend_devices",
+        "gauge",
+        "Known devices by online state.",
+    );
+    out.push_str(&format!(
+        "status_backend_devices{{state=\"online\"}} {}\nstatus_backend_devices{{state=\"offline\"}} {}\n",
+        online,
+        devices.len() - online
+    ));
+    metrics::header(
+        &mut out,
+        "status_backend_link_applications",
+        "gauge",
+        "Friend-link applications awaiting verification or review.",
+    );
+    for status in ["pending", "verify_pending"] {
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_applications WHERE status = ?1",
+                params![status],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        out.push_str(&format!(
+            "status_backend_link_applications{{status=\"{}\"}} {}\n",
+            status, count
+        ));
+    }
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        out,
+    )
 }
 
 async fn heartbeat(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<Heartbeat>,
+    body: Bytes,
 ) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED;
-    }
-
+    let Ok(payload) = serde_json::from_slice::<Heartbeat>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
     let conn = state.db.lock().unwrap();
+    if let Err(code) = device_authorized(&state, &conn, &headers, &payload.device_id, &body) {
+        return code;
+    }
+    METRICS.record_heartbeat(&payload.device_id);
     if is_global_manual_offline(&conn) {
         return StatusCode::OK;
     }
-    let now = now_ts();
-    let music_playing = payload.music_playing.unwrap_or(false);
-    let music_title = payload.music_title;
-    let music_artist = payload.music_artist;
-    let music_source = payload.music_source;
     tracing::info!(
         "heartbeat recv: device_id={} online={} idle={:?} music_playing={} title={:?} artist={:?} source={:?}",
         payload.device_id,
         payload.online,
         payload.idle_seconds,
+        payload.music_playing.unwrap_or(false),
+        payload.music_title,
+        payload.music_artist,
+        payload.music_source
+    );
+    apply_heartbeat(&conn, &state.presence, payload, now_ts());
+    state.hub.publish_changes(&conn, &state.presence);
+
+    StatusCode::OK
+}
+
+async fn heartbeat_backfill(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Ok(payload) = serde_json::from_slice::<HeartbeatBackfill>(&body) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if payload.samples.len() > state.backfill_max_samples {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ApiMessage {
+                message: format!("单次最多补传 {} 条心跳", state.backfill_max_samples),
+            }),
+        )
+            .into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    if let Err(code) = device_authorized(&state, &conn, &headers, &payload.device_id, &body) {
+        return code.into_response();
+    }
+    let device_id = payload.device_id.clone();
+    let total = payload.samples.len();
+    let result = apply_backfill(
+        &conn,
+        &state.presence,
+        payload,
+        state.backfill_max_age_secs,
+        now_ts(),
+    );
+    tracing::info!(
+        "heartbeat backfill: device_id={} accepted={} skipped={}",
+        device_id,
+        result.accepted,
+        total - result.accepted
+    );
+    if result.accepted > 0 {
+        state.hub.publish_changes(&conn, &state.presence);
+    }
+    Json(result).into_response()
+}
+
+/// Applies backfilled samples in time order. Samples newer than the device's
+/// `last_seen` update it like live heartbeats; older ones (a live heartbeat
+/// can beat the backfill after a reconnect) only fill the history.
+fn apply_backfill(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    payload: HeartbeatBackfill,
+    max_age_secs: i64,
+    now: i64,
+) -> HeartbeatBackfillResult {
+    let total = payload.samples.len();
+    let current_last_seen: Option<i64> = conn
+        .query_row(
+            "SELECT last_seen FROM device_status WHERE device_id = ?1",
+            params![payload.device_id],
+            |row| row.get(0),
+        )
+        .ok();
+    let mut last_seen = current_last_seen;
+    let mut samples = payload.samples;
+    samples.sort_by_key(|sample| sample.ts);
+    let mut late = Vec::new();
+    let mut accepted = 0;
+    for sample in samples {
+        let ts = sample.ts.min(now);
+        if sample.ts < now - max_age_secs
+            || sample.ts > now + BACKFILL_MAX_FUTURE_SKEW_SECS
+            || last_seen.is_some_and(|v| ts == v)
+            || manual_offline_state(conn, ts)
+                .is_some_and(|v| v.since.is_none_or(|since| since <= ts))
+        {
+            continue;
+        }
+        METRICS.record_heartbeat(&payload.device_id);
+        accepted += 1;
+        if current_last_seen.is_some_and(|v| ts < v) {
+            late.push(HeartbeatSample { ts, ..sample });
+            continue;
+        }
+        apply_heartbeat(
+            conn,
+            presence,
+            Heartbeat {
+                device_id: payload.device_id.clone(),
+                device_name: payload.device_name.clone(),
+                online: sample.online,
+                idle_seconds: sample.idle_seconds,
+                music_playing: sample.music_playing,
+                music_title: sample.music_title,
+                music_artist: sample.music_artist,
+                music_source: sample.music_source,
+                music_album: None,
+                music_duration_ms: None,
+                music_position_ms: None,
+                music_position_at: None,
+                music_artwork: None,
+                telemetry: None,
+            },
+            ts,
+        );
+        last_seen = Some(ts);
+    }
+    record_late_samples(conn, presence, &payload.device_id, late);
+    HeartbeatBackfillResult {
+        accepted,
+        skipped: total - accepted,
+        last_seen,
+    }
+}
+
+fn apply_heartbeat(conn: &Connection, presence: &PresenceConfig, payload: Heartbeat, ts: i64) {
+    let music_playing = payload.music_playing.unwrap_or(false);
+    let music_title = payload.music_title;
+    let music_artist = payload.music_artist;
+    let music_source = payload.music_source;
+    let music_album = normalize_optional(payload.music_album, 256);
+    let music_duration_ms = payload.music_duration_ms.filter(|v| *v > 0);
+    let music_position_ms = payload
+        .music_position_ms
+        .map(|v| v.clamp(0, music_duration_ms.unwrap_or(i64::MAX)));
+    let music_position_at = music_position_ms.map(|_| {
+        payload
+            .music_position_at
+            .filter(|v| (*v / 1000 - ts).abs() <= BACKFILL_MAX_FUTURE_SKEW_SECS)
+            .unwrap_or(ts * 1000)
+    });
+    let music_artwork = normalize_optional(payload.music_artwork, 1024)
+        .filter(|v| is_artwork_hash(v) || is_valid_http_url(v));
+    record_music_play(
+        conn,
+        presence,
+        &payload.device_id,
         music_playing,
-        music_title,
-        music_artist,
-        music_source
+        music_title.as_deref(),
+        music_artist.as_deref(),
+        music_source.as_deref(),
+        ts,
     );
     let _ = conn.execute(
         "INSERT INTO device_status (
             device_id, device_name, online, last_seen, idle_seconds, manual_offline,
-            music_playing, music_title, music_artist, music_source, music_updated_at
+            music_playing, music_title, music_artist, music_source, music_updated_at,
+            music_album, music_duration_ms, music_position_ms, music_position_at, music_artwork
          )
          VALUES (
             ?1, ?2, ?3, ?4, ?5, COALESCE((SELECT manual_offline FROM device_status WHERE device_id = ?1), 0),
-            ?6, ?7, ?8, ?9, ?10
+            ?6, ?7, ?8, ?9, ?10,
+            ?11, ?12, ?13, ?14, ?15
          )
          ON CONFLICT(device_id) DO UPDATE SET
            device_name=excluded.device_name,
@@ -841,2705 +2163,7818 @@ async fn heartbeat(
            music_title=excluded.music_title,
            music_artist=excluded.music_artist,
            music_source=excluded.music_source,
-           music_updated_at=excluded.music_updated_at;",
+           music_updated_at=excluded.music_updated_at,
+           music_album=excluded.music_album,
+           music_duration_ms=excluded.music_duration_ms,
+           music_position_ms=excluded.music_position_ms,
+           music_position_at=excluded.music_position_at,
+           music_artwork=excluded.music_artwork
+         WHERE excluded.last_seen >= device_status.last_seen;",
         params![
             payload.device_id,
             payload.device_name,
             payload.online as i32,
-            now,
+            ts,
             payload.idle_seconds.map(|v| v as i64),
             music_playing as i32,
             music_title,
             music_artist,
             music_source,
-            now,
+            ts,
+            music_album,
+            music_duration_ms,
+            music_position_ms,
+            music_position_at,
+            music_artwork,
         ],
     );
+    if let Some(telemetry) = payload.telemetry.map(normalize_telemetry) {
+        let _ = conn.execute(
+            "UPDATE device_status SET
+               telemetry_version=?2, battery_level=?3, battery_charging=?4, network_type=?5,
+               os=?6, platform=?7, client_version=?8, telemetry_updated_at=?9
+             WHERE device_id = ?1",
+            params![
+                payload.device_id,
+                telemetry.version,
+                telemetry.battery_level,
+                telemetry.battery_charging.map(|v| v as i32),
+                telemetry.network_type,
+                telemetry.os,
+                telemetry.platform,
+                telemetry.client_version,
+                ts,
+            ],
+        );
+    }
+    let manual_offline = conn
+        .query_row(
+            "SELECT manual_offline FROM device_status WHERE device_id = ?1",
+            params![payload.device_id.as_str()],
+            |row| row.get::<_, i32>(0),
+        )
+        .map(|v| v == 1)
+        .unwrap_or(false);
+    let rules = presence.device_rules(conn, &payload.device_id);
+    record_presence(
+        conn,
+        presence,
+        &payload.device_id,
+        presence_state(payload.online, payload.idle_seconds, manual_offline, &rules),
+        ts,
+    );
+}
 
-    StatusCode::OK
+async fn status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<StatusQuery>,
+) -> impl IntoResponse {
+    let with: Vec<&str> = query
+        .with
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let with_activity = with.contains(&"activity");
+    let with_schedule = with.contains(&"schedule");
+    let key = match (with_activity, with_schedule) {
+        (false, false) => "status",
+        (true, false) => "status:activity",
+        (false, true) => "status:schedule",
+        (true, true) => "status:activity,schedule",
+    };
+    let snapshot = match state.cache.get(key) {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            let now = now_ts();
+            let list = load_device_statuses(&conn, &state.presence, now);
+            let mut last_modified = list.iter().map(|item| item.last_seen).max().unwrap_or(0);
+            if with_activity || with_schedule {
+                let schedule = active_schedule_status(&conn, now);
+                let activity = current_activity(&conn, schedule.as_ref(), now);
+                if let Some(activity) = activity.as_ref().filter(|_| with_activity) {
+                    last_modified = last_modified.max(activity.updated_at);
+                }
+                if let Some(schedule) = schedule.as_ref().filter(|_| with_schedule) {
+                    last_modified = last_modified.max(schedule.started_at);
+                }
+                let body = StatusWithExtras {
+                    devices: list,
+                    activity: with_activity.then_some(activity),
+                    schedule: with_schedule.then_some(schedule),
+                };
+                state.cache.store(key, &body, last_modified)
+            } else {
+                state.cache.store(key, &list, last_modified)
+            }
+        }
+    };
+    cache::respond(&headers, &snapshot, &state.live_cache_control)
 }
 
-async fn status(State(state): State<AppState>) -> impl IntoResponse {
-    let now = now_ts();
-    let conn = state.db.lock().unwrap();
-    let global_manual_offline = is_global_manual_offline(&conn);
-    let mut stmt = conn
-        .prepare(
-            "SELECT device_id, device_name, online, last_seen, idle_seconds, manual_offline,
-                    music_playing, music_title, music_artist, music_source, music_updated_at
-             FROM device_status
-             ORDER BY device_id ASC",
+async fn status_stream(State(state): State<AppState>) -> impl IntoResponse {
+    let rx = state.hub.tx.subscribe();
+    let (snapshot, activity) = {
+        let conn = state.db.lock().unwrap();
+        let now = now_ts();
+        (
+            load_device_statuses(&conn, &state.presence, now),
+            load_current_activity(&conn, now),
         )
-        .unwrap();
-
-    let rows = stmt
-        .query_map([], |row| {
-            let last_seen: i64 = row.get(3)?;
-            let online_flag: i32 = row.get(2)?;
-            let manual_offline: i32 = row.get(5)?;
-            let music_playing: i32 = row.get(6)?;
-            let stale = now.saturating_sub(last_seen) > 300;
-            let device_manual_offline = manual_offline == 1;
-            let online =
-                !global_manual_offline && !device_manual_offline && online_flag == 1 && !stale;
-            Ok(DeviceStatus {
-                device_id: row.get(0)?,
-                device_name: row.get(1)?,
-                online,
-                last_seen,
-                idle_seconds: row.get::<_, Option<i64>>(4)?.map(|v| v as u64),
-                manual_offline: device_manual_offline,
-                global_manual_offline,
-                music_playing: music_playing == 1,
-                music_title: row.get(7)?,
-                music_artist: row.get(8)?,
-                music_source: row.get(9)?,
-                music_updated_at: row.get(10)?,
-            })
-        })
-        .unwrap();
-
-    let list: Vec<DeviceStatus> = rows.filter_map(Result::ok).collect();
-
-    Json(list)
+    };
+    let initial = [
+        Event::default().event("snapshot").json_data(&snapshot),
+        Event::default().event("activity").json_data(&activity),
+    ]
+    .map(|event| {
+        Ok::<Event, Infallible>(event.unwrap_or_else(|_| Event::default().event("resync")))
+    });
+    let updates = BroadcastStream::new(rx).map(|msg| {
+        let event = match msg {
+            Ok(event) => event.to_sse(),
+            Err(_) => Event::default().event("resync").data("lagged"),
+        };
+        Ok::<Event, Infallible>(event)
+    });
+    let stream = tokio_stream::iter(initial).chain(updates);
+    (
+        [("x-accel-buffering", "no")],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
 }
 
-async fn schedule_list(State(state): State<AppState>) -> impl IntoResponse {
-    let conn = state.db.lock().unwrap();
-    let mut stmt = conn
-        .prepare(
-            "SELECT id, title, time, note, location, tag, sort_order, updated_at
-             FROM schedule_items
-             ORDER BY sort_order ASC, updated_at DESC",
+async fn status_history(
+    State(state): State<AppState>,
+    Query(query): Query<PresenceHistoryQuery>,
+) -> impl IntoResponse {
+    let now = now_ts();
+    let to = query.to.unwrap_or(now).min(now);
+    let from = query
+        .from
+        .unwrap_or(to - 24 * 3600)
+        .max(to - PRESENCE_HISTORY_MAX_RANGE_SECS);
+    if from >= to {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "from 需早于 to".to_string(),
+            }),
         )
-        .unwrap();
-
-    let rows = stmt
-        .query_map([], |row| {
-            Ok(ScheduleItem {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                time: row.get(2)?,
-                note: row.get(3)?,
-                location: row.get(4)?,
-                tag: row.get(5)?,
-                sort_order: row.get(6)?,
-                updated_at: row.get(7)?,
-            })
+            .into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let hidden: HashSet<String> = conn
+        .prepare("SELECT device_id FROM device_status WHERE hidden = 1")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))
+                .map(|rows| rows.filter_map(Result::ok).collect())
         })
-        .unwrap();
+        .unwrap_or_default();
+    let device_ids = match normalize_optional(query.device_id, 128) {
+        Some(device_id) => vec![device_id],
+        None => {
+            let mut stmt = match conn.prepare(
+                "SELECT device_id FROM device_status
+                 UNION
+                 SELECT DISTINCT device_id FROM device_presence_sessions
+                 ORDER BY device_id ASC",
+            ) {
+                Ok(stmt) => stmt,
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+            let rows = match stmt.query_map([], |row| row.get::<_, String>(0)) {
+                Ok(rows) => rows,
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            };
+            rows.filter_map(Result::ok).collect()
+        }
+    };
+    let list: Vec<DevicePresenceHistory> = device_ids
+        .into_iter()
+        .filter(|device_id| !hidden.contains(device_id))
+        .map(|device_id| load_presence_history(&conn, &state.presence, device_id, from, to, now))
+        .collect();
+    (StatusCode::OK, Json(list)).into_response()
+}
 
-    let list: Vec<ScheduleItem> = rows.filter_map(Result::ok).collect();
-    Json(list)
+async fn status_summary(State(state): State<AppState>) -> impl IntoResponse {
+    let conn = state.db.lock().unwrap();
+    Json(build_status_summary(&conn, &state.presence, now_ts()))
 }
 
-async fn schedule_update(
+async fn status_badge_svg(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<SchedulePayload>,
+    Query(query): Query<SvgCardQuery>,
 ) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED;
-    }
+    let summary = {
+        let conn = state.db.lock().unwrap();
+        build_status_summary(&conn, &state.presence, now_ts())
+    };
+    let (label, summary_state) = match normalize_optional(query.device_id, 128) {
+        Some(device_id) => match summary.devices.iter().find(|v| v.device_id == device_id) {
+            Some(device) => (device.device_name.clone(), device.state.clone()),
+            None => return device_not_found(),
+        },
+        None => (
+            summary.device_name.unwrap_or_else(|| "status".to_string()),
+            summary.state,
+        ),
+    };
+    let value = match summary_state.as_str() {
+        "active" | "listening" => "online",
+        "idle" => "idle",
+        "busy" => "busy",
+        _ => "offline",
+    };
+    let svg = render_status_badge(
+        &label,
+        value,
+        svg_theme(query.theme.as_deref()),
+        query.width,
+    );
+    svg_response(svg, state.badge_max_age)
+}
 
-    let now = now_ts();
-    let mut conn = state.db.lock().unwrap();
-    let tx = match conn.transaction() {
-        Ok(tx) => tx,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+async fn status_now_playing_svg(
+    State(state): State<AppState>,
+    Query(query): Query<SvgCardQuery>,
+) -> impl IntoResponse {
+    let device = {
+        let conn = state.db.lock().unwrap();
+        let now = now_ts();
+        let summary = build_status_summary(&conn, &state.presence, now);
+        let target = match normalize_optional(query.device_id, 128) {
+            Some(device_id) => {
+                if !summary.devices.iter().any(|v| v.device_id == device_id) {
+                    return device_not_found();
+                }
+                Some(device_id)
+            }
+            None => summary
+                .devices
+                .iter()
+                .filter(|v| v.state == "listening")
+                .find(|v| summary.device_id.as_deref() == Some(v.device_id.as_str()))
+                .or_else(|| summary.devices.iter().find(|v| v.state == "listening"))
+                .map(|v| v.device_id.clone()),
+        };
+        let listening = |device_id: &str| {
+            summary
+                .devices
+                .iter()
+                .any(|v| v.device_id == device_id && v.state == "listening")
+        };
+        target.and_then(|device_id| {
+            load_device_statuses(&conn, &state.presence, now)
+                .into_iter()
+                .find(|v| v.device_id == device_id && listening(&v.device_id))
+        })
     };
+    let svg = render_now_playing_card(
+        device.as_ref(),
+        svg_theme(query.theme.as_deref()),
+        query.width.unwrap_or(360).clamp(240, 800),
+    );
+    svg_response(svg, state.badge_max_age)
+}
 
-    if tx.execute("DELETE FROM schedule_items", []).is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
+fn device_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiMessage {
+            message: "设备不存在".to_string(),
+        }),
+    )
+        .into_response()
+}
 
-    for (idx, item) in payload.items.into_iter().enumerate() {
-        let id = item
-            .id
-            .unwrap_or_else(|| format!("schedule-{}-{}", now, idx));
-        let sort_order = item.sort_order.unwrap_or(idx as i64);
-        if tx
-            .execute(
-                "INSERT INTO schedule_items (id, title, time, note, location, tag, sort_order, updated_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                params![
-                    id,
-                    item.title,
-                    item.time,
-                    item.note,
-                    item.location,
-                    item.tag,
-                    sort_order,
-                    now
-                ],
-            )
-            .is_err()
-        {
-            return StatusCode::INTERNAL_SERVER_ERROR;
-        }
-    }
+fn svg_response(svg: String, max_age: i64) -> Response {
+    let cache_control = if max_age == 0 {
+        "no-cache, no-store, must-revalidate".to_string()
+    } else {
+        format!("public, max-age={}, s-maxage={}", max_age, max_age)
+    };
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                "image/svg+xml; charset=utf-8".to_string(),
+            ),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        svg,
+    )
+        .into_response()
+}
 
-    if tx.commit().is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
+fn svg_theme(name: Option<&str>) -> SvgTheme {
+    match name.map(|v| v.trim().to_lowercase()).as_deref() {
+        Some("dark") => SvgTheme {
+            background: "#0d1117",
+            border: "#30363d",
+            label: "#21262d",
+            text: "#e6edf3",
+            muted: "#8b949e",
+        },
+        _ => SvgTheme {
+            background: "#ffffff",
+            border: "#d0d7de",
+            label: "#555555",
+            text: "#1f2328",
+            muted: "#656d76",
+        },
     }
+}
 
-    StatusCode::OK
+fn svg_text_width(text: &str, size: f64) -> f64 {
+    text.chars()
+        .map(|ch| {
+            if ch as u32 >= 0x2e80 {
+                size
+            } else if ch.is_ascii_uppercase() || ch == 'm' || ch == 'w' {
+                size * 0.72
+            } else {
+                size * 0.58
+            }
+        })
+        .sum()
 }
 
-async fn blog_list(State(state): State<AppState>) -> impl IntoResponse {
-    let conn = state.db.lock().unwrap();
-    let mut stmt = match conn.prepare(
-        "SELECT slug, title, date, tag, excerpt, sort_order, updated_at
-         FROM blog_posts
-         ORDER BY sort_order ASC, date DESC, updated_at DESC",
-    ) {
-        Ok(stmt) => stmt,
-        Err(_) => return Json(Vec::<BlogPostSummary>::new()),
-    };
+fn svg_truncate(text: &str, size: f64, max_width: f64) -> String {
+    if svg_text_width(text, size) <= max_width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    for ch in text.chars() {
+        out.push(ch);
+        if svg_text_width(&out, size) + size > max_width {
+            out.pop();
+            break;
+        }
+    }
+    format!("{}…", out.trim_end())
+}
 
-    let rows = match stmt.query_map([], |row| {
-        Ok(BlogPostSummary {
-            slug: row.get(0)?,
-            title: row.get(1)?,
-            date: row.get(2)?,
-            tag: row.get(3)?,
-            excerpt: row.get(4)?,
-            sort_order: row.get(5)?,
-            updated_at: row.get(6)?,
-        })
-    }) {
-        Ok(rows) => rows,
-        Err(_) => return Json(Vec::<BlogPostSummary>::new()),
+fn render_status_badge(label: &str, value: &str, theme: SvgTheme, width: Option<u32>) -> String {
+    let color = match value {
+        "online" => "#3fb950",
+        "idle" => "#d29922",
+        "busy" => "#db6d28",
+        _ => "#8b949e",
+    };
+    let value_width = svg_text_width(value, 11.0).ceil() + 12.0;
+    let (label, label_width) = match width {
+        Some(width) => {
+            let label_width = (width.clamp(60, 600) as f64 - value_width).max(24.0);
+            (svg_truncate(label, 11.0, label_width - 12.0), label_width)
+        }
+        None => {
+            let label = svg_truncate(label, 11.0, 240.0);
+            let label_width = svg_text_width(&label, 11.0).ceil() + 12.0;
+            (label, label_width)
+        }
     };
+    let total = label_width + value_width;
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{total}" height="20" role="img" aria-label="{label}: {value}">
+<title>{label}: {value}</title>
+<clipPath id="r"><rect width="{total}" height="20" rx="3"/></clipPath>
+<g clip-path="url(#r)">
+<rect width="{label_width}" height="20" fill="{label_bg}"/>
+<rect x="{label_width}" width="{value_width}" height="20" fill="{color}"/>
+</g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,'DejaVu Sans',sans-serif" font-size="11">
+<text x="{label_x}" y="14">{label}</text>
+<text x="{value_x}" y="14">{value}</text>
+</g>
+</svg>"##,
+        total = total,
+        label_width = label_width,
+        value_width = value_width,
+        label_bg = theme.label,
+        color = color,
+        label_x = label_width / 2.0,
+        value_x = label_width + value_width / 2.0,
+        label = escape_html(&label),
+        value = value,
+    )
+}
 
-    let list: Vec<BlogPostSummary> = rows.filter_map(Result::ok).collect();
-    Json(list)
+fn render_now_playing_card(device: Option<&DeviceStatus>, theme: SvgTheme, width: u32) -> String {
+    let text_width = width as f64 - 72.0;
+    let (heading, title, artist, bar_color) = match device {
+        Some(device) => {
+            let heading = match device.music_source.as_deref() {
+                Some(source) => format!("Now playing · {} · {}", device.device_name, source),
+                None => format!("Now playing · {}", device.device_name),
+            };
+            (
+                heading,
+                device
+                    .music_title
+                    .clone()
+                    .unwrap_or_else(|| "Unknown title".to_string()),
+                [
+                    device.music_artist.as_deref(),
+                    device.music_album.as_deref(),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" · "),
+                "#1db954",
+            )
+        }
+        None => (
+            "Now playing".to_string(),
+            "Not playing".to_string(),
+            String::new(),
+            theme.muted,
+        ),
+    };
+    let heading = svg_truncate(&heading, 11.0, text_width);
+    let title = svg_truncate(&title, 15.0, text_width);
+    let artist = svg_truncate(&artist, 12.0, text_width);
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="88" viewBox="0 0 {width} 88" role="img" aria-label="{title}">
+<title>{title}</title>
+<rect x="0.5" y="0.5" width="{rect_width}" height="87" rx="8" fill="{background}" stroke="{border}"/>
+<g fill="{bar_color}">
+<rect x="20" y="38" width="5" height="22" rx="1.5"/>
+<rect x="28" y="28" width="5" height="32" rx="1.5"/>
+<rect x="36" y="44" width="5" height="16" rx="1.5"/>
+</g>
+<g font-family="-apple-system,BlinkMacSystemFont,'Segoe UI',Helvetica,Arial,sans-serif">
+<text x="56" y="26" font-size="11" fill="{muted}">{heading}</text>
+<text x="56" y="50" font-size="15" font-weight="600" fill="{text}">{title}</text>
+<text x="56" y="70" font-size="12" fill="{muted}">{artist}</text>
+</g>
+</svg>"##,
+        width = width,
+        rect_width = width - 1,
+        background = theme.background,
+        border = theme.border,
+        bar_color = bar_color,
+        muted = theme.muted,
+        text = theme.text,
+        heading = escape_html(&heading),
+        title = escape_html(&title),
+        artist = escape_html(&artist),
+    )
 }
 
-async fn get_manual_status(State(state): State<AppState>) -> impl IntoResponse {
+async fn status_summary_rules_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
     let conn = state.db.lock().unwrap();
-    let result = conn.query_row(
-        "SELECT global_manual_offline, updated_at FROM status_control WHERE id = 1",
-        [],
-        |row| {
-            Ok(ManualStatusResponse {
-                enabled: row.get::<_, i32>(0)? == 1,
-                updated_at: row.get(1)?,
-            })
-        },
-    );
-    let payload = result.unwrap_or(ManualStatusResponse {
-        enabled: false,
-        updated_at: 0,
-    });
-    Json(payload)
+    Json(load_summary_rules(&conn)).into_response()
 }
 
-async fn set_manual_status(
+async fn status_summary_rules_set(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<ManualStatusPayload>,
+    Json(payload): Json<SummaryRules>,
 ) -> impl IntoResponse {
     if !authorized(&headers, &state.token) {
         return StatusCode::UNAUTHORIZED.into_response();
     }
-    let now = now_ts();
+    let mut state_priority: Vec<String> = Vec::new();
+    for item in payload.state_priority {
+        let item = item.trim().to_lowercase();
+        if !SUMMARY_STATES.contains(&item.as_str()) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiMessage {
+                    message: format!("未知状态：{}", item),
+                }),
+            )
+                .into_response();
+        }
+        if !state_priority.contains(&item) {
+            state_priority.push(item);
+        }
+    }
+    let device_priority: Vec<String> = payload
+        .device_priority
+        .into_iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect();
+    let rules = SummaryRules {
+        state_priority,
+        device_priority,
+    };
+    let value = serde_json::to_string(&rules).unwrap_or_default();
     let conn = state.db.lock().unwrap();
     if conn
         .execute(
-            "INSERT INTO status_control (id, global_manual_offline, updated_at)
+            "INSERT INTO status_summary_rules (id, rules, updated_at)
              VALUES (1, ?1, ?2)
-             ON CONFLICT(id) DO UPDATE SET
-               global_manual_offline = excluded.global_manual_offline,
-               updated_at = excluded.updated_at",
-            params![payload.enabled as i32, now],
+             ON CONFLICT(id) DO UPDATE SET rules = excluded.rules, updated_at = excluded.updated_at",
+            params![value, now_ts()],
         )
         .is_err()
     {
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
-    (
-        StatusCode::OK,
-        Json(ManualStatusResponse {
-            enabled: payload.enabled,
-            updated_at: now,
-        }),
-    )
-        .into_response()
+    Json(load_summary_rules(&conn)).into_response()
 }
 
-async fn device_status_update(
+async fn music_history(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<DeviceStatusUpdatePayload>,
+    Query(query): Query<MusicHistoryQuery>,
 ) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED;
-    }
-
     let now = now_ts();
+    let to = query.to.unwrap_or(now);
+    let from = query.from.unwrap_or(to - 7 * 24 * 3600);
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let device_id = normalize_optional(query.device_id, 128);
     let conn = state.db.lock().unwrap();
-    let existing = conn
-        .query_row(
-            "SELECT device_name, online, last_seen, idle_seconds, manual_offline,
-                    music_playing, music_title, music_artist, music_source, music_updated_at
-             FROM device_status
-             WHERE device_id = ?1",
-            params![payload.device_id.as_str()],
-            |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, i32>(1)?,
-                    row.get::<_, i64>(2)?,
-                    row.get::<_, Option<i64>>(3)?,
-                    row.get::<_, i32>(4)?,
-                    row.get::<_, i32>(5)?,
-                    row.get::<_, Option<String>>(6)?,
-                    row.get::<_, Option<String>>(7)?,
-                    row.get::<_, Option<String>>(8)?,
-                    row.get::<_, Option<i64>>(9)?,
-                ))
-            },
-        )
-        .ok();
-
-    let device_name = payload
-        .device_name
-        .or_else(|| existing.as_ref().map(|v| v.0.clone()))
-        .unwrap_or_else(|| payload.device_id.clone());
-    let online = payload
-        .online
-        .unwrap_or_else(|| existing.as_ref().map(|v| v.1 == 1).unwrap_or(false));
-    let last_seen = if payload.online.is_some() {
-        now
-    } else {
-        existing.as_ref().map(|v| v.2).unwrap_or(now)
-    };
-    let idle_seconds = if payload.online.is_some() {
-        None
-    } else {
-        existing.as_ref().and_then(|v| v.3)
+    let mut stmt = match conn.prepare(
+        "SELECT id, device_id, title, artist, source, started_at, ended_at, listened_secs
+         FROM music_plays
+         WHERE started_at >= ?1 AND started_at <= ?2
+           AND (?3 IS NULL OR device_id = ?3)
+           AND device_id NOT IN (SELECT device_id FROM device_status WHERE hidden = 1)
+         ORDER BY started_at DESC
+         LIMIT ?4",
+    ) {
+        Ok(stmt) => stmt,
+        Err(_) => return Json(Vec::<MusicPlay>::new()),
     };
-    let manual_offline = payload
-        .manual_offline
-        .unwrap_or_else(|| existing.as_ref().map(|v| v.4 == 1).unwrap_or(false));
-    let has_music_update = payload.music_playing.is_some()
-        || payload.music_title.is_some()
-        || payload.music_artist.is_some()
-        || payload.music_source.is_some();
-    let music_playing = payload
-        .music_playing
-        .unwrap_or_else(|| existing.as_ref().map(|v| v.5 == 1).unwrap_or(false));
-    let music_title = payload
-        .music_title
-        .or_else(|| existing.as_ref().and_then(|v| v.6.clone()));
-    let music_artist = payload
-        .music_artist
-        .or_else(|| existing.as_ref().and_then(|v| v.7.clone()));
-    let music_source = payload
-        .music_source
-        .or_else(|| existing.as_ref().and_then(|v| v.8.clone()));
-    let music_updated_at = if has_music_update {
-        Some(now)
-    } else {
-        existing.as_ref().and_then(|v| v.9)
+    let rows = match stmt.query_map(params![from, to, device_id, limit], |row| {
+        Ok(MusicPlay {
+            id: row.get(0)?,
+            device_id: row.get(1)?,
+            title: row.get(2)?,
+            artist: row.get(3)?,
+            source: row.get(4)?,
+            started_at: row.get(5)?,
+            ended_at: row.get(6)?,
+            listened_secs: row.get(7)?,
+        })
+    }) {
+        Ok(rows) => rows,
+        Err(_) => return Json(Vec::<MusicPlay>::new()),
     };
+    Json(rows.filter_map(Result::ok).collect::<Vec<_>>())
+}
 
-    if conn
-        .execute(
-            "INSERT INTO device_status (
-                device_id, device_name, online, last_seen, idle_seconds, manual_offline,
-                music_playing, music_title, music_artist, music_source, music_updated_at
-             )
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
-             ON CONFLICT(device_id) DO UPDATE SET
-               device_name = excluded.device_name,
-               online = excluded.online,
-               last_seen = excluded.last_seen,
-               idle_seconds = excluded.idle_seconds,
-               manual_offline = excluded.manual_offline,
-               music_playing = excluded.music_playing,
-               music_title = excluded.music_title,
-               music_artist = excluded.music_artist,
-               music_source = excluded.music_source,
-               music_updated_at = excluded.music_updated_at",
-            params![
-                payload.device_id,
-                device_name,
-                online as i32,
-                last_seen,
-                idle_seconds,
-                manual_offline as i32,
-                music_playing as i32,
-                music_title,
-                music_artist,
-                music_source,
-                music_updated_at,
-            ],
-        )
-        .is_err()
-    {
-        return StatusCode::INTERNAL_SERVER_ERROR;
+async fn music_rules_list(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
     }
-
-    StatusCode::OK
+    let conn = state.db.lock().unwrap();
+    Json(load_music_rules(&conn)).into_response()
 }
 
-async fn blog_detail(
+async fn music_rules_upsert(
     State(state): State<AppState>,
-    axum::extract::Path(slug): axum::extract::Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<MusicRule>,
 ) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let kind = payload.kind.trim().to_lowercase();
+    let device_id = normalize_optional(payload.device_id, 128);
+    let source_pattern = normalize_optional(payload.source_pattern, 256);
+    let title_pattern = normalize_optional(payload.title_pattern, 256);
+    let display_name = normalize_optional(payload.display_name, 64);
+    let icon = normalize_optional(payload.icon, 512);
+    let invalid = match kind.as_str() {
+        "source_map" => source_pattern.is_none() || (display_name.is_none() && icon.is_none()),
+        "hide" => device_id.is_none() && source_pattern.is_none(),
+        "redact" => title_pattern.is_none(),
+        _ => true,
+    };
+    if invalid {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "规则无效：source_map 需要 source_pattern 与名称或图标，hide 需要 device_id 或 source_pattern，redact 需要 title_pattern".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let sort_order = payload.sort_order.unwrap_or(0);
     let conn = state.db.lock().unwrap();
-    let row = conn.query_row(
-        "SELECT slug, title, date, tag, excerpt, content_json, content_md, sort_order, updated_at
-         FROM blog_posts
-         WHERE slug = ?1
-         LIMIT 1",
-        params![slug],
-        |row| {
-            let content_json: String = row.get(5)?;
-            let content = serde_json::from_str::<Vec<String>>(&content_json).unwrap_or_default();
-            let content_md = row
-                .get::<_, Option<String>>(6)?
-                .unwrap_or_else(|| content.join("\n"));
-            Ok(BlogPost {
-                slug: row.get(0)?,
-                title: row.get(1)?,
-                date: row.get(2)?,
-                tag: row.get(3)?,
-                excerpt: row.get(4)?,
-                content,
-                content_md,
-                sort_order: row.get(7)?,
-                updated_at: row.get(8)?,
-            })
-        },
-    );
-
-    match row {
-        Ok(post) => (StatusCode::OK, Json(post)).into_response(),
-        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    let result = match payload.id {
+        Some(id) => conn.execute(
+            "UPDATE music_rules
+             SET kind = ?1, device_id = ?2, source_pattern = ?3, title_pattern = ?4,
+                 display_name = ?5, icon = ?6, sort_order = ?7
+             WHERE id = ?8",
+            params![
+                kind,
+                device_id,
+                source_pattern,
+                title_pattern,
+                display_name,
+                icon,
+                sort_order,
+                id
+            ],
+        ),
+        None => conn.execute(
+            "INSERT INTO music_rules
+             (kind, device_id, source_pattern, title_pattern, display_name, icon, sort_order, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                kind,
+                device_id,
+                source_pattern,
+                title_pattern,
+                display_name,
+                icon,
+                sort_order,
+                now_ts()
+            ],
+        ),
+    };
+    match result {
+        Ok(0) => return StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
+    state.hub.publish_changes(&conn, &state.presence);
+    Json(load_music_rules(&conn)).into_response()
 }
 
-async fn blog_update(
+async fn music_artwork_upload(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<BlogPayload>,
+    Query(query): Query<MusicArtworkQuery>,
+    body: Bytes,
 ) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED;
-    }
-
-    let now = now_ts();
-    let mut conn = state.db.lock().unwrap();
-    let tx = match conn.transaction() {
-        Ok(tx) => tx,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    if body.len() > MUSIC_ARTWORK_MAX_BYTES {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ApiMessage {
+                message: "封面图片不能超过 1 MB".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let Some(content_type) = sniff_image_type(&body) else {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ApiMessage {
+                message: "封面仅支持 PNG / JPEG / GIF / WebP".to_string(),
+            }),
+        )
+            .into_response();
     };
-
-    if tx.execute("DELETE FROM blog_posts", []).is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
+    let conn = state.db.lock().unwrap();
+    if let Err(code) = device_authorized(&state, &conn, &headers, &query.device_id, &body) {
+        return code.into_response();
     }
+    let hash: String = Sha256::digest(&body)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let _ = conn.execute(
+        "INSERT INTO music_artwork (hash, content_type, data, created_at)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(hash) DO UPDATE SET created_at = excluded.created_at",
+        params![hash, content_type, body.as_ref(), now_ts()],
+    );
+    Json(MusicArtworkUploaded {
+        url: format!("/music/artwork/{}", hash),
+        hash,
+    })
+    .into_response()
+}
 
-    for (idx, item) in payload.items.into_iter().enumerate() {
-        let mut slug = item.slug.unwrap_or_else(|| format!("post-{}-{}", now, idx));
-        slug = slug
-            .trim()
-            .to_lowercase()
-            .replace(' ', "-")
-            .chars()
-            .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-')
-            .collect::<String>();
-        if slug.is_empty() {
-            slug = format!("post-{}-{}", now, idx);
-        }
-        let sort_order = item.sort_order.unwrap_or(idx as i64);
-        let input_content = item.content.unwrap_or_default();
-        let content_md = item.content_md.unwrap_or_else(|| input_content.join("\n"));
-        let content: Vec<String> = if !input_content.is_empty() {
-            input_content
-        } else {
-            content_md.split('\n').map(|v| v.to_string()).collect()
-        };
-        let excerpt = item
-            .excerpt
-            .filter(|s| !s.trim().is_empty())
-            .unwrap_or_else(|| {
-                content
-                    .iter()
-                    .find(|line| !line.trim().is_empty())
-                    .cloned()
-                    .unwrap_or_default()
-            });
-        let content_json = match serde_json::to_string(&content) {
-            Ok(v) => v,
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        if tx
-            .execute(
-                "INSERT INTO blog_posts (slug, title, date, tag, excerpt, content_json, content_md, sort_order, updated_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                params![
-                    slug,
-                    item.title,
-                    item.date,
-                    item.tag,
-                    excerpt,
-                    content_json,
-                    content_md,
-                    sort_order,
-                    now
-                ],
-            )
-            .is_err()
-        {
-            return StatusCode::INTERNAL_SERVER_ERROR;
+async fn music_artwork_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(hash): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    if !is_artwork_hash(&hash) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let etag = format!("\"{}\"", hash);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            "public, max-age=31536000, immutable".to_string(),
+        ),
+    ];
+    if cache::etag_matches(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let artwork = conn
+        .query_row(
+            "SELECT content_type, data FROM music_artwork WHERE hash = ?1",
+            params![hash],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+        )
+        .ok();
+    match artwork {
+        Some((content_type, data)) => {
+            let headers = [
+                (header::CONTENT_TYPE, content_type),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ];
+            (cache_headers, headers, data).into_response()
         }
+        None => StatusCode::NOT_FOUND.into_response(),
     }
+}
 
-    if tx.commit().is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
+fn is_artwork_hash(value: &str) -> bool {
+    value.len() == 64
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
 
-    StatusCode::OK
+fn sniff_image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() > 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
 }
 
-async fn links_list(State(state): State<AppState>) -> impl IntoResponse {
+async fn music_rules_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<MusicRuleDeletePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
     let conn = state.db.lock().unwrap();
-    let mut stmt = match conn.prepare(
-        "SELECT id, name, url, avatar_url, description, tags, sort_order, created_at,
-                backlink_status, backlink_deadline
-         FROM friend_links
-         ORDER BY sort_order ASC, created_at DESC",
-    ) {
-        Ok(stmt) => stmt,
-        Err(_) => return Json(Vec::<FriendLink>::new()),
-    };
-
-    let rows = match stmt.query_map([], |row| {
-        Ok(FriendLink {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            url: row.get(2)?,
-            avatar_url: row.get(3)?,
-            description: row.get(4)?,
-            tags: row.get(5)?,
-            sort_order: row.get(6)?,
-            created_at: row.get(7)?,
-            backlink_status: row.get(8)?,
-            backlink_deadline: row.get(9)?,
-        })
-    }) {
-        Ok(rows) => rows,
-        Err(_) => return Json(Vec::<FriendLink>::new()),
-    };
+    let _ = conn.execute("DELETE FROM music_rules WHERE id = ?1", params![payload.id]);
+    state.hub.publish_changes(&conn, &state.presence);
+    Json(load_music_rules(&conn)).into_response()
+}
 
-    Json(rows.filter_map(Result::ok).collect::<Vec<_>>())
+async fn webhooks_list(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    Json(load_webhooks(&conn)).into_response()
 }
 
-async fn links_apply(
+async fn webhooks_upsert(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<LinkApplyPayload>,
+    Json(payload): Json<WebhookPayload>,
 ) -> impl IntoResponse {
-    let anti_abuse = {
-        let conn = state.db.lock().unwrap();
-        resolve_anti_abuse_config(&conn, &state.anti_abuse)
-    };
-    let site_name = payload.site_name.trim();
-    if site_name.is_empty() || site_name.chars().count() > 32 {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let url = payload.url.trim().to_string();
+    let valid_url = Url::parse(&url)
+        .map(|v| matches!(v.scheme(), "http" | "https"))
+        .unwrap_or(false);
+    if !valid_url || url.len() > 1024 {
         return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: "站点名称长度需在 1-32 字符内".to_string(),
+                message: "Webhook 地址需为 http(s) URL".to_string(),
             }),
         )
             .into_response();
     }
+    let mut events = Vec::new();
+    for event in payload.events.unwrap_or_default() {
+        let event = event.trim().to_lowercase();
+        if !WEBHOOK_EVENTS.contains(&event.as_str()) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiMessage {
+                    message: format!("未知事件：{}", event),
+                }),
+            )
+                .into_response();
+        }
+        if !events.contains(&event) {
+            events.push(event);
+        }
+    }
+    let events = events.join(",");
+    let secret = normalize_optional(payload.secret, 256);
+    let enabled = payload.enabled.unwrap_or(true);
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let result = match payload.id {
+        Some(id) => conn.execute(
+            "UPDATE webhooks
+             SET url = ?1, secret = COALESCE(?2, secret), events = ?3, enabled = ?4, updated_at = ?5
+             WHERE id = ?6",
+            params![url, secret, events, enabled as i32, now, id],
+        ),
+        None => conn.execute(
+            "INSERT INTO webhooks (url, secret, events, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+            params![
+                url,
+                secret.unwrap_or_else(|| generate_device_secret("whsec")),
+                events,
+                enabled as i32,
+                now
+            ],
+        ),
+    };
+    match result {
+        Ok(0) => return StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+    Json(load_webhooks(&conn)).into_response()
+}
 
-    let site_url = payload.site_url.trim();
-    if !is_valid_http_url(site_url) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "站点地址必须是可访问的 http/https 链接".to_string(),
-            }),
-        )
-            .into_response();
+async fn webhooks_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<WebhookIdPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
     }
-    if is_disallowed_link_host(site_url, &anti_abuse) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "该站点类型不接受友链申请，请提交可互链的个人/博客站点".to_string(),
-            }),
+    let conn = state.db.lock().unwrap();
+    let _ = conn.execute("DELETE FROM webhooks WHERE id = ?1", params![payload.id]);
+    let _ = conn.execute(
+        "DELETE FROM webhook_deliveries WHERE webhook_id = ?1",
+        params![payload.id],
+    );
+    Json(load_webhooks(&conn)).into_response()
+}
+
+async fn webhooks_test(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<WebhookIdPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let body = webhook_body("ping", serde_json::json!({ "webhook_id": payload.id }), now);
+    let conn = state.db.lock().unwrap();
+    let inserted = conn
+        .execute(
+            "INSERT INTO webhook_deliveries
+             (webhook_id, event, payload, status, attempts, next_attempt_at, created_at, updated_at)
+             SELECT id, 'ping', ?2, 'pending', 0, ?3, ?3, ?3 FROM webhooks WHERE id = ?1",
+            params![payload.id, body, now],
         )
-            .into_response();
+        .unwrap_or(0);
+    if inserted == 0 {
+        return StatusCode::NOT_FOUND.into_response();
     }
+    state.hub.webhook_wake.notify_one();
+    (
+        StatusCode::ACCEPTED,
+        Json(ApiMessage {
+            message: "测试事件已加入发送队列".to_string(),
+        }),
+    )
+        .into_response()
+}
 
-    let avatar_url = normalize_optional(payload.avatar_url, 255);
-    if avatar_url
-        .as_deref()
-        .is_some_and(|value| !is_valid_http_url(value))
-    {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "头像地址格式不正确（需为 http/https）".to_string(),
-            }),
-        )
-            .into_response();
+async fn webhook_deliveries_list(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<WebhookDeliveryQuery>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
     }
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let status = normalize_optional(query.status, 16);
+    let conn = state.db.lock().unwrap();
+    let items = conn
+        .prepare(
+            "SELECT id, webhook_id, event, status, attempts, next_attempt_at, response_status,
+                    last_error, payload, created_at, updated_at
+             FROM webhook_deliveries
+             WHERE (?1 IS NULL OR webhook_id = ?1) AND (?2 IS NULL OR status = ?2)
+             ORDER BY id DESC
+             LIMIT ?3",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![query.webhook_id, status, limit], |row| {
+                let payload: String = row.get(8)?;
+                Ok(WebhookDeliveryItem {
+                    id: row.get(0)?,
+                    webhook_id: row.get(1)?,
+                    event: row.get(2)?,
+                    status: row.get(3)?,
+                    attempts: row.get(4)?,
+                    next_attempt_at: row.get(5)?,
+                    response_status: row.get(6)?,
+                    last_error: row.get(7)?,
+                    payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::Null),
+                    created_at: row.get(9)?,
+                    updated_at: row.get(10)?,
+                })
+            })
+            .map(|rows| rows.filter_map(Result::ok).collect::<Vec<_>>())
+        })
+        .unwrap_or_default();
+    Json(items).into_response()
+}
 
-    let description = normalize_optional(payload.description, 280);
-    let email = normalize_optional(payload.email, 128);
-    if email.is_none() {
+async fn music_stats(
+    State(state): State<AppState>,
+    Query(query): Query<MusicStatsQuery>,
+) -> impl IntoResponse {
+    let period = query
+        .period
+        .map(|v| v.trim().to_lowercase())
+        .unwrap_or_else(|| "week".to_string());
+    let Some(from) = music_period_start(&period) else {
         return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: "请填写联系邮箱".to_string(),
+                message: "period 仅支持 day/week/month".to_string(),
             }),
         )
             .into_response();
-    }
-    let email = email.unwrap();
-    if !is_valid_email_address(&email) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "联系邮箱格式不正确".to_string(),
-            }),
+    };
+    let to = now_ts();
+    let limit = query.limit.unwrap_or(10).clamp(1, 50);
+    let device_id = normalize_optional(query.device_id, 128);
+    let conn = state.db.lock().unwrap();
+    let (play_count, total_listened_secs) = conn
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(listened_secs), 0) FROM music_plays
+             WHERE started_at >= ?1 AND started_at <= ?2
+               AND (?3 IS NULL OR device_id = ?3)
+               AND device_id NOT IN (SELECT device_id FROM device_status WHERE hidden = 1)",
+            params![from, to, device_id],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
         )
-            .into_response();
+        .unwrap_or((0, 0));
+    let top_tracks = conn
+        .prepare(
+            "SELECT title, artist, COUNT(*) AS plays, COALESCE(SUM(listened_secs), 0) AS secs
+             FROM music_plays
+             WHERE started_at >= ?1 AND started_at <= ?2
+               AND (?3 IS NULL OR device_id = ?3)
+               AND device_id NOT IN (SELECT device_id FROM device_status WHERE hidden = 1)
+             GROUP BY title, artist
+             ORDER BY plays DESC, secs DESC
+             LIMIT ?4",
+        )
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![from, to, device_id, limit], |row| {
+                Ok(MusicTrackStat {
+                    title: row.get(0)?,
+                    artist: row.get(1)?,
+                    plays: row.get(2)?,
+                    listened_secs: row.get(3)?,
+                })
+            })?;
+            Ok(rows.filter_map(Result::ok).collect::<Vec<_>>())
+        })
+        .unwrap_or_default();
+    let top_artists = conn
+        .prepare(
+            "SELECT artist, COUNT(*) AS plays, COALESCE(SUM(listened_secs), 0) AS secs
+             FROM music_plays
+             WHERE started_at >= ?1 AND started_at <= ?2
+               AND (?3 IS NULL OR device_id = ?3)
+               AND device_id NOT IN (SELECT device_id FROM device_status WHERE hidden = 1)
+               AND artist IS NOT NULL AND artist != ''
+             GROUP BY artist
+             ORDER BY secs DESC, plays DESC
+             LIMIT ?4",
+        )
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![from, to, device_id, limit], |row| {
+                Ok(MusicArtistStat {
+                    artist: row.get(0)?,
+                    plays: row.get(1)?,
+                    listened_secs: row.get(2)?,
+                })
+            })?;
+            Ok(rows.filter_map(Result::ok).collect::<Vec<_>>())
+        })
+        .unwrap_or_default();
+    (
+        StatusCode::OK,
+        Json(MusicStats {
+            period,
+            from,
+            to,
+            total_listened_secs,
+            play_count,
+            top_tracks,
+            top_artists,
+        }),
+    )
+        .into_response()
+}
+
+async fn schedule_list(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ScheduleQuery>,
+) -> impl IntoResponse {
+    if query.from.is_none() && query.to.is_none() && query.now.is_none() {
+        let snapshot = match state.cache.get("schedule") {
+            Some(snapshot) => snapshot,
+            None => {
+                let conn = state.db.lock().unwrap();
+                let list: Vec<ScheduleItem> = load_schedule_items(&conn)
+                    .iter()
+                    .map(schedule_with_timing)
+                    .collect();
+                let last_modified = list.iter().map(|item| item.updated_at).max().unwrap_or(0);
+                state.cache.store("schedule", &list, last_modified)
+            }
+        };
+        return cache::respond(&headers, &snapshot, &state.content_cache_control);
     }
-    if is_blocked_contact_email(&email) {
+
+    let now = now_ts();
+    let tz = state.schedule_timezone;
+    let bound = |value: Option<&str>, default: i64| match value.map(str::trim) {
+        None | Some("") => Some(default),
+        Some(raw) => parse_schedule_bound(raw, tz, now),
+    };
+    let window = match query.now.as_deref() {
+        Some(at) => bound(Some(at), now).map(|at| (at, at + 1)),
+        None => bound(query.from.as_deref(), now).and_then(|from| {
+            bound(query.to.as_deref(), from + SCHEDULE_DEFAULT_WINDOW_SECS).map(|to| (from, to))
+        }),
+    };
+    let Some((mut from, mut to)) = window else {
         return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: "该邮箱不支持用于友链申请".to_string(),
+                message: "时间格式无效".to_string(),
             }),
         )
             .into_response();
-    }
-    if anti_abuse.disposable_email_block && is_disposable_email_domain(&email) {
+    };
+    // `?from=` alone is the homepage's "upcoming" view: round it to the minute
+    // so polls share a cached body, keeping only the latest such view.
+    let view_key = (query.now.is_none() && query.to.is_none()).then(|| {
+        from -= from.rem_euclid(60);
+        to = from + SCHEDULE_DEFAULT_WINDOW_SECS;
+        format!("schedule:view:{}", from)
+    });
+    if to <= from || to - from > SCHEDULE_MAX_WINDOW_SECS {
         return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: "不支持一次性邮箱，请使用常用邮箱提交".to_string(),
+                message: "时间范围无效（最长 366 天）".to_string(),
             }),
         )
             .into_response();
     }
-    if anti_abuse.edu_gov_email_block && is_edu_or_gov_email_domain(&email) {
+    let Some(view_key) = view_key else {
+        let items = {
+            let conn = state.db.lock().unwrap();
+            load_schedule_items(&conn)
+        };
+        return Json(expand_schedule(&items, from, to, query.now.is_none())).into_response();
+    };
+    let snapshot = match state.cache.get(&view_key) {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            let items = load_schedule_items(&conn);
+            let last_modified = items.iter().map(|item| item.updated_at).max().unwrap_or(0);
+            state.cache.invalidate("schedule:view");
+            state.cache.store(
+                &view_key,
+                &expand_schedule(&items, from, to, true),
+                last_modified,
+            )
+        }
+    };
+    cache::respond(&headers, &snapshot, &state.content_cache_control)
+}
+
+const SCHEDULE_COLUMNS: &str =
+    "id, title, time, note, location, tag, start_at, end_at, timezone, all_day, rrule,
+     exdates, source, sort_order, updated_at, version, status_effect, status_text, reminders";
+
+fn schedule_item_from_row(row: &rusqlite::Row) -> rusqlite::Result<ScheduleItem> {
+    Ok(ScheduleItem {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        time: row.get(2)?,
+        note: row.get(3)?,
+        location: row.get(4)?,
+        tag: row.get(5)?,
+        start_at: row.get(6)?,
+        end_at: row.get(7)?,
+        timezone: row.get(8)?,
+        all_day: row.get::<_, i64>(9)? == 1,
+        rrule: row.get(10)?,
+        exdates: row
+            .get::<_, Option<String>>(11)?
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default(),
+        source: row.get(12)?,
+        status_effect: row.get(16)?,
+        status_text: row.get(17)?,
+        reminders: row
+            .get::<_, Option<String>>(18)?
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default(),
+        start_ts: None,
+        end_ts: None,
+        recurrence_id: None,
+        sort_order: row.get(13)?,
+        updated_at: row.get(14)?,
+        version: row.get(15)?,
+    })
+}
+
+fn load_schedule_items(conn: &Connection) -> Vec<ScheduleItem> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM schedule_items ORDER BY sort_order ASC, updated_at DESC",
+            SCHEDULE_COLUMNS
+        ))
+        .unwrap();
+
+    let rows = stmt.query_map([], schedule_item_from_row).unwrap();
+
+    rows.filter_map(Result::ok).collect()
+}
+
+fn load_schedule_item(conn: &Connection, id: &str) -> Option<ScheduleItem> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM schedule_items WHERE id = ?1",
+            SCHEDULE_COLUMNS
+        ),
+        params![id],
+        schedule_item_from_row,
+    )
+    .ok()
+}
+
+fn upsert_schedule_item(conn: &Connection, item: &ScheduleItem) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO schedule_items (
+            id, title, time, note, location, tag, start_at, end_at, timezone, all_day, rrule,
+            exdates, source, sort_order, updated_at, version, status_effect, status_text,
+            reminders
+         )
+         VALUES (
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19
+         )
+         ON CONFLICT(id) DO UPDATE SET
+           title = excluded.title,
+           time = excluded.time,
+           note = excluded.note,
+           location = excluded.location,
+           tag = excluded.tag,
+           start_at = excluded.start_at,
+           end_at = excluded.end_at,
+           timezone = excluded.timezone,
+           all_day = excluded.all_day,
+           rrule = excluded.rrule,
+           exdates = excluded.exdates,
+           source = excluded.source,
+           sort_order = excluded.sort_order,
+           updated_at = excluded.updated_at,
+           version = excluded.version,
+           status_effect = excluded.status_effect,
+           status_text = excluded.status_text,
+           reminders = excluded.reminders",
+        params![
+            item.id,
+            item.title,
+            item.time,
+            item.note,
+            item.location,
+            item.tag,
+            item.start_at,
+            item.end_at,
+            item.timezone,
+            item.all_day as i64,
+            item.rrule,
+            schedule_exdates_json(&item.exdates),
+            item.source,
+            item.sort_order,
+            item.updated_at,
+            item.version,
+            item.status_effect,
+            item.status_text,
+            (!item.reminders.is_empty())
+                .then(|| serde_json::to_string(&item.reminders).ok())
+                .flatten()
+        ],
+    )
+}
+
+/// Carries `updated_at` / `version` over from `old` when nothing else changed,
+/// otherwise bumps the version. Returns whether the item changed.
+fn schedule_item_bump(item: &mut ScheduleItem, old: Option<&ScheduleItem>) -> bool {
+    let Some(old) = old else {
+        item.version = 1;
+        return true;
+    };
+    item.version = old.version;
+    let updated_at = std::mem::replace(&mut item.updated_at, old.updated_at);
+    if item == old {
+        return false;
+    }
+    item.updated_at = updated_at;
+    item.version = old.version + 1;
+    true
+}
+
+/// `expected` follows the per-item write endpoints: `None` skips the check,
+/// `0` only matches a missing row, anything else must equal the stored version.
+fn version_matches(expected: Option<i64>, current: Option<i64>) -> bool {
+    match expected {
+        None => true,
+        Some(0) => current.is_none(),
+        Some(version) => current == Some(version),
+    }
+}
+
+fn version_conflict<T: Serialize>(current: Option<T>) -> Response {
+    let message = if current.is_some() {
+        "内容已被修改，请刷新后重试"
+    } else {
+        "内容已被删除，请刷新后重试"
+    };
+    (
+        StatusCode::CONFLICT,
+        Json(VersionConflict {
+            message: message.to_string(),
+            current,
+        }),
+    )
+        .into_response()
+}
+
+fn schedule_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiMessage {
+            message: "日程不存在".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+async fn schedule_update(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<SchedulePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let now = now_ts();
+    let mut items = Vec::with_capacity(payload.items.len());
+    for (idx, item) in payload.items.into_iter().enumerate() {
+        match validate_schedule_item(item, idx, now, state.schedule_timezone) {
+            Ok(item) => items.push(item),
+            Err(err) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiMessage {
+                        message: format!("第 {} 项：{}", idx + 1, err),
+                    }),
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    let mut conn = state.db.lock().unwrap();
+    let existing: HashMap<String, ScheduleItem> = load_schedule_items(&conn)
+        .into_iter()
+        .map(|item| (item.id.clone(), item))
+        .collect();
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if tx.execute("DELETE FROM schedule_items", []).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    for mut item in items {
+        let old = existing.get(&item.id);
+        schedule_item_bump(&mut item, old);
+        if upsert_schedule_item(&tx, &item).is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    if tx.commit().is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    state.cache.invalidate("schedule");
+    state.hub.publish_changes(&conn, &state.presence);
+
+    StatusCode::OK.into_response()
+}
+
+async fn schedule_put(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(input): Json<ScheduleItemInput>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let id = id.trim().to_string();
+    if id.is_empty() || id.chars().count() > 128 {
         return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: "不支持 .edu/.gov 邮箱，请使用常用邮箱提交".to_string(),
+                message: "id 无效".to_string(),
             }),
         )
             .into_response();
     }
-    let note = normalize_optional(payload.note, 280);
-    let ip = client_ip(&headers).or_else(|| Some("unknown".to_string()));
-    let ip_prefix = ip
-        .as_deref()
-        .and_then(ip_prefix_key)
-        .unwrap_or_else(|| "unknown".to_string());
-    let user_agent = headers
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .map(|v| v.to_string());
-    let captcha_token = normalize_optional(payload.captcha_token, 4096);
-    let email_domain = email_domain_of(&email).unwrap_or_else(|| "unknown".to_string());
-    let site_host = Url::parse(site_url)
-        .ok()
-        .and_then(|u| u.host_str().map(normalize_host))
-        .unwrap_or_else(|| "unknown".to_string());
-    let now = now_ts();
-    let verify_token = generate_verify_token();
-    let verify_deadline = now + anti_abuse.verify_window_secs;
-    let application_id: i64;
 
-    if let Some(captcha_cfg) = anti_abuse.captcha.as_ref() {
-        let Some(token) = captcha_token.as_deref() else {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiMessage {
-                    message: "请完成人机验证后再提交".to_string(),
-                }),
-            )
-                .into_response();
-        };
-        let verified = verify_captcha(captcha_cfg, token, ip.as_deref()).await;
-        if !verified {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiMessage {
-                    message: "人机验证失败，请刷新后重试".to_string(),
-                }),
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let existing = load_schedule_item(&conn, &id);
+    if !version_matches(input.version, existing.as_ref().map(|item| item.version)) {
+        return version_conflict(existing.as_ref().map(schedule_with_timing));
+    }
+    let sort_order = input
+        .sort_order
+        .or(existing.as_ref().map(|item| item.sort_order))
+        .unwrap_or_else(|| {
+            conn.query_row(
+                "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM schedule_items",
+                [],
+                |row| row.get(0),
             )
-                .into_response();
+            .unwrap_or(0)
+        });
+    let input = ScheduleItemInput {
+        id: Some(id),
+        sort_order: Some(sort_order),
+        ..input
+    };
+    schedule_save(&state, &conn, input, existing, now)
+}
+
+async fn schedule_patch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(patch): Json<SchedulePatchPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let Some(old) = load_schedule_item(&conn, &id) else {
+        return schedule_not_found();
+    };
+    if !version_matches(patch.version, Some(old.version)) {
+        return version_conflict(Some(schedule_with_timing(&old)));
+    }
+    let merge = |value: Option<String>, old: &Option<String>| match value {
+        Some(v) if v.trim().is_empty() => None,
+        Some(v) => Some(v),
+        None => old.clone(),
+    };
+    let input = ScheduleItemInput {
+        id: Some(old.id.clone()),
+        title: patch.title.unwrap_or_else(|| old.title.clone()),
+        time: Some(patch.time.unwrap_or_else(|| old.time.clone())),
+        note: merge(patch.note, &old.note),
+        location: merge(patch.location, &old.location),
+        tag: merge(patch.tag, &old.tag),
+        start_at: merge(patch.start_at, &old.start_at),
+        end_at: merge(patch.end_at, &old.end_at),
+        timezone: merge(patch.timezone, &old.timezone),
+        all_day: Some(patch.all_day.unwrap_or(old.all_day)),
+        rrule: merge(patch.rrule, &old.rrule),
+        exdates: Some(patch.exdates.unwrap_or_else(|| old.exdates.clone())),
+        source: merge(patch.source, &old.source),
+        status_effect: merge(patch.status_effect, &old.status_effect),
+        status_text: merge(patch.status_text, &old.status_text),
+        reminders: Some(patch.reminders.unwrap_or_else(|| old.reminders.clone())),
+        sort_order: Some(patch.sort_order.unwrap_or(old.sort_order)),
+        version: None,
+    };
+    schedule_save(&state, &conn, input, Some(old), now)
+}
+
+/// Validates and stores one item for the per-item endpoints; answers `201`
+/// for new items and `200` otherwise, leaving unchanged items untouched.
+fn schedule_save(
+    state: &AppState,
+    conn: &Connection,
+    input: ScheduleItemInput,
+    existing: Option<ScheduleItem>,
+    now: i64,
+) -> Response {
+    let mut item = match validate_schedule_item(input, 0, now, state.schedule_timezone) {
+        Ok(item) => item,
+        Err(err) => {
+            return (StatusCode::BAD_REQUEST, Json(ApiMessage { message: err })).into_response()
+        }
+    };
+    let created = existing.is_none();
+    if schedule_item_bump(&mut item, existing.as_ref()) {
+        if upsert_schedule_item(conn, &item).is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
+        state.cache.invalidate("schedule");
+        state.hub.publish_changes(conn, &state.presence);
+    }
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(schedule_with_timing(&item))).into_response()
+}
+
+async fn schedule_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Query(query): Query<VersionQuery>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
     }
 
+    let conn = state.db.lock().unwrap();
+    let Some(old) = load_schedule_item(&conn, &id) else {
+        return schedule_not_found();
+    };
+    if !version_matches(query.version, Some(old.version)) {
+        return version_conflict(Some(schedule_with_timing(&old)));
+    }
+    if conn
+        .execute("DELETE FROM schedule_items WHERE id = ?1", params![id])
+        .is_err()
     {
-        let conn = state.db.lock().unwrap();
-        let cleanup_before = now - VERIFY_EXPIRED_CLEANUP_GRACE_SECS;
-        let _ = conn.execute(
-            "DELETE FROM friend_link_verify_email_log
-             WHERE application_id IN (
-               SELECT id FROM friend_link_applications
-               WHERE status = 'verify_pending'
-                 AND verify_deadline IS NOT NULL
-                 AND verify_deadline < ?1
-             )",
-            params![cleanup_before],
-        );
-        let _ = conn.execute(
-            "DELETE FROM friend_link_applications
-             WHERE status = 'verify_pending'
-               AND verify_deadline IS NOT NULL
-               AND verify_deadline < ?1",
-            params![cleanup_before],
-        );
-        let existing_verify = {
-            let mut stmt = match conn.prepare(
-                "SELECT id, site_url, verify_token, verify_deadline
-                 FROM friend_link_applications
-                 WHERE status = 'verify_pending'
-                 ORDER BY created_at DESC",
-            ) {
-                Ok(v) => v,
-                Err(_) => {
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            };
-            let rows = match stmt.query_map([], |row| {
-                Ok((
-                    row.get::<_, i64>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, Option<String>>(2)?,
-                    row.get::<_, Option<i64>>(3)?,
-                ))
-            }) {
-                Ok(v) => v,
-                Err(_) => {
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            };
-            let candidates: Vec<_> = rows.filter_map(Result::ok).collect();
-            candidates.into_iter().find(|(_, url, _, _)| {
-                Url::parse(url)
-                    .ok()
-                    .and_then(|u| u.host_str().map(normalize_host))
-                    .map(|host| host == site_host || host.ends_with(&format!(".{}", site_host)))
-                    .unwrap_or(false)
-            })
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    state.cache.invalidate("schedule");
+    state.hub.publish_changes(&conn, &state.presence);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn schedule_ics(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let snapshot = match state.cache.get("schedule:ics") {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            let items = load_schedule_items(&conn);
+            let last_modified = items.iter().map(|item| item.updated_at).max().unwrap_or(0);
+            let body = render_schedule_ics(&items, state.schedule_timezone, now_ts());
+            state.cache.store_raw(
+                "schedule:ics",
+                body.into_bytes(),
+                "text/calendar; charset=utf-8",
+                last_modified,
+            )
+        }
+    };
+    cache::respond(&headers, &snapshot, &state.content_cache_control)
+}
+
+/// Items with neither `start_at` nor a parseable `time` cannot be placed on a
+/// calendar and are left out of the feed.
+fn render_schedule_ics(items: &[ScheduleItem], default_tz: chrono_tz::Tz, now: i64) -> String {
+    let local = |value: chrono::NaiveDateTime| value.format("%Y%m%dT%H%M%S").to_string();
+    let mut events = ics::Writer::default();
+    let mut zones: Vec<(chrono_tz::Tz, i32)> = Vec::new();
+    for item in items {
+        let Some(timing) =
+            schedule_timing(item).or_else(|| legacy_schedule_timing(&item.time, default_tz))
+        else {
+            continue;
         };
-        if let Some((id, _url, token_opt, deadline_opt)) = existing_verify {
-            let mut token = token_opt.unwrap_or_default();
-            let mut deadline = deadline_opt.unwrap_or(0);
-            if deadline > 0 && deadline < now {
-                token = generate_verify_token();
-                deadline = now + anti_abuse.verify_window_secs;
-                let _ = conn.execute(
-                    "UPDATE friend_link_applications
-                     SET verify_token = ?1, verify_deadline = ?2, updated_at = ?3
-                     WHERE id = ?4",
-                    params![token, deadline, now, id],
-                );
-            } else if token.is_empty() {
-                deadline = deadline.max(verify_deadline);
-                token = generate_verify_token();
-                let _ = conn.execute(
-                    "UPDATE friend_link_applications
-                     SET verify_token = ?1, verify_deadline = ?2, updated_at = ?3
-                     WHERE id = ?4",
-                    params![token, deadline, now, id],
-                );
+        events.line("BEGIN", "VEVENT");
+        events.text("UID", &format!("{}@status-backend", item.id));
+        events.line("DTSTAMP", &ics::utc_stamp(item.updated_at));
+        events.line("LAST-MODIFIED", &ics::utc_stamp(item.updated_at));
+        if timing.all_day {
+            let end = timing.end.unwrap_or(timing.start) + chrono::Duration::days(1);
+            events.line(
+                "DTSTART;VALUE=DATE",
+                &timing.start.format("%Y%m%d").to_string(),
+            );
+            events.line("DTEND;VALUE=DATE", &end.format("%Y%m%d").to_string());
+        } else if timing.tz == chrono_tz::UTC {
+            events.line("DTSTART", &format!("{}Z", local(timing.start)));
+            if let Some(end) = timing.end {
+                events.line("DTEND", &format!("{}Z", local(end)));
+            }
+        } else {
+            let tzid = timing.tz.name();
+            events.line(&format!("DTSTART;TZID={}", tzid), &local(timing.start));
+            if let Some(end) = timing.end {
+                events.line(&format!("DTEND;TZID={}", tzid), &local(end));
+            }
+            let year = timing.start.year();
+            match zones.iter_mut().find(|(tz, _)| *tz == timing.tz) {
+                Some(zone) => zone.1 = zone.1.min(year),
+                None => zones.push((timing.tz, year)),
             }
-            return (
-                StatusCode::OK,
-                Json(LinkApplyResponse {
-                    message: "该站点已有待验证申请，已为你加载验证信息。".to_string(),
-                    application_id: id,
-                    verify_status: "verify_pending".to_string(),
-                    http_verify_path: "/.well-known/meow-links.txt".to_string(),
-                    verify_token: token,
-                    verify_deadline: deadline,
-                }),
-            )
-                .into_response();
         }
-        let recent_apply_count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM friend_link_applications
-                 WHERE ip = ?1 AND created_at >= ?2",
-                params![
-                    ip.as_deref().unwrap_or("unknown"),
-                    now - anti_abuse.apply_rate_limit_window_secs
-                ],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
-        if recent_apply_count >= anti_abuse.apply_rate_limit_max {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                Json(ApiMessage {
-                    message: "提交过于频繁，请稍后再试".to_string(),
-                }),
-            )
-                .into_response();
+        if let Some(rule) = timing.rule.as_ref() {
+            events.line("RRULE", &ics_rrule(rule, &timing));
+            if !timing.exdates.is_empty() {
+                let (name, format) = if timing.all_day {
+                    ("EXDATE;VALUE=DATE".to_string(), "%Y%m%d")
+                } else if timing.tz == chrono_tz::UTC {
+                    ("EXDATE".to_string(), "%Y%m%dT%H%M%SZ")
+                } else {
+                    (format!("EXDATE;TZID={}", timing.tz.name()), "%Y%m%dT%H%M%S")
+                };
+                let values: Vec<String> = timing
+                    .exdates
+                    .iter()
+                    .map(|v| v.format(format).to_string())
+                    .collect();
+                events.line(&name, &values.join(","));
+            }
         }
-        let prefix_apply_count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM friend_link_applications
-                 WHERE created_at >= ?1
-                   AND (
-                     ip = ?2 OR ip LIKE ?3
-                   )",
-                params![
-                    now - anti_abuse.apply_rate_limit_window_secs,
-                    ip_prefix,
-                    format!("{}%", ip_prefix)
-                ],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
-        if prefix_apply_count >= anti_abuse.apply_rate_limit_prefix_max {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                Json(ApiMessage {
-                    message: "当前网络段提交过于频繁，请稍后再试".to_string(),
-                }),
-            )
-                .into_response();
+        events.text("SUMMARY", &item.title);
+        if let Some(location) = item.location.as_deref().filter(|v| !v.trim().is_empty()) {
+            events.text("LOCATION", location);
         }
-        let email_domain_count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM friend_link_applications
-                 WHERE created_at >= ?1
-                   AND lower(substr(email, instr(email, '@') + 1)) = ?2",
-                params![
-                    now - anti_abuse.apply_rate_limit_window_secs,
-                    email_domain.to_lowercase()
-                ],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
-        if email_domain_count >= anti_abuse.apply_rate_limit_email_domain_max {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                Json(ApiMessage {
-                    message: "该邮箱域名提交过于频繁，请稍后再试".to_string(),
-                }),
-            )
-                .into_response();
+        if let Some(note) = item.note.as_deref().filter(|v| !v.trim().is_empty()) {
+            events.text("DESCRIPTION", note);
         }
-        let site_host_count: i64 = {
-            let mut stmt = match conn.prepare(
-                "SELECT site_url FROM friend_link_applications WHERE created_at >= ?1",
-            ) {
-                Ok(v) => v,
-                Err(_) => {
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            };
-            let rows = match stmt.query_map(params![now - anti_abuse.apply_rate_limit_window_secs], |row| {
-                row.get::<_, String>(0)
-            }) {
-                Ok(v) => v,
-                Err(_) => {
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            };
-            rows.filter_map(Result::ok)
-                .filter(|existing_url| {
-                    Url::parse(existing_url)
-                        .ok()
-                        .and_then(|u| u.host_str().map(normalize_host))
-                        .map(|host| host == site_host || host.ends_with(&format!(".{}", site_host)))
-                        .unwrap_or(false)
-                })
-                .count() as i64
-        };
-        if site_host_count >= anti_abuse.apply_rate_limit_site_host_max {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                Json(ApiMessage {
-                    message: "该站点提交过于频繁，请稍后再试".to_string(),
-                }),
-            )
-                .into_response();
+        let tags: Vec<String> = item
+            .tag
+            .as_deref()
+            .unwrap_or("")
+            .split([',', '，'])
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(ics::escape_text)
+            .collect();
+        if !tags.is_empty() {
+            events.line("CATEGORIES", &tags.join(","));
         }
-        let pending_count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM friend_link_applications
-                 WHERE site_url = ?1
-                   AND (
-                     status = 'pending'
-                     OR (status = 'verify_pending' AND (verify_deadline IS NULL OR verify_deadline >= ?2))
-                   )",
-                params![site_url, now],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
-        if pending_count > 0 {
-            return (
-                StatusCode::CONFLICT,
-                Json(ApiMessage {
-                    message: "该站点已有待处理申请，请勿重复提交".to_string(),
-                }),
-            )
-                .into_response();
+        for offset in &item.reminders {
+            events.line("BEGIN", "VALARM");
+            events.line("ACTION", "DISPLAY");
+            events.text("DESCRIPTION", &item.title);
+            events.line("TRIGGER", &format!("-PT{}M", offset));
+            events.line("END", "VALARM");
         }
-        let linked_count: i64 = conn
+        events.line("END", "VEVENT");
+    }
+
+    let this_year = Utc
+        .timestamp_opt(now, 0)
+        .single()
+        .map(|v| v.year())
+        .unwrap_or(1970);
+    let mut cal = ics::Writer::default();
+    cal.line("BEGIN", "VCALENDAR");
+    cal.line("VERSION", "2.0");
+    cal.line("PRODID", "-//Personal Homepage//status-backend//ZH");
+    cal.line("CALSCALE", "GREGORIAN");
+    cal.line("METHOD", "PUBLISH");
+    cal.text("X-WR-CALNAME", "Meow Schedule");
+    cal.line("X-WR-TIMEZONE", default_tz.name());
+    cal.line("REFRESH-INTERVAL;VALUE=DURATION", "PT1H");
+    cal.line("X-PUBLISHED-TTL", "PT1H");
+    for (tz, from_year) in zones {
+        ics::write_vtimezone(&mut cal, tz, from_year.min(this_year), this_year + 5);
+    }
+    cal.extend(events);
+    cal.line("END", "VCALENDAR");
+    cal.finish()
+}
+
+/// RFC 5545 wants UNTIL as a DATE for all-day events and as UTC otherwise.
+fn ics_rrule(rule: &rrule::Rule, timing: &ScheduleTiming) -> String {
+    let mut rule = rule.clone();
+    rule.until = match rule.until {
+        Some(rrule::Until::Instant(ts)) if timing.all_day => Utc
+            .timestamp_opt(ts, 0)
+            .single()
+            .map(|v| rrule::Until::Date(v.with_timezone(&timing.tz).date_naive())),
+        Some(rrule::Until::Date(date)) if !timing.all_day => date
+            .and_hms_opt(23, 59, 59)
+            .and_then(|v| local_to_ts(timing.tz, v))
+            .map(rrule::Until::Instant),
+        other => other,
+    };
+    rule.to_string()
+}
+
+/// Best-effort reading of a free-text `time` such as `2026-10-20 19:00` or
+/// `2026/10/20`, interpreted in the default schedule time zone.
+fn legacy_schedule_timing(time: &str, tz: chrono_tz::Tz) -> Option<ScheduleTiming> {
+    let value = time.trim().replace('/', "-");
+    let (start, all_day) = match parse_schedule_local(&value, false) {
+        Some(start) => (start, false),
+        None => (parse_schedule_local(&value, true)?, true),
+    };
+    Some(ScheduleTiming {
+        start,
+        end: None,
+        tz,
+        all_day,
+        rule: None,
+        exdates: Vec::new(),
+    })
+}
+
+async fn schedule_import(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ScheduleImportQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let mode = query
+        .mode
+        .as_deref()
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "merge".to_string());
+    if mode != "merge" && mode != "replace" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "mode 需为 merge 或 replace".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let text = String::from_utf8_lossy(&body);
+    let events = ics::parse_components(&text, "VEVENT");
+    if events.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "未找到 VEVENT，请上传 .ics 文件内容".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let source = normalize_optional(query.source, 64)
+        .or_else(|| {
+            ics::parse_components(&text, "VCALENDAR")
+                .first()
+                .and_then(|cal| normalize_optional(cal.text("X-WR-CALNAME"), 64))
+        })
+        .unwrap_or_else(|| "ics".to_string());
+    let dry_run = query.dry_run.unwrap_or(false);
+    let now = now_ts();
+    let (items, skipped) = schedule_items_from_ics(&events, &source, now, state.schedule_timezone);
+
+    let mut conn = state.db.lock().unwrap();
+    let existing: HashMap<String, Option<String>> = conn
+        .prepare("SELECT id, source FROM schedule_items")
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+    let imported: HashSet<&str> = items.iter().map(|item| item.id.as_str()).collect();
+    let removed: Vec<String> = if mode == "replace" {
+        existing
+            .iter()
+            .filter(|(id, item_source)| {
+                item_source.as_deref() == Some(source.as_str()) && !imported.contains(id.as_str())
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let created = items
+        .iter()
+        .filter(|item| !existing.contains_key(&item.id))
+        .count();
+
+    if !dry_run {
+        let next_order: i64 = conn
             .query_row(
-                "SELECT COUNT(*) FROM friend_links WHERE url = ?1",
-                params![site_url],
+                "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM schedule_items",
+                [],
                 |row| row.get(0),
             )
             .unwrap_or(0);
-        if linked_count > 0 {
-            return (
-                StatusCode::CONFLICT,
-                Json(ApiMessage {
-                    message: "该站点已在友链列表中".to_string(),
-                }),
-            )
-                .into_response();
+        let tx = match conn.transaction() {
+            Ok(tx) => tx,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        for (idx, item) in items.iter().enumerate() {
+            if tx
+                .execute(
+                    "INSERT INTO schedule_items (
+                        id, title, time, note, location, tag, start_at, end_at, timezone, all_day, rrule,
+                        exdates, source, sort_order, updated_at
+                     )
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+                     ON CONFLICT(id) DO UPDATE SET
+                       title = excluded.title,
+                       time = excluded.time,
+                       note = excluded.note,
+                       location = excluded.location,
+                       tag = excluded.tag,
+                       start_at = excluded.start_at,
+                       end_at = excluded.end_at,
+                       timezone = excluded.timezone,
+                       all_day = excluded.all_day,
+                       rrule = excluded.rrule,
+                       exdates = excluded.exdates,
+                       source = excluded.source,
+                       updated_at = excluded.updated_at,
+                       version = schedule_items.version + 1",
+                    params![
+                        item.id,
+                        item.title,
+                        item.time,
+                        item.note,
+                        item.location,
+                        item.tag,
+                        item.start_at,
+                        item.end_at,
+                        item.timezone,
+                        item.all_day as i64,
+                        item.rrule,
+                        schedule_exdates_json(&item.exdates),
+                        item.source,
+                        next_order + idx as i64,
+                        now
+                    ],
+                )
+                .is_err()
+            {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
         }
-
-        let inserted = conn.execute(
-            "INSERT INTO friend_link_applications (
-                site_name, site_url, avatar_url, description, email, note,
-                status, ip, user_agent, verify_token, verify_deadline, created_at, updated_at
-             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'verify_pending', ?7, ?8, ?9, ?10, ?11, ?12)",
-            params![
-                site_name,
-                site_url,
-                avatar_url,
-                description,
-                email,
-                note,
-                ip,
-                user_agent,
-                verify_token,
-                verify_deadline,
-                now,
-                now
-            ],
-        );
-        if inserted.is_err() {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiMessage {
-                    message: "提交失败，请稍后重试".to_string(),
-                }),
-            )
-                .into_response();
+        for id in &removed {
+            if tx
+                .execute("DELETE FROM schedule_items WHERE id = ?1", params![id])
+                .is_err()
+            {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
         }
-        application_id = conn.last_insert_rowid();
-    }
+        if tx.commit().is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        state.cache.invalidate("schedule");
+        state.hub.publish_changes(&conn, &state.presence);
+    }
+    drop(conn);
+
+    Json(ScheduleImportResult {
+        dry_run,
+        mode,
+        source,
+        created,
+        updated: items.len() - created,
+        removed: removed.len(),
+        skipped,
+        items: items.iter().map(schedule_with_timing).collect(),
+    })
+    .into_response()
+}
 
-    (
-        StatusCode::CREATED,
-        Json(LinkApplyResponse {
-            message: format!(
-                "友链申请已提交，编号 #{}。请先完成验证（HTTP 或 邮箱 任一项）后进入审核队列。",
-                application_id
-            ),
-            application_id,
-            verify_status: "verify_pending".to_string(),
-            http_verify_path: "/.well-known/meow-links.txt".to_string(),
-            verify_token: verify_token.clone(),
-            verify_deadline,
-        }),
-    )
-        .into_response()
-}
-
-async fn links_verify_http(
+async fn schedule_reminders_list(
     State(state): State<AppState>,
-    Json(payload): Json<LinkVerifyHttpPayload>,
+    headers: HeaderMap,
+    Query(query): Query<ScheduleReminderQuery>,
 ) -> impl IntoResponse {
-    let anti_abuse = {
-        let conn = state.db.lock().unwrap();
-        resolve_anti_abuse_config(&conn, &state.anti_abuse)
-    };
-    let now = now_ts();
-    let (site_url, verify_token, status, verify_deadline) = {
-        let conn = state.db.lock().unwrap();
-        let row = conn.query_row(
-            "SELECT site_url, verify_token, status, verify_deadline
-             FROM friend_link_applications WHERE id = ?1",
-            params![payload.application_id],
-            |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, Option<String>>(1)?,
-                    row.get::<_, String>(2)?,
-                    row.get::<_, Option<i64>>(3)?,
-                ))
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let item_id = normalize_optional(query.item_id, 128);
+    let status = normalize_optional(query.status, 16);
+    let conn = state.db.lock().unwrap();
+    let items = conn
+        .prepare(
+            "SELECT item_id, title, occurrence_start, offset_minutes, status, channels,
+                    last_error, created_at, updated_at
+             FROM schedule_reminders
+             WHERE (?1 IS NULL OR item_id = ?1) AND (?2 IS NULL OR status = ?2)
+             ORDER BY created_at DESC, occurrence_start DESC
+             LIMIT ?3",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![item_id, status, limit], |row| {
+                Ok(ScheduleReminderItem {
+                    item_id: row.get(0)?,
+                    title: row.get(1)?,
+                    occurrence_start: row.get(2)?,
+                    offset_minutes: row.get(3)?,
+                    status: row.get(4)?,
+                    channels: row
+                        .get::<_, Option<String>>(5)?
+                        .map(|v| v.split(',').map(str::to_string).collect())
+                        .unwrap_or_default(),
+                    last_error: row.get(6)?,
+                    created_at: row.get(7)?,
+                    updated_at: row.get(8)?,
+                })
+            })
+            .map(|rows| rows.filter_map(Result::ok).collect::<Vec<_>>())
+        })
+        .unwrap_or_default();
+    Json(items).into_response()
+}
+
+/// Turns VEVENTs into validated schedule items. Instances overridden via
+/// RECURRENCE-ID become standalone items and are excluded from their series.
+fn schedule_items_from_ics(
+    events: &[ics::Component],
+    source: &str,
+    now: i64,
+    default_tz: chrono_tz::Tz,
+) -> (Vec<ScheduleItem>, Vec<ScheduleImportSkip>) {
+    let mut inputs: Vec<(String, String, ScheduleItemInput)> = Vec::new();
+    let mut overrides = Vec::new();
+    let mut skipped = Vec::new();
+    for event in events {
+        let uid = event.text("UID").unwrap_or_default();
+        let summary = event.text("SUMMARY").unwrap_or_default();
+        let skip = |reason: String| ScheduleImportSkip {
+            uid: uid.clone(),
+            summary: summary.clone(),
+            reason,
+        };
+        let recurrence = match event.get("RECURRENCE-ID") {
+            Some(prop) => match ics_datetime(prop, &prop.value, default_tz) {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    skipped.push(skip(err));
+                    continue;
+                }
             },
-        );
-        match row {
-            Ok(v) => v,
-            Err(_) => {
-                return (
-                    StatusCode::NOT_FOUND,
-                    Json(ApiMessage {
-                        message: "申请不存在".to_string(),
-                    }),
-                )
-                    .into_response()
+            None => None,
+        };
+        if let Some((local, tz, _)) = recurrence {
+            overrides.push((uid.clone(), local, tz));
+        }
+        if event
+            .text("STATUS")
+            .is_some_and(|v| v.eq_ignore_ascii_case("CANCELLED"))
+        {
+            if recurrence.is_none() {
+                skipped.push(skip("已取消".to_string()));
             }
+            continue;
+        }
+        let recurrence_key =
+            recurrence.map(|(local, _, _)| local.format("%Y%m%dT%H%M%S").to_string());
+        match ics_event_input(event, &uid, recurrence_key.as_deref(), source, default_tz) {
+            Ok(input) => inputs.push((uid, summary, input)),
+            Err(err) => skipped.push(skip(err)),
         }
-    };
-    if status == "pending" {
-        return (
-            StatusCode::OK,
-            Json(ApiMessage {
-                message: "该申请已完成验证并进入审核队列".to_string(),
-            }),
-        )
-            .into_response();
     }
-    if status != "verify_pending" {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "该申请当前不可验证".to_string(),
-            }),
-        )
-            .into_response();
+    for (uid, local, tz) in overrides {
+        let Some((_, _, master)) = inputs
+            .iter_mut()
+            .find(|(id, _, input)| !uid.is_empty() && *id == uid && input.rrule.is_some())
+        else {
+            continue;
+        };
+        let all_day = master.all_day.unwrap_or(false);
+        let master_tz = master
+            .timezone
+            .as_deref()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default_tz);
+        let local = if all_day {
+            Some(local)
+        } else {
+            relocalize(local, tz, master_tz)
+        };
+        if let Some(local) = local {
+            master
+                .exdates
+                .get_or_insert_with(Vec::new)
+                .push(format_schedule_local(local, all_day));
+        }
     }
-    if verify_deadline.unwrap_or(0) < now {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "验证已过期，请重新提交申请".to_string(),
+
+    let mut items = Vec::with_capacity(inputs.len());
+    let mut seen = HashSet::new();
+    for (idx, (uid, summary, input)) in inputs.into_iter().enumerate() {
+        match validate_schedule_item(input, idx, now, default_tz) {
+            Ok(item) if seen.insert(item.id.clone()) => items.push(item),
+            Ok(_) => skipped.push(ScheduleImportSkip {
+                uid,
+                summary,
+                reason: "UID 重复".to_string(),
             }),
-        )
-            .into_response();
-    }
-    let Some(token) = verify_token else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "该申请缺少验证 token，请重新提交".to_string(),
+            Err(reason) => skipped.push(ScheduleImportSkip {
+                uid,
+                summary,
+                reason,
             }),
-        )
-            .into_response();
+        }
+    }
+    (items, skipped)
+}
+
+fn ics_event_input(
+    event: &ics::Component,
+    uid: &str,
+    recurrence_key: Option<&str>,
+    source: &str,
+    default_tz: chrono_tz::Tz,
+) -> Result<ScheduleItemInput, String> {
+    let dtstart = event
+        .get("DTSTART")
+        .ok_or_else(|| "缺少 DTSTART".to_string())?;
+    let (start, tz, all_day) = ics_datetime(dtstart, &dtstart.value, default_tz)?;
+    let end = if let Some(prop) = event.get("DTEND") {
+        let (end, end_tz, _) = ics_datetime(prop, &prop.value, default_tz)?;
+        if all_day {
+            Some(end - chrono::Duration::days(1))
+        } else {
+            Some(relocalize(end, end_tz, tz).ok_or_else(|| "DTEND 无效".to_string())?)
+        }
+    } else if let Some(prop) = event.get("DURATION") {
+        let duration = parse_ics_duration(&prop.value)
+            .ok_or_else(|| format!("DURATION 无效：{}", prop.value))?;
+        if all_day {
+            Some(start + duration - chrono::Duration::days(1))
+        } else {
+            Some(start + duration)
+        }
+    } else {
+        None
     };
-    let ok = verify_http_token_present(&site_url, &token).await;
-    if !ok {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "未检测到验证 token，请确认已配置：/.well-known/meow-links.txt 或首页 meta 或 DNS TXT".to_string(),
-            }),
-        )
-            .into_response();
+    let end = end.filter(|end| *end > start || (!all_day && *end == start));
+
+    let mut exdates = Vec::new();
+    for prop in event.all("EXDATE") {
+        for value in prop.value.split(',') {
+            let (local, ex_tz, _) = ics_datetime(prop, value, default_tz)?;
+            let local = if all_day {
+                local
+            } else {
+                relocalize(local, ex_tz, tz).unwrap_or(local)
+            };
+            exdates.push(format_schedule_local(local, all_day));
+        }
     }
+    let tags: Vec<String> = event
+        .all("CATEGORIES")
+        .flat_map(|prop| ics::split_text_list(&prop.value))
+        .collect();
+    let hash_input = if uid.is_empty() {
+        format!(
+            "{}\n{}",
+            event.text("SUMMARY").unwrap_or_default(),
+            dtstart.value
+        )
+    } else {
+        uid.to_string()
+    };
+    let digest = Sha256::digest(format!("{}\n{}", hash_input, recurrence_key.unwrap_or("")));
+    let id: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+
+    Ok(ScheduleItemInput {
+        id: Some(format!("ics-{}", id)),
+        title: normalize_optional(event.text("SUMMARY"), 200)
+            .unwrap_or_else(|| "(无标题)".to_string()),
+        time: None,
+        note: normalize_optional(event.text("DESCRIPTION"), 2000),
+        location: normalize_optional(event.text("LOCATION"), 200),
+        tag: normalize_optional(Some(tags.join(",")), 120),
+        start_at: Some(format_schedule_local(start, all_day)),
+        end_at: end.map(|end| format_schedule_local(end, all_day)),
+        timezone: Some(tz.name().to_string()),
+        all_day: Some(all_day),
+        rrule: match recurrence_key {
+            Some(_) => None,
+            None => event.get("RRULE").map(|prop| prop.value.trim().to_string()),
+        },
+        exdates: Some(exdates),
+        source: Some(source.to_string()),
+        status_effect: None,
+        status_text: None,
+        reminders: None,
+        sort_order: None,
+        version: None,
+    })
+}
+
+/// Reads a DATE / DATE-TIME value (one entry of a possibly comma-separated
+/// list). UTC values are moved into the default schedule time zone.
+fn ics_datetime(
+    prop: &ics::Property,
+    value: &str,
+    default_tz: chrono_tz::Tz,
+) -> Result<(chrono::NaiveDateTime, chrono_tz::Tz, bool), String> {
+    let value = value.trim();
+    let invalid = || format!("{} 无效：{}", prop.name, value);
+    let tz = match prop.param("TZID") {
+        Some(id) => ics_timezone(id).ok_or_else(|| format!("未知时区 TZID：{}", id))?,
+        None => default_tz,
+    };
+    if prop
+        .param("VALUE")
+        .is_some_and(|v| v.eq_ignore_ascii_case("DATE"))
+        || value.len() == 8
     {
-        let conn = state.db.lock().unwrap();
-        let _ = conn.execute(
-            "UPDATE friend_link_applications
-             SET verify_http_at = ?1, status = 'pending', updated_at = ?2
-             WHERE id = ?3 AND status = 'verify_pending'",
-            params![now, now, payload.application_id],
-        );
+        let date = chrono::NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+        return Ok((date.and_hms_opt(0, 0, 0).ok_or_else(invalid)?, tz, true));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive =
+            chrono::NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        let local = naive.and_utc().with_timezone(&default_tz).naive_local();
+        return Ok((local, default_tz, false));
+    }
+    let naive =
+        chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+    Ok((naive, tz, false))
+}
+
+/// Accepts IANA names, including prefixed ones such as
+/// `/mozilla.org/20050126_1/Europe/London`.
+fn ics_timezone(id: &str) -> Option<chrono_tz::Tz> {
+    let id = id.trim();
+    std::iter::once(id)
+        .chain(id.match_indices('/').map(|(idx, _)| &id[idx + 1..]))
+        .find_map(|v| v.parse().ok())
+}
+
+fn relocalize(
+    value: chrono::NaiveDateTime,
+    from: chrono_tz::Tz,
+    to: chrono_tz::Tz,
+) -> Option<chrono::NaiveDateTime> {
+    if from == to {
+        return Some(value);
+    }
+    let ts = local_to_ts(from, value)?;
+    Utc.timestamp_opt(ts, 0)
+        .single()
+        .map(|v| v.with_timezone(&to).naive_local())
+}
+
+fn parse_ics_duration(value: &str) -> Option<chrono::Duration> {
+    let value = value.trim();
+    let value = value.strip_prefix('+').unwrap_or(value).strip_prefix('P')?;
+    let mut total = 0i64;
+    let mut number = String::new();
+    let mut in_time = false;
+    for ch in value.chars() {
+        match ch {
+            '0'..='9' => number.push(ch),
+            'T' => in_time = true,
+            _ => {
+                let count: i64 = number.parse().ok()?;
+                number.clear();
+                total += count
+                    * match (ch, in_time) {
+                        ('W', false) => 7 * 86400,
+                        ('D', false) => 86400,
+                        ('H', true) => 3600,
+                        ('M', true) => 60,
+                        ('S', true) => 1,
+                        _ => return None,
+                    };
+            }
+        }
     }
-    let remain_hours = (anti_abuse.verify_window_secs / 3600).max(1);
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: format!("HTTP 验证通过，已进入审核队列（验证窗口 {}h）", remain_hours),
-        }),
-    )
-        .into_response()
+    if !number.is_empty() {
+        return None;
+    }
+    Some(chrono::Duration::seconds(total))
 }
 
-async fn links_verify_email_send(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<LinkVerifyEmailSendPayload>,
-) -> impl IntoResponse {
-    let anti_abuse = {
-        let conn = state.db.lock().unwrap();
-        resolve_anti_abuse_config(&conn, &state.anti_abuse)
-    };
-    let now = now_ts();
-    let (email, status, verify_token, verify_deadline, site_name) = {
-        let conn = state.db.lock().unwrap();
-        let row = conn.query_row(
-            "SELECT email, status, verify_token, verify_deadline, site_name
-             FROM friend_link_applications WHERE id = ?1",
-            params![payload.application_id],
-            |row| {
-                Ok((
-                    row.get::<_, Option<String>>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, Option<String>>(2)?,
-                    row.get::<_, Option<i64>>(3)?,
-                    row.get::<_, String>(4)?,
-                ))
-            },
-        );
-        match row {
-            Ok(v) => v,
-            Err(_) => {
-                return (
-                    StatusCode::NOT_FOUND,
-                    Json(ApiMessage {
-                        message: "申请不存在".to_string(),
-                    }),
-                )
-                    .into_response()
+fn validate_schedule_item(
+    item: ScheduleItemInput,
+    idx: usize,
+    now: i64,
+    default_tz: chrono_tz::Tz,
+) -> Result<ScheduleItem, String> {
+    let title = item.title.trim().to_string();
+    if title.is_empty() {
+        return Err("标题不能为空".to_string());
+    }
+    let time = item.time.unwrap_or_default().trim().to_string();
+    let all_day = item.all_day.unwrap_or(false);
+    let start_at = normalize_optional(item.start_at, 64);
+    let end_at = normalize_optional(item.end_at, 64);
+    let rrule = normalize_optional(item.rrule, 256);
+    let mut timezone = normalize_optional(item.timezone, 64);
+    let exdates = item.exdates.unwrap_or_default();
+    let status_effect = normalize_optional(item.status_effect, 16).map(|v| v.to_lowercase());
+    if let Some(effect) = status_effect.as_deref() {
+        if !SCHEDULE_STATUS_EFFECTS.contains(&effect) {
+            return Err("status_effect 只能是 busy / offline / activity".to_string());
+        }
+        if start_at.is_none() || (end_at.is_none() && !all_day) {
+            return Err(
+                "status_effect 需要填写 start_at 与 end_at（全天日程可省略 end_at）".to_string(),
+            );
+        }
+    }
+    let mut reminders = item.reminders.unwrap_or_default();
+    if !reminders.is_empty() {
+        if start_at.is_none() {
+            return Err("reminders 需要同时填写 start_at".to_string());
+        }
+        if reminders
+            .iter()
+            .any(|v| !(0..=SCHEDULE_REMINDER_MAX_OFFSET_MINUTES).contains(v))
+        {
+            return Err(format!(
+                "reminders 需为 0 到 {} 之间的分钟数",
+                SCHEDULE_REMINDER_MAX_OFFSET_MINUTES
+            ));
+        }
+        reminders.sort_unstable();
+        reminders.dedup();
+        if reminders.len() > 10 {
+            return Err("reminders 最多 10 项".to_string());
+        }
+    }
+
+    let (start_at, end_at, rrule, exdates) = match start_at {
+        None => {
+            if time.is_empty() {
+                return Err("time 与 start_at 至少填写一项".to_string());
+            }
+            if end_at.is_some() || rrule.is_some() || !exdates.is_empty() {
+                return Err("end_at / rrule / exdates 需要同时填写 start_at".to_string());
+            }
+            timezone = None;
+            (None, None, None, Vec::new())
+        }
+        Some(raw) => {
+            let start = parse_schedule_local(&raw, all_day)
+                .ok_or_else(|| format!("start_at 格式无效：{}", raw))?;
+            let end = match end_at {
+                Some(raw) => Some(
+                    parse_schedule_local(&raw, all_day)
+                        .ok_or_else(|| format!("end_at 格式无效：{}", raw))?,
+                ),
+                None => None,
+            };
+            if end.is_some_and(|end| end < start) {
+                return Err("end_at 不能早于 start_at".to_string());
+            }
+            let tz = match timezone.as_deref() {
+                Some(name) => name
+                    .parse::<chrono_tz::Tz>()
+                    .map_err(|_| format!("未知时区：{}", name))?,
+                None => default_tz,
+            };
+            timezone = Some(tz.name().to_string());
+            let rrule = match rrule {
+                Some(raw) => Some(rrule::Rule::parse(&raw)?.to_string()),
+                None => None,
+            };
+            let mut parsed = Vec::with_capacity(exdates.len());
+            for raw in exdates.iter().map(|v| v.trim()).filter(|v| !v.is_empty()) {
+                let date = parse_schedule_local(raw, all_day)
+                    .ok_or_else(|| format!("exdates 格式无效：{}", raw))?;
+                parsed.push(format_schedule_local(date, all_day));
+            }
+            parsed.sort();
+            parsed.dedup();
+            if parsed.len() > 500 {
+                return Err("exdates 最多 500 项".to_string());
             }
+            (
+                Some(format_schedule_local(start, all_day)),
+                end.map(|end| format_schedule_local(end, all_day)),
+                rrule,
+                parsed,
+            )
         }
     };
-    if status == "pending" {
-        return (
-            StatusCode::OK,
-            Json(ApiMessage {
-                message: "该申请已完成验证并进入审核队列".to_string(),
-            }),
-        )
-            .into_response();
+
+    Ok(ScheduleItem {
+        id: normalize_optional(item.id, 128).unwrap_or_else(|| format!("schedule-{}-{}", now, idx)),
+        title,
+        time,
+        note: item.note,
+        location: item.location,
+        tag: item.tag,
+        all_day: all_day && start_at.is_some(),
+        start_at,
+        end_at,
+        timezone,
+        rrule,
+        exdates,
+        source: normalize_optional(item.source, 64),
+        status_text: status_effect
+            .as_ref()
+            .and_then(|_| normalize_optional(item.status_text, 120)),
+        status_effect,
+        reminders,
+        start_ts: None,
+        end_ts: None,
+        recurrence_id: None,
+        sort_order: item.sort_order.unwrap_or(idx as i64),
+        updated_at: now,
+        version: 1,
+    })
+}
+
+fn schedule_exdates_json(exdates: &[String]) -> Option<String> {
+    if exdates.is_empty() {
+        None
+    } else {
+        serde_json::to_string(exdates).ok()
     }
-    if status != "verify_pending" {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "该申请当前不可发送验证邮件".to_string(),
-            }),
-        )
-            .into_response();
+}
+
+fn parse_schedule_local(value: &str, all_day: bool) -> Option<chrono::NaiveDateTime> {
+    let value = value.trim();
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return if all_day {
+            date.and_hms_opt(0, 0, 0)
+        } else {
+            None
+        };
     }
-    if verify_deadline.unwrap_or(0) < now {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "验证已过期，请重新提交申请".to_string(),
-            }),
-        )
-            .into_response();
+    let parsed = [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|fmt| chrono::NaiveDateTime::parse_from_str(value, fmt).ok())?;
+    if all_day {
+        parsed.date().and_hms_opt(0, 0, 0)
+    } else {
+        Some(parsed)
     }
-    let Some(email) = email else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "申请未填写邮箱，无法发送验证邮件".to_string(),
-            }),
-        )
-            .into_response();
+}
+
+fn format_schedule_local(value: chrono::NaiveDateTime, all_day: bool) -> String {
+    if all_day {
+        value.format("%Y-%m-%d").to_string()
+    } else {
+        value.format("%Y-%m-%dT%H:%M").to_string()
+    }
+}
+
+fn parse_schedule_bound(value: &str, tz: chrono_tz::Tz, now: i64) -> Option<i64> {
+    if matches!(value, "1" | "true" | "now") {
+        return Some(now);
+    }
+    if let Ok(ts) = value.parse::<i64>() {
+        return Some(ts);
+    }
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(dt.timestamp());
+    }
+    let local = parse_schedule_local(value, false).or_else(|| parse_schedule_local(value, true))?;
+    local_to_ts(tz, local)
+}
+
+fn schedule_timing(item: &ScheduleItem) -> Option<ScheduleTiming> {
+    let start = parse_schedule_local(item.start_at.as_deref()?, item.all_day)?;
+    let end = item
+        .end_at
+        .as_deref()
+        .and_then(|v| parse_schedule_local(v, item.all_day));
+    let tz = item
+        .timezone
+        .as_deref()
+        .and_then(|v| v.parse::<chrono_tz::Tz>().ok())
+        .unwrap_or(chrono_tz::UTC);
+    let rule = item
+        .rrule
+        .as_deref()
+        .and_then(|v| rrule::Rule::parse(v).ok());
+    let exdates = item
+        .exdates
+        .iter()
+        .filter_map(|v| parse_schedule_local(v, item.all_day))
+        .collect();
+    Some(ScheduleTiming {
+        start,
+        end,
+        tz,
+        all_day: item.all_day,
+        rule,
+        exdates,
+    })
+}
+
+/// Fills `start_ts` / `end_ts` (and, in expanded views, a blank `time`) for
+/// the occurrence starting at `start`, keeping the item's duration. All-day
+/// `end_at` is the last day inclusive; `end_ts` is the exclusive midnight after it.
+fn schedule_occurrence(
+    item: &ScheduleItem,
+    timing: &ScheduleTiming,
+    start: chrono::NaiveDateTime,
+    fill_time: bool,
+) -> Option<ScheduleItem> {
+    let end = timing.end.map(|end| start + (end - timing.start));
+    let end_exclusive = if timing.all_day {
+        Some(end.unwrap_or(start) + chrono::Duration::days(1))
+    } else {
+        end
     };
-    let Some(token) = verify_token else {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "该申请缺少验证 token，请重新提交".to_string(),
-            }),
-        )
-            .into_response();
+    let mut out = item.clone();
+    out.start_at = Some(format_schedule_local(start, timing.all_day));
+    out.end_at = end.map(|end| format_schedule_local(end, timing.all_day));
+    out.start_ts = Some(local_to_ts(timing.tz, start)?);
+    out.end_ts = match end_exclusive {
+        Some(end) => Some(local_to_ts(timing.tz, end)?),
+        None => None,
     };
-    if token != payload.verify_token.trim() {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(ApiMessage {
-                message: "验证 token 不匹配".to_string(),
-            }),
-        )
-            .into_response();
+    if fill_time && out.time.is_empty() {
+        out.time = schedule_time_label(start, end, timing.all_day);
     }
-    let ip = client_ip(&headers).or_else(|| Some("unknown".to_string()));
-    let ip_value = ip.as_deref().unwrap_or("unknown");
-    if let Some(captcha_cfg) = anti_abuse.captcha.as_ref() {
-        let captcha_token = normalize_optional(payload.captcha_token, 4096);
-        let Some(captcha_token) = captcha_token.as_deref() else {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiMessage {
-                    message: "请先完成人机验证".to_string(),
-                }),
-            )
-                .into_response();
+    Some(out)
+}
+
+fn schedule_with_timing(item: &ScheduleItem) -> ScheduleItem {
+    schedule_timing(item)
+        .and_then(|timing| schedule_occurrence(item, &timing, timing.start, false))
+        .unwrap_or_else(|| item.clone())
+}
+
+fn schedule_time_label(
+    start: chrono::NaiveDateTime,
+    end: Option<chrono::NaiveDateTime>,
+    all_day: bool,
+) -> String {
+    if all_day {
+        let first = start.format("%Y-%m-%d").to_string();
+        return match end.filter(|end| end.date() > start.date()) {
+            Some(end) => format!("{} – {}", first, end.format("%Y-%m-%d")),
+            None => first,
         };
-        let verified = verify_captcha(captcha_cfg, captcha_token, ip.as_deref()).await;
-        if !verified {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ApiMessage {
-                    message: "人机验证失败，请重试".to_string(),
-                }),
-            )
-                .into_response();
+    }
+    let first = start.format("%Y-%m-%d %H:%M").to_string();
+    match end.filter(|end| *end > start) {
+        Some(end) if end.date() == start.date() => format!("{}–{}", first, end.format("%H:%M")),
+        Some(end) => format!("{} – {}", first, end.format("%Y-%m-%d %H:%M")),
+        None => first,
+    }
+}
+
+/// Occurrences overlapping `[from, to)` in chronological order. Events
+/// without `end_at` count as instants. Free-text items have no date and are
+/// appended after the dated ones when `include_undated` is set.
+fn expand_schedule(
+    items: &[ScheduleItem],
+    from: i64,
+    to: i64,
+    include_undated: bool,
+) -> Vec<ScheduleItem> {
+    let overlaps = |item: &ScheduleItem| {
+        let start = item.start_ts.unwrap_or(0);
+        match item.end_ts {
+            Some(end) if end > start => start < to && end > from,
+            _ => start >= from && start < to,
+        }
+    };
+    let mut dated = Vec::new();
+    let mut undated = Vec::new();
+    for item in items {
+        let Some(timing) = schedule_timing(item) else {
+            if include_undated {
+                undated.push(item.clone());
+            }
+            continue;
+        };
+        let Some(rule) = timing.rule.as_ref() else {
+            if let Some(occurrence) = schedule_occurrence(item, &timing, timing.start, true) {
+                if overlaps(&occurrence) {
+                    dated.push(occurrence);
+                }
+            }
+            continue;
+        };
+        let dates = match rule.count {
+            Some(_) => rule.dates(timing.start.date()),
+            None => {
+                // Skip straight to occurrences that can still overlap `from`;
+                // the extra day absorbs UTC offsets and all-day end padding.
+                let span = timing
+                    .end
+                    .map_or(chrono::Duration::zero(), |end| end - timing.start)
+                    .max(chrono::Duration::zero());
+                let first = chrono::DateTime::from_timestamp(from, 0)
+                    .map(|v| v.with_timezone(&timing.tz).naive_local() - span)
+                    .map(|v| v.date() - chrono::Duration::days(1))
+                    .unwrap_or(timing.start.date());
+                rule.dates_from(timing.start.date(), first)
+            }
+        };
+        for (idx, date) in dates.enumerate() {
+            if rule.count.is_some_and(|count| idx as u32 >= count)
+                || dated.len() >= SCHEDULE_MAX_OCCURRENCES
+            {
+                break;
+            }
+            if let Some(rrule::Until::Date(until)) = rule.until {
+                if date > until {
+                    break;
+                }
+            }
+            let start = date.and_time(timing.start.time());
+            if timing.exdates.contains(&start) {
+                continue;
+            }
+            let Some(mut occurrence) = schedule_occurrence(item, &timing, start, true) else {
+                continue;
+            };
+            let start_ts = occurrence.start_ts.unwrap_or(0);
+            if let Some(rrule::Until::Instant(until)) = rule.until {
+                if start_ts > until {
+                    break;
+                }
+            }
+            if start_ts >= to {
+                break;
+            }
+            if overlaps(&occurrence) {
+                occurrence.recurrence_id = Some(format_schedule_local(start, timing.all_day));
+                dated.push(occurrence);
+            }
         }
     }
-    {
-        let conn = state.db.lock().unwrap();
-        let recent_count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM friend_link_verify_email_log
-                 WHERE ip = ?1 AND created_at >= ?2",
-                params![
-                    ip_value,
-                    now - anti_abuse.verify_email_rate_limit_window_secs
-                ],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
-        if recent_count >= anti_abuse.verify_email_rate_limit_max {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                Json(ApiMessage {
-                    message: "验证邮件发送过于频繁，请稍后再试".to_string(),
-                }),
-            )
-                .into_response();
+    dated.sort_by_key(|item| (item.start_ts, item.sort_order));
+    dated.extend(undated);
+    dated
+}
+
+async fn blog_list(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let snapshot = match state.cache.get("blog") {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            let list = load_blog_summaries(&conn);
+            let last_modified = list.iter().map(|item| item.updated_at).max().unwrap_or(0);
+            state.cache.store("blog", &list, last_modified)
         }
-        let app_recent_count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM friend_link_verify_email_log
-                 WHERE application_id = ?1 AND created_at >= ?2",
-                params![
-                    payload.application_id,
-                    now - anti_abuse.verify_email_rate_limit_window_secs
-                ],
-                |row| row.get(0),
-            )
-            .unwrap_or(0);
-        if app_recent_count >= anti_abuse.verify_email_rate_limit_app_max {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                Json(ApiMessage {
-                    message: "该申请发送次数已达上限，请稍后再试".to_string(),
-                }),
+    };
+    cache::respond(&headers, &snapshot, &state.content_cache_control)
+}
+
+fn load_blog_summaries(conn: &Connection) -> Vec<BlogPostSummary> {
+    let mut stmt = match conn.prepare(
+        "SELECT slug, title, date, tag, excerpt, sort_order, updated_at, version
+         FROM blog_posts
+         ORDER BY sort_order ASC, date DESC, updated_at DESC",
+    ) {
+        Ok(stmt) => stmt,
+        Err(_) => return Vec::new(),
+    };
+
+    let rows = match stmt.query_map([], |row| {
+        Ok(BlogPostSummary {
+            slug: row.get(0)?,
+            title: row.get(1)?,
+            date: row.get(2)?,
+            tag: row.get(3)?,
+            excerpt: row.get(4)?,
+            sort_order: row.get(5)?,
+            updated_at: row.get(6)?,
+            version: row.get(7)?,
+        })
+    }) {
+        Ok(rows) => rows,
+        Err(_) => return Vec::new(),
+    };
+
+    rows.filter_map(Result::ok).collect()
+}
+
+async fn get_manual_status(State(state): State<AppState>) -> impl IntoResponse {
+    let conn = state.db.lock().unwrap();
+    Json(build_manual_status(&conn, now_ts()))
+}
+
+async fn set_manual_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ManualStatusPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let mut quiet_hours = Vec::new();
+    if let Some(items) = payload.quiet_hours.as_ref() {
+        for item in items {
+            match validate_quiet_hours(item) {
+                Ok(rule) => quiet_hours.push(rule),
+                Err(message) => {
+                    return (StatusCode::BAD_REQUEST, Json(ApiMessage { message })).into_response();
+                }
+            }
+        }
+    }
+    let mut conn = state.db.lock().unwrap();
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    if let Some(enabled) = payload.enabled {
+        if tx
+            .execute(
+                "INSERT INTO status_control (id, global_manual_offline, updated_at)
+                 VALUES (1, ?1, ?2)
+                 ON CONFLICT(id) DO UPDATE SET
+                   global_manual_offline = excluded.global_manual_offline,
+                   updated_at = excluded.updated_at",
+                params![enabled as i32, now],
             )
-                .into_response();
+            .is_err()
+        {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
-        let last_sent: Option<i64> = conn
-            .query_row(
-                "SELECT MAX(created_at) FROM friend_link_verify_email_log
-                 WHERE application_id = ?1",
-                params![payload.application_id],
-                |row| row.get(0),
+    }
+    if let Some(until) = payload.offline_until {
+        let until = if until > now { Some(until) } else { None };
+        if tx
+            .execute(
+                "UPDATE status_control SET offline_until = ?1, updated_at = ?2 WHERE id = 1",
+                params![until, now],
             )
-            .unwrap_or(None);
-        if let Some(last_sent) = last_sent {
-            if now.saturating_sub(last_sent) < anti_abuse.verify_email_cooldown_secs {
-                return (
-                    StatusCode::TOO_MANY_REQUESTS,
-                    Json(ApiMessage {
-                        message: "发送过于频繁，请稍后再试".to_string(),
-                    }),
+            .is_err()
+        {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    if payload.quiet_hours.is_some() {
+        if tx.execute("DELETE FROM status_quiet_hours", []).is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        for rule in &quiet_hours {
+            let weekdays = rule
+                .weekdays
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            if tx
+                .execute(
+                    "INSERT INTO status_quiet_hours
+                     (label, weekdays, start_time, end_time, timezone, enabled, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        rule.label,
+                        weekdays,
+                        rule.start,
+                        rule.end,
+                        rule.timezone,
+                        rule.enabled as i32,
+                        now
+                    ],
                 )
-                    .into_response();
+                .is_err()
+            {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         }
     }
-    let new_token = generate_verify_token();
-    {
-        let conn = state.db.lock().unwrap();
-        let updated = conn
-            .execute(
-                "UPDATE friend_link_applications
-                 SET verify_token = ?1, updated_at = ?2
-                 WHERE id = ?3 AND status = 'verify_pending' AND verify_token = ?4",
-                params![new_token, now, payload.application_id, token],
-            )
-            .unwrap_or(0);
-        if updated == 0 {
+    if tx.commit().is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    state.hub.publish_changes(&conn, &state.presence);
+    (StatusCode::OK, Json(build_manual_status(&conn, now))).into_response()
+}
+
+async fn get_status_activity(State(state): State<AppState>) -> impl IntoResponse {
+    let conn = state.db.lock().unwrap();
+    Json(load_current_activity(&conn, now_ts()))
+}
+
+async fn set_status_activity(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<StatusActivityPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let text = normalize_optional(payload.text, 140);
+    let emoji = normalize_optional(payload.emoji, 16);
+    let conn = state.db.lock().unwrap();
+    if text.is_none() && emoji.is_none() {
+        let _ = conn.execute("DELETE FROM status_activity WHERE id = 1", []);
+        state.hub.publish_changes(&conn, &state.presence);
+        return Json(None::<StatusActivity>).into_response();
+    }
+    let link = normalize_optional(payload.link, 512);
+    if let Some(link) = link.as_deref() {
+        if !is_valid_http_url(link) {
             return (
-                StatusCode::CONFLICT,
+                StatusCode::BAD_REQUEST,
                 Json(ApiMessage {
-                    message: "验证 token 已更新，请刷新后重试".to_string(),
+                    message: "链接必须是 http/https 地址".to_string(),
                 }),
             )
                 .into_response();
         }
     }
-    let notify_cfg = {
-        let conn = state.db.lock().unwrap();
-        state.notifier.runtime_config(&conn)
-    };
-    let verify_url = anti_abuse
-        .public_base_url
-        .as_deref()
-        .map(|base| format!("{}/links/verify/email?token={}", base.trim_end_matches('/'), new_token))
-        .unwrap_or_else(|| format!("/links/verify/email?token={}", new_token));
-    let plain = format!(
-        "站点：{}\n请点击链接完成邮箱验证（完成后进入审核队列）：\n{}\n\n若链接无法点击，可复制 token：{}\n",
-        site_name, verify_url, new_token
-    );
-    let html = format!(
-        r#"<!doctype html><html><body style="margin:0;padding:0;background:#fdf7fb;font-family:'Segoe UI','PingFang SC','Microsoft YaHei',sans-serif;color:#2b1d2a;">
-<div style="max-width:640px;margin:24px auto;padding:0 12px;">
-  <div style="border:1px solid #eadbea;border-radius:18px;background:#ffffff;overflow:hidden;box-shadow:0 10px 26px rgba(84,34,86,0.08);">
-    <div style="padding:14px 16px;background:linear-gradient(120deg,#ffe6f2,#f1f8ff);font-weight:700;letter-spacing:.2px;">Meow Links 邮箱验证</div>
-    <div style="padding:16px;line-height:1.75;">
-      <div><strong>站点：</strong>{name}</div>
-      <div style="margin-top:10px;">请点击以下链接完成邮箱验证（完成后进入审核队列）：</div>
-      <div style="margin-top:8px;"><a href="{url}" style="color:#5b4cc4;text-decoration:none;word-break:break-all;">{url}</a></div>
-      <div style="margin-top:12px;padding:10px 12px;border-radius:10px;background:#f6f4ff;color:#3d3567;font-size:12px;">
-        若无法点击，可复制 token：<code style="font-family:ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,'Liberation Mono',monospace;">{token}</code>
-      </div>
-      <div style="margin-top:14px;font-size:12px;color:#7b6b7a;">此邮件由系统自动发送，请勿直接回复。</div>
-    </div>
-  </div>
-</div>
-</body></html>"#,
-        name = escape_html(&site_name),
-        url = escape_html(&verify_url),
-        token = escape_html(&new_token)
-    );
-    match state
-        .notifier
-        .send_smtp_rich(
-            notify_cfg.smtp.as_ref(),
-            "Meow Links 邮箱验证",
-            &plain,
-            Some(&html),
-            Some(vec![email]),
+    let expires_at = payload
+        .expires_at
+        .or_else(|| payload.duration_secs.map(|v| now + v.max(0)));
+    if expires_at.is_some_and(|v| v <= now) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "过期时间必须晚于当前时间".to_string(),
+            }),
         )
-        .await
+            .into_response();
+    }
+    if conn
+        .execute(
+            "INSERT INTO status_activity (id, text, emoji, link, expires_at, updated_at)
+             VALUES (1, ?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(id) DO UPDATE SET
+               text = excluded.text,
+               emoji = excluded.emoji,
+               link = excluded.link,
+               expires_at = excluded.expires_at,
+               updated_at = excluded.updated_at",
+            params![text.unwrap_or_default(), emoji, link, expires_at, now],
+        )
+        .is_err()
     {
-        Ok(_) => {
-            {
-                let conn = state.db.lock().unwrap();
-                let _ = conn.execute(
-                    "INSERT INTO friend_link_verify_email_log (application_id, ip, created_at)
-                     VALUES (?1, ?2, ?3)",
-                    params![payload.application_id, ip_value, now],
-                );
-            }
-            (
-                StatusCode::OK,
-                Json(VerifyEmailSendResponse {
-                    message: "验证邮件已发送，请检查收件箱".to_string(),
-                    verify_token: Some(new_token),
-                }),
-            )
-                .into_response()
-        }
-        Err(err) => {
-            {
-                let conn = state.db.lock().unwrap();
-                let _ = conn.execute(
-                    "UPDATE friend_link_applications
-                     SET verify_token = ?1, updated_at = ?2
-                     WHERE id = ?3 AND verify_token = ?4",
-                    params![token, now, payload.application_id, new_token],
-                );
-            }
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ApiMessage {
-                    message: format!("发送失败：{}", err),
-                }),
-            )
-                .into_response()
-        }
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
+    state.hub.publish_changes(&conn, &state.presence);
+    Json(load_status_activity(&conn, now)).into_response()
 }
 
-async fn links_verify_email(
+async fn device_status_update(
     State(state): State<AppState>,
-    Query(query): Query<LinkVerifyEmailQuery>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> impl IntoResponse {
-    let token = query.token.trim();
-    if token.is_empty() {
-        return (StatusCode::BAD_REQUEST, "missing token").into_response();
-    }
+    let Ok(payload) = serde_json::from_slice::<DeviceStatusUpdatePayload>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
     let now = now_ts();
     let conn = state.db.lock().unwrap();
-    let updated = conn
-        .execute(
-            "UPDATE friend_link_applications
-             SET verify_email_at = ?1, status = 'pending', updated_at = ?2
-             WHERE verify_token = ?3
-               AND status = 'verify_pending'
-               AND (verify_deadline IS NULL OR verify_deadline >= ?4)",
-            params![now, now, token, now],
+    // The admin token may only toggle manual offline on known devices; presence
+    // and music have to come from the device's own credential.
+    let admin = payload.device_name.is_none()
+        && payload.online.is_none()
+        && payload.music_playing.is_none()
+        && payload.music_title.is_none()
+        && payload.music_artist.is_none()
+        && payload.music_source.is_none()
+        && authorized(&headers, &state.token);
+    if !admin {
+        if let Err(code) = device_authorized(&state, &conn, &headers, &payload.device_id, &body) {
+            return code;
+        }
+    }
+    let existing = conn
+        .query_row(
+            "SELECT device_name, online, last_seen, idle_seconds, manual_offline,
+                    music_playing, music_title, music_artist, music_source, music_updated_at
+             FROM device_status
+             WHERE device_id = ?1",
+            params![payload.device_id.as_str()],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i32>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                    row.get::<_, i32>(4)?,
+                    row.get::<_, i32>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get::<_, Option<i64>>(9)?,
+                ))
+            },
         )
-        .unwrap_or(0);
-    if updated == 0 {
-        return (
-            StatusCode::BAD_REQUEST,
-            "<!doctype html><html><body style=\"font-family:sans-serif;padding:24px;\">验证失败：token 无效或已过期。</body></html>",
+        .ok();
+    if admin && existing.is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+
+    let device_name = payload
+        .device_name
+        .or_else(|| existing.as_ref().map(|v| v.0.clone()))
+        .unwrap_or_else(|| payload.device_id.clone());
+    let online = payload
+        .online
+        .unwrap_or_else(|| existing.as_ref().map(|v| v.1 == 1).unwrap_or(false));
+    let last_seen = if payload.online.is_some() {
+        now
+    } else {
+        existing.as_ref().map(|v| v.2).unwrap_or(now)
+    };
+    let idle_seconds = if payload.online.is_some() {
+        None
+    } else {
+        existing.as_ref().and_then(|v| v.3)
+    };
+    let manual_offline = payload
+        .manual_offline
+        .unwrap_or_else(|| existing.as_ref().map(|v| v.4 == 1).unwrap_or(false));
+    let has_music_update = payload.music_playing.is_some()
+        || payload.music_title.is_some()
+        || payload.music_artist.is_some()
+        || payload.music_source.is_some();
+    let music_playing = payload
+        .music_playing
+        .unwrap_or_else(|| existing.as_ref().map(|v| v.5 == 1).unwrap_or(false));
+    let music_title = payload
+        .music_title
+        .or_else(|| existing.as_ref().and_then(|v| v.6.clone()));
+    let music_artist = payload
+        .music_artist
+        .or_else(|| existing.as_ref().and_then(|v| v.7.clone()));
+    let music_source = payload
+        .music_source
+        .or_else(|| existing.as_ref().and_then(|v| v.8.clone()));
+    let music_updated_at = if has_music_update {
+        Some(now)
+    } else {
+        existing.as_ref().and_then(|v| v.9)
+    };
+
+    if conn
+        .execute(
+            "INSERT INTO device_status (
+                device_id, device_name, online, last_seen, idle_seconds, manual_offline,
+                music_playing, music_title, music_artist, music_source, music_updated_at
+             )
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+             ON CONFLICT(device_id) DO UPDATE SET
+               device_name = excluded.device_name,
+               online = excluded.online,
+               last_seen = excluded.last_seen,
+               idle_seconds = excluded.idle_seconds,
+               manual_offline = excluded.manual_offline,
+               music_playing = excluded.music_playing,
+               music_title = excluded.music_title,
+               music_artist = excluded.music_artist,
+               music_source = excluded.music_source,
+               music_updated_at = excluded.music_updated_at",
+            params![
+                payload.device_id,
+                device_name,
+                online as i32,
+                last_seen,
+                idle_seconds,
+                manual_offline as i32,
+                music_playing as i32,
+                music_title,
+                music_artist,
+                music_source,
+                music_updated_at,
+            ],
         )
-            .into_response();
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR;
     }
-    (
-        StatusCode::OK,
-        "<!doctype html><html><body style=\"font-family:sans-serif;padding:24px;\">邮箱验证成功，申请已进入审核队列。</body></html>",
+    if has_music_update {
+        record_music_play(
+            &conn,
+            &state.presence,
+            &payload.device_id,
+            music_playing,
+            music_title.as_deref(),
+            music_artist.as_deref(),
+            music_source.as_deref(),
+            now,
+        );
+    }
+    if payload.online.is_some() || payload.manual_offline.is_some() {
+        let rules = state.presence.device_rules(&conn, &payload.device_id);
+        record_presence(
+            &conn,
+            &state.presence,
+            &payload.device_id,
+            presence_state(
+                online,
+                idle_seconds.map(|v| v as u64),
+                manual_offline,
+                &rules,
+            ),
+            now,
+        );
+    }
+    state.hub.publish_changes(&conn, &state.presence);
+
+    StatusCode::OK
+}
+
+async fn blog_detail(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(slug): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let key = format!("blog:{}", slug);
+    let snapshot = match state.cache.get(&key) {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            match load_blog_post(&conn, &slug) {
+                Some(post) => state.cache.store(&key, &post, post.updated_at),
+                None => return StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+    };
+    cache::respond(&headers, &snapshot, &state.content_cache_control)
+}
+
+const BLOG_COLUMNS: &str =
+    "slug, title, date, tag, excerpt, content_json, content_md, sort_order, updated_at, version";
+
+fn blog_post_from_row(row: &rusqlite::Row) -> rusqlite::Result<BlogPost> {
+    let content_json: String = row.get(5)?;
+    let content = serde_json::from_str::<Vec<String>>(&content_json).unwrap_or_default();
+    let content_md = row
+        .get::<_, Option<String>>(6)?
+        .unwrap_or_else(|| content.join("\n"));
+    Ok(BlogPost {
+        slug: row.get(0)?,
+        title: row.get(1)?,
+        date: row.get(2)?,
+        tag: row.get(3)?,
+        excerpt: row.get(4)?,
+        content,
+        content_md,
+        sort_order: row.get(7)?,
+        updated_at: row.get(8)?,
+        version: row.get(9)?,
+    })
+}
+
+fn load_blog_post(conn: &Connection, slug: &str) -> Option<BlogPost> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM blog_posts WHERE slug = ?1 LIMIT 1",
+            BLOG_COLUMNS
+        ),
+        params![slug],
+        blog_post_from_row,
     )
-        .into_response()
+    .ok()
 }
 
-async fn links_verify_reset(
+async fn blog_update(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<LinkVerifyResetPayload>,
+    Json(payload): Json<BlogPayload>,
 ) -> impl IntoResponse {
     if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+        return StatusCode::UNAUTHORIZED;
     }
-    let anti_abuse = {
-        let conn = state.db.lock().unwrap();
-        resolve_anti_abuse_config(&conn, &state.anti_abuse)
-    };
+
     let now = now_ts();
-    let status = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
-            "SELECT status FROM friend_link_applications WHERE id = ?1",
-            params![payload.application_id],
-            |row| row.get::<_, String>(0),
-        )
-        .ok()
+    let mut conn = state.db.lock().unwrap();
+    let existing: HashMap<String, BlogPost> = conn
+        .prepare(&format!("SELECT {} FROM blog_posts", BLOG_COLUMNS))
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map([], blog_post_from_row)?;
+            Ok(rows
+                .filter_map(Result::ok)
+                .map(|post| (post.slug.clone(), post))
+                .collect())
+        })
+        .unwrap_or_default();
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
     };
-    let Some(status) = status else {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ApiMessage {
-                message: "申请不存在".to_string(),
-            }),
-        )
-            .into_response();
+
+    if tx.execute("DELETE FROM blog_posts", []).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    for (idx, item) in payload.items.into_iter().enumerate() {
+        let mut slug = normalize_blog_slug(item.slug.as_deref().unwrap_or_default());
+        if slug.is_empty() {
+            slug = format!("post-{}-{}", now, idx);
+        }
+        let old = existing.get(&slug);
+        let mut post = blog_post_from_input(item, slug, idx, now);
+        blog_post_bump(&mut post, old);
+        if upsert_blog_post(&tx, &post).is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    if tx.commit().is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    state.cache.invalidate("blog");
+
+    StatusCode::OK
+}
+
+fn normalize_blog_slug(raw: &str) -> String {
+    raw.trim()
+        .to_lowercase()
+        .replace(' ', "-")
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-')
+        .collect()
+}
+
+fn blog_post_from_input(item: BlogPostInput, slug: String, idx: usize, now: i64) -> BlogPost {
+    let input_content = item.content.unwrap_or_default();
+    let content_md = item.content_md.unwrap_or_else(|| input_content.join("\n"));
+    let content: Vec<String> = if !input_content.is_empty() {
+        input_content
+    } else {
+        content_md.split('\n').map(|v| v.to_string()).collect()
     };
-    if status != "verify_pending" {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "仅支持未验证申请重置验证".to_string(),
-            }),
-        )
-            .into_response();
+    let excerpt = item
+        .excerpt
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| {
+            content
+                .iter()
+                .find(|line| !line.trim().is_empty())
+                .cloned()
+                .unwrap_or_default()
+        });
+    BlogPost {
+        slug,
+        title: item.title,
+        date: item.date,
+        tag: item.tag,
+        excerpt,
+        content,
+        content_md,
+        sort_order: item.sort_order.unwrap_or(idx as i64),
+        updated_at: now,
+        version: 1,
     }
-    let new_token = generate_verify_token();
-    let verify_deadline = now + anti_abuse.verify_window_secs;
-    {
-        let conn = state.db.lock().unwrap();
-        let _ = conn.execute(
-            "UPDATE friend_link_applications
-             SET verify_token = ?1,
-                 verify_deadline = ?2,
-                 verify_http_at = NULL,
-                 verify_email_at = NULL,
-                 updated_at = ?3
-             WHERE id = ?4 AND status = 'verify_pending'",
-            params![new_token, verify_deadline, now, payload.application_id],
-        );
-        let _ = conn.execute(
-            "DELETE FROM friend_link_verify_email_log WHERE application_id = ?1",
-            params![payload.application_id],
-        );
+}
+
+/// Same as [`schedule_item_bump`] for blog posts.
+fn blog_post_bump(post: &mut BlogPost, old: Option<&BlogPost>) -> bool {
+    let Some(old) = old else {
+        post.version = 1;
+        return true;
+    };
+    post.version = old.version;
+    let updated_at = std::mem::replace(&mut post.updated_at, old.updated_at);
+    if post == old {
+        return false;
     }
+    post.updated_at = updated_at;
+    post.version = old.version + 1;
+    true
+}
+
+fn upsert_blog_post(conn: &Connection, post: &BlogPost) -> rusqlite::Result<usize> {
+    let content_json = serde_json::to_string(&post.content).unwrap_or_else(|_| "[]".to_string());
+    conn.execute(
+        "INSERT INTO blog_posts (
+            slug, title, date, tag, excerpt, content_json, content_md, sort_order, updated_at, version
+         )
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+         ON CONFLICT(slug) DO UPDATE SET
+           title = excluded.title,
+           date = excluded.date,
+           tag = excluded.tag,
+           excerpt = excluded.excerpt,
+           content_json = excluded.content_json,
+           content_md = excluded.content_md,
+           sort_order = excluded.sort_order,
+           updated_at = excluded.updated_at,
+           version = excluded.version",
+        params![
+            post.slug,
+            post.title,
+            post.date,
+            post.tag,
+            post.excerpt,
+            content_json,
+            post.content_md,
+            post.sort_order,
+            post.updated_at,
+            post.version
+        ],
+    )
+}
+
+fn blog_not_found() -> Response {
     (
-        StatusCode::OK,
-        Json(VerifyResetResponse {
-            message: "验证已重置，可重新走验证流程".to_string(),
-            verify_token: new_token,
-            verify_deadline,
+        StatusCode::NOT_FOUND,
+        Json(ApiMessage {
+            message: "文章不存在".to_string(),
         }),
     )
         .into_response()
 }
 
-async fn links_verify_release(
+async fn blog_put(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<LinkVerifyReleasePayload>,
+    axum::extract::Path(slug): axum::extract::Path<String>,
+    Json(input): Json<BlogPostInput>,
 ) -> impl IntoResponse {
     if !authorized(&headers, &state.token) {
         return StatusCode::UNAUTHORIZED.into_response();
     }
-    let now = now_ts();
-    let (status, existing_note) = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
-            "SELECT status, review_note FROM friend_link_applications WHERE id = ?1",
-            params![payload.application_id],
-            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
-        )
-        .ok()
-        .unwrap_or_else(|| ("".to_string(), None))
-    };
-    if status.is_empty() {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ApiMessage {
-                message: "申请不存在".to_string(),
-            }),
-        )
-            .into_response();
-    }
-    if status == "pending" {
-        return (
-            StatusCode::OK,
-            Json(ApiMessage {
-                message: "该申请已在审核队列中".to_string(),
-            }),
-        )
-            .into_response();
-    }
-    if status != "verify_pending" {
+    if slug.is_empty() || normalize_blog_slug(&slug) != slug {
         return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: "该申请当前不可放行".to_string(),
+                message: "slug 只能包含小写字母、数字与 -".to_string(),
             }),
         )
             .into_response();
     }
 
-    let manual_note = "手动放行未验证申请，进入自动审查队列";
-    let merged_note = match normalize_optional(existing_note, 520) {
-        Some(existing) => format!("{}\n{}", existing, manual_note),
-        None => manual_note.to_string(),
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let existing = load_blog_post(&conn, &slug);
+    if !version_matches(input.version, existing.as_ref().map(|post| post.version)) {
+        return version_conflict(existing);
+    }
+    let sort_order = input
+        .sort_order
+        .or(existing.as_ref().map(|post| post.sort_order))
+        .unwrap_or_else(|| {
+            conn.query_row(
+                "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM blog_posts",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+        });
+    let input = BlogPostInput {
+        sort_order: Some(sort_order),
+        ..input
     };
+    let post = blog_post_from_input(input, slug, 0, now);
+    blog_save(&state, &conn, post, existing)
+}
 
-    {
-        let conn = state.db.lock().unwrap();
-        let _ = conn.execute(
-            "UPDATE friend_link_applications
-             SET status = 'pending',
-                 review_note = ?1,
-                 verify_token = NULL,
-                 verify_deadline = NULL,
-                 verify_http_at = NULL,
-                 verify_email_at = NULL,
-                 updated_at = ?2
-             WHERE id = ?3 AND status = 'verify_pending'",
-            params![merged_note, now, payload.application_id],
-        );
+async fn blog_patch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(slug): axum::extract::Path<String>,
+    Json(patch): Json<BlogPatchPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
     }
 
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: "已手动放行，进入自动审查队列".to_string(),
-        }),
-    )
-        .into_response()
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let Some(old) = load_blog_post(&conn, &slug) else {
+        return blog_not_found();
+    };
+    if !version_matches(patch.version, Some(old.version)) {
+        return version_conflict(Some(old));
+    }
+    let content_changed = patch.content.is_some() || patch.content_md.is_some();
+    let input = BlogPostInput {
+        slug: None,
+        title: patch.title.unwrap_or_else(|| old.title.clone()),
+        date: patch.date.unwrap_or_else(|| old.date.clone()),
+        tag: match patch.tag {
+            Some(v) if v.trim().is_empty() => None,
+            Some(v) => Some(v),
+            None => old.tag.clone(),
+        },
+        excerpt: Some(patch.excerpt.unwrap_or_else(|| old.excerpt.clone())),
+        content: if content_changed {
+            patch.content
+        } else {
+            Some(old.content.clone())
+        },
+        content_md: if content_changed {
+            patch.content_md
+        } else {
+            Some(old.content_md.clone())
+        },
+        sort_order: Some(patch.sort_order.unwrap_or(old.sort_order)),
+        version: None,
+    };
+    let post = blog_post_from_input(input, old.slug.clone(), 0, now);
+    blog_save(&state, &conn, post, Some(old))
 }
 
-async fn links_applications(
+fn blog_save(
+    state: &AppState,
+    conn: &Connection,
+    mut post: BlogPost,
+    existing: Option<BlogPost>,
+) -> Response {
+    let created = existing.is_none();
+    if blog_post_bump(&mut post, existing.as_ref()) {
+        if upsert_blog_post(conn, &post).is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        state.cache.invalidate("blog");
+    }
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(post)).into_response()
+}
+
+async fn blog_delete(
     State(state): State<AppState>,
     headers: HeaderMap,
+    axum::extract::Path(slug): axum::extract::Path<String>,
+    Query(query): Query<VersionQuery>,
 ) -> impl IntoResponse {
     if !authorized(&headers, &state.token) {
         return StatusCode::UNAUTHORIZED.into_response();
     }
+
     let conn = state.db.lock().unwrap();
+    let Some(old) = load_blog_post(&conn, &slug) else {
+        return blog_not_found();
+    };
+    if !version_matches(query.version, Some(old.version)) {
+        return version_conflict(Some(old));
+    }
+    if conn
+        .execute("DELETE FROM blog_posts WHERE slug = ?1", params![slug])
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    state.cache.invalidate("blog");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn links_list(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let snapshot = match state.cache.get("links") {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            let list = load_friend_links(&conn);
+            let last_modified: i64 = conn
+                .query_row(
+                    "SELECT COALESCE(MAX(updated_at), 0) FROM friend_links",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap_or(0);
+            state.cache.store("links", &list, last_modified)
+        }
+    };
+    cache::respond(&headers, &snapshot, &state.content_cache_control)
+}
+
+fn load_friend_links(conn: &Connection) -> Vec<FriendLink> {
     let mut stmt = match conn.prepare(
-        "SELECT id, site_name, site_url, avatar_url, description, email, note, status,
-                ip, user_agent, review_note, verify_token, verify_deadline, verify_http_at, verify_email_at, created_at, updated_at
-         FROM friend_link_applications
-         ORDER BY status IN ('pending','verify_pending') DESC, created_at DESC",
+        "SELECT id, name, url, avatar_url, description, tags, sort_order, created_at,
+                backlink_status, backlink_deadline
+         FROM friend_links
+         ORDER BY sort_order ASC, created_at DESC",
     ) {
         Ok(stmt) => stmt,
-        Err(_) => return (StatusCode::OK, Json(Vec::<LinkApplication>::new())).into_response(),
+        Err(_) => return Vec::new(),
     };
+
     let rows = match stmt.query_map([], |row| {
-        let status: String = row.get(7)?;
-        Ok(LinkApplication {
+        Ok(FriendLink {
             id: row.get(0)?,
-            site_name: row.get(1)?,
-            site_url: row.get(2)?,
+            name: row.get(1)?,
+            url: row.get(2)?,
             avatar_url: row.get(3)?,
             description: row.get(4)?,
-            email: row.get(5)?,
-            note: row.get(6)?,
-            status: status.clone(),
-            ip: row.get(8)?,
-            user_agent: row.get(9)?,
-            review_note: row.get(10)?,
-            verify_status: if status == "verify_pending" {
-                Some(status)
-            } else {
-                None
-            },
-            verify_deadline: row.get(12)?,
-            verify_http_at: row.get(13)?,
-            verify_email_at: row.get(14)?,
-            created_at: row.get(15)?,
-            updated_at: row.get(16)?,
+            tags: row.get(5)?,
+            sort_order: row.get(6)?,
+            created_at: row.get(7)?,
+            backlink_status: row.get(8)?,
+            backlink_deadline: row.get(9)?,
         })
     }) {
         Ok(rows) => rows,
-        Err(_) => return (StatusCode::OK, Json(Vec::<LinkApplication>::new())).into_response(),
+        Err(_) => return Vec::new(),
     };
 
-    (
-        StatusCode::OK,
-        Json(rows.filter_map(Result::ok).collect::<Vec<_>>()),
-    )
-        .into_response()
+    rows.filter_map(Result::ok).collect()
 }
 
-async fn links_review(
+async fn links_apply(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<LinkReviewPayload>,
+    Json(payload): Json<LinkApplyPayload>,
 ) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
-    }
-    match perform_review_decision(&state, payload, true, false).await {
-        Ok(message) => (StatusCode::OK, Json(ApiMessage { message })).into_response(),
-        Err(err) => (
+    let anti_abuse = {
+        let conn = state.db.lock().unwrap();
+        resolve_anti_abuse_config(&conn, &state.anti_abuse)
+    };
+    let site_name = payload.site_name.trim();
+    if site_name.is_empty() || site_name.chars().count() > 32 {
+        return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: err.to_string(),
+                message: "站点名称长度需在 1-32 字符内".to_string(),
             }),
         )
-            .into_response(),
+            .into_response();
     }
-}
 
-async fn links_review_report_decision(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<ReviewDecisionReportPayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.review_report_token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+    let site_url = payload.site_url.trim();
+    if !is_valid_http_url(site_url) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "站点地址必须是可访问的 http/https 链接".to_string(),
+            }),
+        )
+            .into_response();
     }
-    let review_payload = LinkReviewPayload {
-        application_id: payload.application_id,
-        action: payload.action,
-        sort_order: payload.sort_order,
-        tags: payload.tags,
-        review_note: payload.review_note,
-    };
-    match perform_review_decision(
-        &state,
-        review_payload,
-        payload.send_email.unwrap_or(true),
-        true,
-    )
-    .await
+    if is_disallowed_link_host(site_url, &anti_abuse) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该站点类型不接受友链申请，请提交可互链的个人/博客站点".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    let avatar_url = normalize_optional(payload.avatar_url, 255);
+    if avatar_url
+        .as_deref()
+        .is_some_and(|value| !is_valid_http_url(value))
     {
-        Ok(message) => (StatusCode::OK, Json(ApiMessage { message })).into_response(),
-        Err(err) => (
+        return (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: err.to_string(),
+                message: "头像地址格式不正确（需为 http/https）".to_string(),
             }),
         )
-            .into_response(),
+            .into_response();
     }
-}
 
-async fn links_review_report_manual(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<ReviewManualReportPayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.review_report_token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+    let description = normalize_optional(payload.description, 280);
+    let email = normalize_optional(payload.email, 128);
+    if email.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "请填写联系邮箱".to_string(),
+            }),
+        )
+            .into_response();
     }
-    let now = now_ts();
-    let review_note = normalize_optional(payload.review_note, 280);
-    let app_row = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
-            "SELECT site_name, site_url, email, status, manual_notified
-             FROM friend_link_applications WHERE id = ?1",
-            params![payload.application_id],
-            |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, Option<String>>(2)?,
-                    row.get::<_, String>(3)?,
-                    row.get::<_, i32>(4)?,
-                ))
-            },
+    let email = email.unwrap();
+    if !is_valid_email_address(&email) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "联系邮箱格式不正确".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if is_blocked_contact_email(&email) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该邮箱不支持用于友链申请".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if anti_abuse.disposable_email_block && is_disposable_email_domain(&email) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "不支持一次性邮箱，请使用常用邮箱提交".to_string(),
+            }),
         )
+            .into_response();
+    }
+    if anti_abuse.edu_gov_email_block && is_edu_or_gov_email_domain(&email) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "不支持 .edu/.gov 邮箱，请使用常用邮箱提交".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let note = normalize_optional(payload.note, 280);
+    let ip = client_ip(&headers).or_else(|| Some("unknown".to_string()));
+    let ip_prefix = ip
+        .as_deref()
+        .and_then(ip_prefix_key)
+        .unwrap_or_else(|| "unknown".to_string());
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let captcha_token = normalize_optional(payload.captcha_token, 4096);
+    let email_domain = email_domain_of(&email).unwrap_or_else(|| "unknown".to_string());
+    let site_host = Url::parse(site_url)
         .ok()
-    };
-    let (site_name, site_url, email, status, manual_notified) = match app_row {
-        Some(v) => v,
-        None => {
+        .and_then(|u| u.host_str().map(normalize_host))
+        .unwrap_or_else(|| "unknown".to_string());
+    let now = now_ts();
+    let verify_token = generate_verify_token();
+    let verify_deadline = now + anti_abuse.verify_window_secs;
+    let application_id: i64;
+
+    if let Some(captcha_cfg) = anti_abuse.captcha.as_ref() {
+        let Some(token) = captcha_token.as_deref() else {
             return (
-                StatusCode::NOT_FOUND,
+                StatusCode::BAD_REQUEST,
                 Json(ApiMessage {
-                    message: "申请记录不存在".to_string(),
+                    message: "请完成人机验证后再提交".to_string(),
                 }),
             )
-                .into_response()
+                .into_response();
+        };
+        let verified = verify_captcha(captcha_cfg, token, ip.as_deref()).await;
+        METRICS.record_captcha(captcha_cfg.provider.as_str(), verified);
+        if !verified {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiMessage {
+                    message: "人机验证失败，请刷新后重试".to_string(),
+                }),
+            )
+                .into_response();
         }
-    };
-    if status != "pending" {
-        return (
-            StatusCode::OK,
-            Json(ApiMessage {
-                message: "申请状态已非 pending，跳过手动提醒".to_string(),
-            }),
-        )
-            .into_response();
     }
 
     {
         let conn = state.db.lock().unwrap();
+        let cleanup_before = now - VERIFY_EXPIRED_CLEANUP_GRACE_SECS;
         let _ = conn.execute(
-            "UPDATE friend_link_applications
-             SET review_note = ?1, updated_at = ?2
-             WHERE id = ?3",
-            params![review_note, now, payload.application_id],
-        );
-    }
-
-    let should_notify = payload.send_admin_notify.unwrap_or(true) && manual_notified == 0;
-    if should_notify {
-        let notify_cfg = {
-            let conn = state.db.lock().unwrap();
-            state.notifier.runtime_config(&conn)
-        };
-        let subject = "New friend-link application (manual review required)".to_string();
-        let msg = format!(
-            "New friend-link application (manual review required)\nsite: {}\nurl: {}\nemail: {}\nreview_note: {}",
-            site_name,
-            site_url,
-            email.as_deref().unwrap_or("-"),
-            review_note.as_deref().unwrap_or("-")
+            "DELETE FROM friend_link_verify_email_log
+             WHERE application_id IN (
+               SELECT id FROM friend_link_applications
+               WHERE status = 'verify_pending'
+                 AND verify_deadline IS NOT NULL
+                 AND verify_deadline < ?1
+             )",
+            params![cleanup_before],
         );
-        let html = build_manual_review_admin_html(
-            payload.application_id,
-            &site_name,
-            &site_url,
-            email.as_deref(),
-            review_note.as_deref().unwrap_or("-"),
+        let _ = conn.execute(
+            "DELETE FROM friend_link_applications
+             WHERE status = 'verify_pending'
+               AND verify_deadline IS NOT NULL
+               AND verify_deadline < ?1",
+            params![cleanup_before],
         );
-        let send_result = if let Some(smtp_cfg) = notify_cfg.smtp.as_ref() {
-            if smtp_cfg.to.is_empty() {
-                Err("smtp to recipients is empty".to_string())
-            } else {
-                state
-                    .notifier
-                    .send_smtp_rich(Some(smtp_cfg), &subject, &msg, Some(&html), None)
-                    .await
-            }
-        } else {
-            Err("smtp not configured".to_string())
-        };
-        if let Err(err) = send_result {
-            tracing::warn!("manual review smtp notify failed: {}", err);
-        } else {
-            let conn = state.db.lock().unwrap();
-            let _ = conn.execute(
-                "UPDATE friend_link_applications
-                 SET manual_notified = 1, updated_at = ?1
-                 WHERE id = ?2",
-                params![now, payload.application_id],
-            );
-        }
-    }
-
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: if should_notify {
-                "已标记为待人工审核并推送提醒".to_string()
-            } else {
-                "已标记为待人工审核（提醒已发送过）".to_string()
-            },
-        }),
-    )
-        .into_response()
-}
-
-async fn links_review_report_tasks(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> impl IntoResponse {
+        let existing_verify = {
+            let mut stmt = match conn.prepare(
+                "SELECT id, site_url, verify_token, verify_deadline
+                 FROM friend_link_applications
+                 WHERE status = 'verify_pending'
+                 ORDER BY created_at DESC",
+            ) {
+                Ok(v) => v,
+                Err(_) => {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            let rows = match stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            }) {
+                Ok(v) => v,
+                Err(_) => {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            let candidates: Vec<_> = rows.filter_map(Result::ok).collect();
+            candidates.into_iter().find(|(_, url, _, _)| {
+                Url::parse(url)
+                    .ok()
+                    .and_then(|u| u.host_str().map(normalize_host))
+                    .map(|host| host == site_host || host.ends_with(&format!(".{}", site_host)))
+                    .unwrap_or(false)
+            })
+        };
+        if let Some((id, _url, token_opt, deadline_opt)) = existing_verify {
+            let mut token = token_opt.unwrap_or_default();
+            let mut deadline = deadline_opt.unwrap_or(0);
+            if deadline > 0 && deadline < now {
+                token = generate_verify_token();
+                deadline = now + anti_abuse.verify_window_secs;
+                let _ = conn.execute(
+                    "UPDATE friend_link_applications
+                     SET verify_token = ?1, verify_deadline = ?2, updated_at = ?3
+                     WHERE id = ?4",
+                    params![token, deadline, now, id],
+                );
+            } else if token.is_empty() {
+                deadline = deadline.max(verify_deadline);
+                token = generate_verify_token();
+                let _ = conn.execute(
+                    "UPDATE friend_link_applications
+                     SET verify_token = ?1, verify_deadline = ?2, updated_at = ?3
+                     WHERE id = ?4",
+                    params![token, deadline, now, id],
+                );
+            }
+            return (
+                StatusCode::OK,
+                Json(LinkApplyResponse {
+                    message: "该站点已有待验证申请，已为你加载验证信息。".to_string(),
+                    application_id: id,
+                    verify_status: "verify_pending".to_string(),
+                    http_verify_path: "/.well-known/meow-links.txt".to_string(),
+                    verify_token: token,
+                    verify_deadline: deadline,
+                }),
+            )
+                .into_response();
+        }
+        let recent_apply_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_applications
+                 WHERE ip = ?1 AND created_at >= ?2",
+                params![
+                    ip.as_deref().unwrap_or("unknown"),
+                    now - anti_abuse.apply_rate_limit_window_secs
+                ],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if recent_apply_count >= anti_abuse.apply_rate_limit_max {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiMessage {
+                    message: "提交过于频繁，请稍后再试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let prefix_apply_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_applications
+                 WHERE created_at >= ?1
+                   AND (
+                     ip = ?2 OR ip LIKE ?3
+                   )",
+                params![
+                    now - anti_abuse.apply_rate_limit_window_secs,
+                    ip_prefix,
+                    format!("{}%", ip_prefix)
+                ],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if prefix_apply_count >= anti_abuse.apply_rate_limit_prefix_max {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiMessage {
+                    message: "当前网络段提交过于频繁，请稍后再试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let email_domain_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_applications
+                 WHERE created_at >= ?1
+                   AND lower(substr(email, instr(email, '@') + 1)) = ?2",
+                params![
+                    now - anti_abuse.apply_rate_limit_window_secs,
+                    email_domain.to_lowercase()
+                ],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if email_domain_count >= anti_abuse.apply_rate_limit_email_domain_max {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiMessage {
+                    message: "该邮箱域名提交过于频繁，请稍后再试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let site_host_count: i64 = {
+            let mut stmt = match conn.prepare(
+                "SELECT site_url FROM friend_link_applications WHERE created_at >= ?1",
+            ) {
+                Ok(v) => v,
+                Err(_) => {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            let rows = match stmt.query_map(params![now - anti_abuse.apply_rate_limit_window_secs], |row| {
+                row.get::<_, String>(0)
+            }) {
+                Ok(v) => v,
+                Err(_) => {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            rows.filter_map(Result::ok)
+                .filter(|existing_url| {
+                    Url::parse(existing_url)
+                        .ok()
+                        .and_then(|u| u.host_str().map(normalize_host))
+                        .map(|host| host == site_host || host.ends_with(&format!(".{}", site_host)))
+                        .unwrap_or(false)
+                })
+                .count() as i64
+        };
+        if site_host_count >= anti_abuse.apply_rate_limit_site_host_max {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiMessage {
+                    message: "该站点提交过于频繁，请稍后再试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let pending_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_applications
+                 WHERE site_url = ?1
+                   AND (
+                     status = 'pending'
+                     OR (status = 'verify_pending' AND (verify_deadline IS NULL OR verify_deadline >= ?2))
+                   )",
+                params![site_url, now],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if pending_count > 0 {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiMessage {
+                    message: "该站点已有待处理申请，请勿重复提交".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let linked_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_links WHERE url = ?1",
+                params![site_url],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if linked_count > 0 {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiMessage {
+                    message: "该站点已在友链列表中".to_string(),
+                }),
+            )
+                .into_response();
+        }
+
+        let inserted = conn.execute(
+            "INSERT INTO friend_link_applications (
+                site_name, site_url, avatar_url, description, email, note,
+                status, ip, user_agent, verify_token, verify_deadline, created_at, updated_at
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'verify_pending', ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                site_name,
+                site_url,
+                avatar_url,
+                description,
+                email,
+                note,
+                ip,
+                user_agent,
+                verify_token,
+                verify_deadline,
+                now,
+                now
+            ],
+        );
+        if inserted.is_err() {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiMessage {
+                    message: "提交失败，请稍后重试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        application_id = conn.last_insert_rowid();
+    }
+
+    (
+        StatusCode::CREATED,
+        Json(LinkApplyResponse {
+            message: format!(
+                "友链申请已提交，编号 #{}。请先完成验证（HTTP 或 邮箱 任一项）后进入审核队列。",
+                application_id
+            ),
+            application_id,
+            verify_status: "verify_pending".to_string(),
+            http_verify_path: "/.well-known/meow-links.txt".to_string(),
+            verify_token: verify_token.clone(),
+            verify_deadline,
+        }),
+    )
+        .into_response()
+}
+
+async fn links_verify_http(
+    State(state): State<AppState>,
+    Json(payload): Json<LinkVerifyHttpPayload>,
+) -> impl IntoResponse {
+    let anti_abuse = {
+        let conn = state.db.lock().unwrap();
+        resolve_anti_abuse_config(&conn, &state.anti_abuse)
+    };
+    let now = now_ts();
+    let (site_url, verify_token, status, verify_deadline) = {
+        let conn = state.db.lock().unwrap();
+        let row = conn.query_row(
+            "SELECT site_url, verify_token, status, verify_deadline
+             FROM friend_link_applications WHERE id = ?1",
+            params![payload.application_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            },
+        );
+        match row {
+            Ok(v) => v,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(ApiMessage {
+                        message: "申请不存在".to_string(),
+                    }),
+                )
+                    .into_response()
+            }
+        }
+    };
+    if status == "pending" {
+        return (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "该申请已完成验证并进入审核队列".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if status != "verify_pending" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该申请当前不可验证".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if verify_deadline.unwrap_or(0) < now {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "验证已过期，请重新提交申请".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let Some(token) = verify_token else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该申请缺少验证 token，请重新提交".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    let ok = verify_http_token_present(&site_url, &token).await;
+    if !ok {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "未检测到验证 token，请确认已配置：/.well-known/meow-links.txt 或首页 meta 或 DNS TXT".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    {
+        let conn = state.db.lock().unwrap();
+        let updated = conn
+            .execute(
+                "UPDATE friend_link_applications
+                 SET verify_http_at = ?1, status = 'pending', updated_at = ?2
+                 WHERE id = ?3 AND status = 'verify_pending'",
+                params![now, now, payload.application_id],
+            )
+            .unwrap_or(0);
+        if updated > 0 {
+            if let Some(text) = application_telegram_text(
+                &conn,
+                payload.application_id,
+                "新友链申请（HTTP 已验证）",
+            ) {
+                spawn_telegram_notify(&state, text, Some(payload.application_id));
+            }
+        }
+    }
+    let remain_hours = (anti_abuse.verify_window_secs / 3600).max(1);
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: format!("HTTP 验证通过，已进入审核队列（验证窗口 {}h）", remain_hours),
+        }),
+    )
+        .into_response()
+}
+
+async fn links_verify_email_send(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkVerifyEmailSendPayload>,
+) -> impl IntoResponse {
+    let anti_abuse = {
+        let conn = state.db.lock().unwrap();
+        resolve_anti_abuse_config(&conn, &state.anti_abuse)
+    };
+    let now = now_ts();
+    let (email, status, verify_token, verify_deadline, site_name) = {
+        let conn = state.db.lock().unwrap();
+        let row = conn.query_row(
+            "SELECT email, status, verify_token, verify_deadline, site_name
+             FROM friend_link_applications WHERE id = ?1",
+            params![payload.application_id],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            },
+        );
+        match row {
+            Ok(v) => v,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(ApiMessage {
+                        message: "申请不存在".to_string(),
+                    }),
+                )
+                    .into_response()
+            }
+        }
+    };
+    if status == "pending" {
+        return (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "该申请已完成验证并进入审核队列".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if status != "verify_pending" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该申请当前不可发送验证邮件".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if verify_deadline.unwrap_or(0) < now {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "验证已过期，请重新提交申请".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let Some(email) = email else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "申请未填写邮箱，无法发送验证邮件".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    let Some(token) = verify_token else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该申请缺少验证 token，请重新提交".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    if token != payload.verify_token.trim() {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiMessage {
+                message: "验证 token 不匹配".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let ip = client_ip(&headers).or_else(|| Some("unknown".to_string()));
+    let ip_value = ip.as_deref().unwrap_or("unknown");
+    if let Some(captcha_cfg) = anti_abuse.captcha.as_ref() {
+        let captcha_token = normalize_optional(payload.captcha_token, 4096);
+        let Some(captcha_token) = captcha_token.as_deref() else {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiMessage {
+                    message: "请先完成人机验证".to_string(),
+                }),
+            )
+                .into_response();
+        };
+        let verified = verify_captcha(captcha_cfg, captcha_token, ip.as_deref()).await;
+        METRICS.record_captcha(captcha_cfg.provider.as_str(), verified);
+        if !verified {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiMessage {
+                    message: "人机验证失败，请重试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+    }
+    {
+        let conn = state.db.lock().unwrap();
+        let recent_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_verify_email_log
+                 WHERE ip = ?1 AND created_at >= ?2",
+                params![
+                    ip_value,
+                    now - anti_abuse.verify_email_rate_limit_window_secs
+                ],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if recent_count >= anti_abuse.verify_email_rate_limit_max {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiMessage {
+                    message: "验证邮件发送过于频繁，请稍后再试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let app_recent_count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM friend_link_verify_email_log
+                 WHERE application_id = ?1 AND created_at >= ?2",
+                params![
+                    payload.application_id,
+                    now - anti_abuse.verify_email_rate_limit_window_secs
+                ],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        if app_recent_count >= anti_abuse.verify_email_rate_limit_app_max {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiMessage {
+                    message: "该申请发送次数已达上限，请稍后再试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+        let last_sent: Option<i64> = conn
+            .query_row(
+                "SELECT MAX(created_at) FROM friend_link_verify_email_log
+                 WHERE application_id = ?1",
+                params![payload.application_id],
+                |row| row.get(0),
+            )
+            .unwrap_or(None);
+        if let Some(last_sent) = last_sent {
+            if now.saturating_sub(last_sent) < anti_abuse.verify_email_cooldown_secs {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(ApiMessage {
+                        message: "发送过于频繁，请稍后再试".to_string(),
+                    }),
+                )
+                    .into_response();
+            }
+        }
+    }
+    let new_token = generate_verify_token();
+    {
+        let conn = state.db.lock().unwrap();
+        let updated = conn
+            .execute(
+                "UPDATE friend_link_applications
+                 SET verify_token = ?1, updated_at = ?2
+                 WHERE id = ?3 AND status = 'verify_pending' AND verify_token = ?4",
+                params![new_token, now, payload.application_id, token],
+            )
+            .unwrap_or(0);
+        if updated == 0 {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiMessage {
+                    message: "验证 token 已更新，请刷新后重试".to_string(),
+                }),
+            )
+                .into_response();
+        }
+    }
+    let notify_cfg = {
+        let conn = state.db.lock().unwrap();
+        state.notifier.runtime_config(&conn)
+    };
+    let verify_url = anti_abuse
+        .public_base_url
+        .as_deref()
+        .map(|base| format!("{}/links/verify/email?token={}", base.trim_end_matches('/'), new_token))
+        .unwrap_or_else(|| format!("/links/verify/email?token={}", new_token));
+    let plain = format!(
+        "站点：{}\n请点击链接完成邮箱验证（完成后进入审核队列）：\n{}\n\n若链接无法点击，可复制 token：{}\n",
+        site_name, verify_url, new_token
+    );
+    let html = format!(
+        r#"<!doctype html><html><body style="margin:0;padding:0;background:#fdf7fb;font-family:'Segoe UI','PingFang SC','Microsoft YaHei',sans-serif;color:#2b1d2a;">
+<div style="max-width:640px;margin:24px auto;padding:0 12px;">
+  <div style="border:1px solid #eadbea;border-radius:18px;background:#ffffff;overflow:hidden;box-shadow:0 10px 26px rgba(84,34,86,0.08);">
+    <div style="padding:14px 16px;background:linear-gradient(120deg,#ffe6f2,#f1f8ff);font-weight:700;letter-spacing:.2px;">Meow Links 邮箱验证</div>
+    <div style="padding:16px;line-height:1.75;">
+      <div><strong>站点：</strong>{name}</div>
+      <div style="margin-top:10px;">请点击以下链接完成邮箱验证（完成后进入审核队列）：</div>
+      <div style="margin-top:8px;"><a href="{url}" style="color:#5b4cc4;text-decoration:none;word-break:break-all;">{url}</a></div>
+      <div style="margin-top:12px;padding:10px 12px;border-radius:10px;background:#f6f4ff;color:#3d3567;font-size:12px;">
+        若无法点击，可复制 token：<code style="font-family:ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,'Liberation Mono',monospace;">{token}</code>
+      </div>
+      <div style="margin-top:14px;font-size:12px;color:#7b6b7a;">此邮件由系统自动发送，请勿直接回复。</div>
+    </div>
+  </div>
+</div>
+</body></html>"#,
+        name = escape_html(&site_name),
+        url = escape_html(&verify_url),
+        token = escape_html(&new_token)
+    );
+    match state
+        .notifier
+        .send_smtp_rich(
+            notify_cfg.smtp.as_ref(),
+            "Meow Links 邮箱验证",
+            &plain,
+            Some(&html),
+            Some(vec![email]),
+        )
+        .await
+    {
+        Ok(_) => {
+            {
+                let conn = state.db.lock().unwrap();
+                let _ = conn.execute(
+                    "INSERT INTO friend_link_verify_email_log (application_id, ip, created_at)
+                     VALUES (?1, ?2, ?3)",
+                    params![payload.application_id, ip_value, now],
+                );
+            }
+            (
+                StatusCode::OK,
+                Json(VerifyEmailSendResponse {
+                    message: "验证邮件已发送，请检查收件箱".to_string(),
+                    verify_token: Some(new_token),
+                }),
+            )
+                .into_response()
+        }
+        Err(err) => {
+            {
+                let conn = state.db.lock().unwrap();
+                let _ = conn.execute(
+                    "UPDATE friend_link_applications
+                     SET verify_token = ?1, updated_at = ?2
+                     WHERE id = ?3 AND verify_token = ?4",
+                    params![token, now, payload.application_id, new_token],
+                );
+            }
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiMessage {
+                    message: format!("发送失败：{}", err),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn links_verify_email(
+    State(state): State<AppState>,
+    Query(query): Query<LinkVerifyEmailQuery>,
+) -> impl IntoResponse {
+    let token = query.token.trim();
+    if token.is_empty() {
+        return (StatusCode::BAD_REQUEST, "missing token").into_response();
+    }
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let updated = conn
+        .execute(
+            "UPDATE friend_link_applications
+             SET verify_email_at = ?1, status = 'pending', updated_at = ?2
+             WHERE verify_token = ?3
+               AND status = 'verify_pending'
+               AND (verify_deadline IS NULL OR verify_deadline >= ?4)",
+            params![now, now, token, now],
+        )
+        .unwrap_or(0);
+    if updated == 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            "<!doctype html><html><body style=\"font-family:sans-serif;padding:24px;\">验证失败：token 无效或已过期。</body></html>",
+        )
+            .into_response();
+    }
+    let application_id = conn
+        .query_row(
+            "SELECT id FROM friend_link_applications WHERE verify_token = ?1",
+            params![token],
+            |row| row.get::<_, i64>(0),
+        )
+        .ok();
+    if let Some(application_id) = application_id {
+        if let Some(text) =
+            application_telegram_text(&conn, application_id, "新友链申请（邮箱已验证）")
+        {
+            spawn_telegram_notify(&state, text, Some(application_id));
+        }
+    }
+    (
+        StatusCode::OK,
+        "<!doctype html><html><body style=\"font-family:sans-serif;padding:24px;\">邮箱验证成功，申请已进入审核队列。</body></html>",
+    )
+        .into_response()
+}
+
+async fn links_verify_reset(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkVerifyResetPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let anti_abuse = {
+        let conn = state.db.lock().unwrap();
+        resolve_anti_abuse_config(&conn, &state.anti_abuse)
+    };
+    let now = now_ts();
+    let status = {
+        let conn = state.db.lock().unwrap();
+        conn.query_row(
+            "SELECT status FROM friend_link_applications WHERE id = ?1",
+            params![payload.application_id],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+    };
+    let Some(status) = status else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiMessage {
+                message: "申请不存在".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    if status != "verify_pending" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "仅支持未验证申请重置验证".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let new_token = generate_verify_token();
+    let verify_deadline = now + anti_abuse.verify_window_secs;
+    {
+        let conn = state.db.lock().unwrap();
+        let _ = conn.execute(
+            "UPDATE friend_link_applications
+             SET verify_token = ?1,
+                 verify_deadline = ?2,
+                 verify_http_at = NULL,
+                 verify_email_at = NULL,
+                 updated_at = ?3
+             WHERE id = ?4 AND status = 'verify_pending'",
+            params![new_token, verify_deadline, now, payload.application_id],
+        );
+        let _ = conn.execute(
+            "DELETE FROM friend_link_verify_email_log WHERE application_id = ?1",
+            params![payload.application_id],
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(VerifyResetResponse {
+            message: "验证已重置，可重新走验证流程".to_string(),
+            verify_token: new_token,
+            verify_deadline,
+        }),
+    )
+        .into_response()
+}
+
+async fn links_verify_release(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkVerifyReleasePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let (status, existing_note) = {
+        let conn = state.db.lock().unwrap();
+        conn.query_row(
+            "SELECT status, review_note FROM friend_link_applications WHERE id = ?1",
+            params![payload.application_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+        )
+        .ok()
+        .unwrap_or_else(|| ("".to_string(), None))
+    };
+    if status.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiMessage {
+                message: "申请不存在".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if status == "pending" {
+        return (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "该申请已在审核队列中".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    if status != "verify_pending" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "该申请当前不可放行".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    let manual_note = "手动放行未验证申请，进入自动审查队列";
+    let merged_note = match normalize_optional(existing_note, 520) {
+        Some(existing) => format!("{}\n{}", existing, manual_note),
+        None => manual_note.to_string(),
+    };
+
+    {
+        let conn = state.db.lock().unwrap();
+        let updated = conn
+            .execute(
+                "UPDATE friend_link_applications
+                 SET status = 'pending',
+                     review_note = ?1,
+                     verify_token = NULL,
+                     verify_deadline = NULL,
+                     verify_http_at = NULL,
+                     verify_email_at = NULL,
+                     updated_at = ?2
+                 WHERE id = ?3 AND status = 'verify_pending'",
+                params![merged_note, now, payload.application_id],
+            )
+            .unwrap_or(0);
+        if updated > 0 {
+            if let Some(text) = application_telegram_text(
+                &conn,
+                payload.application_id,
+                "新友链申请（管理员手动放行）",
+            ) {
+                spawn_telegram_notify(&state, text, Some(payload.application_id));
+            }
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: "已手动放行，进入自动审查队列".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+async fn links_applications(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let mut stmt = match conn.prepare(
+        "SELECT id, site_name, site_url, avatar_url, description, email, note, status,
+                ip, user_agent, review_note, verify_token, verify_deadline, verify_http_at, verify_email_at, created_at, updated_at
+         FROM friend_link_applications
+         ORDER BY status IN ('pending','verify_pending') DESC, created_at DESC",
+    ) {
+        Ok(stmt) => stmt,
+        Err(_) => return (StatusCode::OK, Json(Vec::<LinkApplication>::new())).into_response(),
+    };
+    let rows = match stmt.query_map([], |row| {
+        let status: String = row.get(7)?;
+        Ok(LinkApplication {
+            id: row.get(0)?,
+            site_name: row.get(1)?,
+            site_url: row.get(2)?,
+            avatar_url: row.get(3)?,
+            description: row.get(4)?,
+            email: row.get(5)?,
+            note: row.get(6)?,
+            status: status.clone(),
+            ip: row.get(8)?,
+            user_agent: row.get(9)?,
+            review_note: row.get(10)?,
+            verify_status: if status == "verify_pending" {
+                Some(status)
+            } else {
+                None
+            },
+            verify_deadline: row.get(12)?,
+            verify_http_at: row.get(13)?,
+            verify_email_at: row.get(14)?,
+            created_at: row.get(15)?,
+            updated_at: row.get(16)?,
+        })
+    }) {
+        Ok(rows) => rows,
+        Err(_) => return (StatusCode::OK, Json(Vec::<LinkApplication>::new())).into_response(),
+    };
+
+    (
+        StatusCode::OK,
+        Json(rows.filter_map(Result::ok).collect::<Vec<_>>()),
+    )
+        .into_response()
+}
+
+async fn links_review(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkReviewPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match perform_review_decision(&state, payload, true, false).await {
+        Ok(message) => (StatusCode::OK, Json(ApiMessage { message })).into_response(),
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: err.to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+async fn links_review_report_decision(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ReviewDecisionReportPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.review_report_token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let review_payload = LinkReviewPayload {
+        application_id: payload.application_id,
+        action: payload.action,
+        sort_order: payload.sort_order,
+        tags: payload.tags,
+        review_note: payload.review_note,
+    };
+    match perform_review_decision(
+        &state,
+        review_payload,
+        payload.send_email.unwrap_or(true),
+        true,
+    )
+    .await
+    {
+        Ok(message) => (StatusCode::OK, Json(ApiMessage { message })).into_response(),
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: err.to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+async fn links_review_report_manual(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ReviewManualReportPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.review_report_token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let review_note = normalize_optional(payload.review_note, 280);
+    let app_row = {
+        let conn = state.db.lock().unwrap();
+        conn.query_row(
+            "SELECT site_name, site_url, email, status, manual_notified
+             FROM friend_link_applications WHERE id = ?1",
+            params![payload.application_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i32>(4)?,
+                ))
+            },
+        )
+        .ok()
+    };
+    let (site_name, site_url, email, status, manual_notified) = match app_row {
+        Some(v) => v,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiMessage {
+                    message: "申请记录不存在".to_string(),
+                }),
+            )
+                .into_response()
+        }
+    };
+    if status != "pending" {
+        return (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "申请状态已非 pending，跳过手动提醒".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    {
+        let conn = state.db.lock().unwrap();
+        let _ = conn.execute(
+            "UPDATE friend_link_applications
+             SET review_note = ?1, updated_at = ?2
+             WHERE id = ?3",
+            params![review_note, now, payload.application_id],
+        );
+    }
+
+    let should_notify = payload.send_admin_notify.unwrap_or(true) && manual_notified == 0;
+    if should_notify {
+        let notify_cfg = {
+            let conn = state.db.lock().unwrap();
+            state.notifier.runtime_config(&conn)
+        };
+        let subject = "New friend-link application (manual review required)".to_string();
+        let msg = format!(
+            "New friend-link application (manual review required)\nsite: {}\nurl: {}\nemail: {}\nreview_note: {}",
+            site_name,
+            site_url,
+            email.as_deref().unwrap_or("-"),
+            review_note.as_deref().unwrap_or("-")
+        );
+        let html = build_manual_review_admin_html(
+            payload.application_id,
+            &site_name,
+            &site_url,
+            email.as_deref(),
+            review_note.as_deref().unwrap_or("-"),
+        );
+        let send_result = if let Some(smtp_cfg) = notify_cfg.smtp.as_ref() {
+            if smtp_cfg.to.is_empty() {
+                Err("smtp to recipients is empty".to_string())
+            } else {
+                state
+                    .notifier
+                    .send_smtp_rich(Some(smtp_cfg), &subject, &msg, Some(&html), None)
+                    .await
+            }
+        } else {
+            Err("smtp not configured".to_string())
+        };
+        let tg_text = {
+            let conn = state.db.lock().unwrap();
+            application_telegram_text(&conn, payload.application_id, "友链申请需人工审核")
+        };
+        let tg_result = match tg_text {
+            Some(text) if notify_cfg.tg_bot_token.is_some() && notify_cfg.tg_chat_id.is_some() => {
+                state
+                    .notifier
+                    .send_telegram(&notify_cfg, &text, Some(payload.application_id))
+                    .await
+            }
+            _ => Err("telegram not configured".to_string()),
+        };
+        if let Err(err) = send_result.as_ref() {
+            tracing::warn!("manual review smtp notify failed: {}", err);
+        }
+        if let Err(err) = tg_result.as_ref() {
+            tracing::warn!("manual review telegram notify failed: {}", err);
+        }
+        if send_result.is_ok() || tg_result.is_ok() {
+            let conn = state.db.lock().unwrap();
+            let _ = conn.execute(
+                "UPDATE friend_link_applications
+                 SET manual_notified = 1, updated_at = ?1
+                 WHERE id = ?2",
+                params![now, payload.application_id],
+            );
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: if should_notify {
+                "已标记为待人工审核并推送提醒".to_string()
+            } else {
+                "已标记为待人工审核（提醒已发送过）".to_string()
+            },
+        }),
+    )
+        .into_response()
+}
+
+async fn links_review_report_tasks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.review_report_token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+
+    let pending_applications = {
+        let mut stmt = match conn.prepare(
+            "SELECT id, site_name, site_url, avatar_url, description, note
+             FROM friend_link_applications
+             WHERE status = 'pending'
+             ORDER BY created_at ASC",
+        ) {
+            Ok(stmt) => stmt,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        let rows = match stmt.query_map([], |row| {
+            Ok(PendingApplicationTask {
+                id: row.get(0)?,
+                site_name: row.get(1)?,
+                site_url: row.get(2)?,
+                avatar_url: row.get(3)?,
+                description: row.get(4)?,
+                note: row.get(5)?,
+            })
+        }) {
+            Ok(rows) => rows,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        rows.filter_map(Result::ok).collect::<Vec<_>>()
+    };
+
+    let active_links = {
+        let mut stmt = match conn.prepare(
+            "SELECT id, url, application_id, backlink_deadline
+             FROM friend_links
+             ORDER BY created_at ASC",
+        ) {
+            Ok(stmt) => stmt,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        let rows = match stmt.query_map([], |row| {
+            Ok(ActiveLinkTask {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                application_id: row.get(2)?,
+                backlink_deadline: row.get(3)?,
+            })
+        }) {
+            Ok(rows) => rows,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        rows.filter_map(Result::ok).collect::<Vec<_>>()
+    };
+
+    let unreachable_whitelist_raw = read_setting(&conn, "unreachable_whitelist_hosts");
+    let unreachable_whitelist = parse_host_list(unreachable_whitelist_raw);
+
+    (
+        StatusCode::OK,
+        Json(ReviewTasksResponse {
+            pending_applications,
+            active_links,
+            now_ts: now_ts(),
+            backlink_target: std::env::var("LINK_BACKLINK_TARGET")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| "https://www.meowra.cn/".to_string()),
+            backlink_enforce_hours: state.auto_review.backlink_window_secs / 3600,
+            unreachable_enforce_hours: std::env::var("LINK_UNREACHABLE_ENFORCE_HOURS")
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(72),
+            unreachable_whitelist,
+        }),
+    )
+        .into_response()
+}
+
+async fn links_review_report_removal(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ReviewRemovalReportPayload>,
+) -> impl IntoResponse {
     if !authorized(&headers, &state.review_report_token) {
         return StatusCode::UNAUTHORIZED.into_response();
     }
-    let conn = state.db.lock().unwrap();
-
-    let pending_applications = {
-        let mut stmt = match conn.prepare(
-            "SELECT id, site_name, site_url, avatar_url, description, note
-             FROM friend_link_applications
-             WHERE status = 'pending'
-             ORDER BY created_at ASC",
-        ) {
-            Ok(stmt) => stmt,
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        };
-        let rows = match stmt.query_map([], |row| {
-            Ok(PendingApplicationTask {
-                id: row.get(0)?,
-                site_name: row.get(1)?,
-                site_url: row.get(2)?,
-                avatar_url: row.get(3)?,
-                description: row.get(4)?,
-                note: row.get(5)?,
-            })
-        }) {
-            Ok(rows) => rows,
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        };
-        rows.filter_map(Result::ok).collect::<Vec<_>>()
+    let now = now_ts();
+    let app_status = payload
+        .app_status
+        .unwrap_or_else(|| "removed_external_review".to_string());
+    let reason = payload
+        .reason
+        .unwrap_or_else(|| "由内网审查服务判定下架".to_string());
+    match remove_link_and_notify(
+        &state,
+        payload.link_id,
+        payload.application_id,
+        &app_status,
+        &reason,
+        now,
+        payload.send_email.unwrap_or(true),
+    )
+    .await
+    {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "已执行下架并同步状态".to_string(),
+            }),
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: format!("下架失败: {}", err),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+async fn perform_review_decision(
+    state: &AppState,
+    payload: LinkReviewPayload,
+    send_email: bool,
+    send_admin_smtp_notify: bool,
+) -> Result<String, String> {
+    let action = payload.action.trim().to_lowercase();
+    if action != "approve" && action != "reject" {
+        return Err("action 仅支持 approve/reject".to_string());
+    }
+
+    let now = now_ts();
+    let incoming_review_note = normalize_optional(payload.review_note, 280);
+    let manual_note_provided = incoming_review_note.is_some();
+    let (site_name, site_url, applicant_email, final_review_note) = {
+        let mut conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
+        let tx = match conn.transaction() {
+            Ok(tx) => tx,
+            Err(_) => return Err("db transaction failed".to_string()),
+        };
+        let app_row = tx
+            .query_row(
+                "SELECT site_name, site_url, avatar_url, description, email, status, review_note
+                 FROM friend_link_applications WHERE id = ?1",
+                params![payload.application_id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, String>(5)?,
+                        row.get::<_, Option<String>>(6)?,
+                    ))
+                },
+            )
+            .ok();
+        let (
+            site_name,
+            site_url,
+            avatar_url,
+            description,
+            applicant_email,
+            status,
+            existing_review_note,
+        ) =
+            match app_row {
+                Some(v) => v,
+                None => return Err("申请记录不存在".to_string()),
+            };
+        if action == "approve" && status == "verify_pending" {
+            return Err("该申请尚未验证，请先验证或手动放行进入自动审查".to_string());
+        }
+        let existing_review_note = normalize_optional(existing_review_note, 560);
+        let final_review_note = match (existing_review_note, incoming_review_note.clone()) {
+            (Some(existing), Some(incoming)) => {
+                if existing == incoming {
+                    Some(existing)
+                } else {
+                    Some(format!("{}\n人工复核：{}", existing, incoming))
+                }
+            }
+            (Some(existing), None) => Some(existing),
+            (None, Some(incoming)) => Some(incoming),
+            (None, None) => None,
+        };
+
+        if action == "approve" {
+            let link_id = format!(
+                "link-{}-{}",
+                slugify_ascii(&site_name),
+                payload.application_id
+            );
+            let manual_tags = normalize_optional(payload.tags, 120);
+            let tags = if send_admin_smtp_notify {
+                merge_link_tags(
+                    manual_tags,
+                    extract_auto_link_tags(
+                        &site_name,
+                        &site_url,
+                        description.as_deref(),
+                        final_review_note.as_deref(),
+                    ),
+                    120,
+                )
+            } else {
+                manual_tags
+            };
+            let sort_order = payload.sort_order.unwrap_or(now);
+            let backlink_window = state.auto_review.backlink_window_secs.max(3600);
+            if tx
+                .execute(
+                    "INSERT INTO friend_links (
+                        id, name, url, avatar_url, description, tags, sort_order, created_at, updated_at,
+                        application_id, backlink_status, backlink_deadline, backlink_checked_at, unreachable_since
+                     )
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8, ?9, 'pending', ?10, NULL, NULL)
+                     ON CONFLICT(id) DO UPDATE SET
+                       name = excluded.name,
+                       url = excluded.url,
+                       avatar_url = excluded.avatar_url,
+                       description = excluded.description,
+                       tags = excluded.tags,
+                       sort_order = excluded.sort_order,
+                       updated_at = excluded.updated_at,
+                       application_id = excluded.application_id,
+                       backlink_status = excluded.backlink_status,
+                       backlink_deadline = excluded.backlink_deadline,
+                       backlink_checked_at = excluded.backlink_checked_at,
+                       unreachable_since = excluded.unreachable_since",
+                    params![
+                        link_id,
+                        site_name,
+                        site_url,
+                        avatar_url,
+                        description,
+                        tags,
+                        sort_order,
+                        now,
+                        payload.application_id,
+                        now + backlink_window,
+                    ],
+                )
+                .is_err()
+            {
+                return Err("写入友链失败".to_string());
+            }
+        }
+
+        if tx
+            .execute(
+                "UPDATE friend_link_applications
+                 SET status = ?1, review_note = ?2, updated_at = ?3
+                 WHERE id = ?4",
+                params![
+                    action,
+                    final_review_note.clone(),
+                    now,
+                    payload.application_id
+                ],
+            )
+            .is_err()
+        {
+            return Err("更新申请状态失败".to_string());
+        }
+        if tx.commit().is_err() {
+            return Err("事务提交失败".to_string());
+        }
+        state.cache.invalidate("links");
+        (site_name, site_url, applicant_email, final_review_note)
+    };
+
+    let applicant_email = normalize_optional(applicant_email, 128);
+    let mail_note: String;
+    if let Some(email) = applicant_email.as_deref() {
+        if !send_email {
+            mail_note = "（邮件通知已跳过）".to_string();
+        } else {
+            let notify_cfg = {
+                let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
+                state.notifier.runtime_config(&conn)
+            };
+            if let Err(err) = state
+                .notifier
+                .notify_review_result_email(
+                    notify_cfg.smtp.as_ref(),
+                    email,
+                    &site_name,
+                    &site_url,
+                    &action,
+                    final_review_note.as_deref(),
+                    !send_admin_smtp_notify && manual_note_provided,
+                )
+                .await
+            {
+                tracing::warn!("review result mail failed: {}", err);
+                mail_note = "（邮件通知失败）".to_string();
+            } else {
+                mail_note = "（邮件通知已发送）".to_string();
+            }
+        }
+    } else {
+        mail_note = "（申请方未提供邮箱）".to_string();
+    }
+
+    if send_admin_smtp_notify {
+        spawn_telegram_notify(
+            state,
+            format!(
+                "自动审核结果：{}\n#{} {}\n{}\n邮箱：{}\n备注：{}",
+                if action == "approve" {
+                    "通过"
+                } else {
+                    "拒绝"
+                },
+                payload.application_id,
+                site_name,
+                site_url,
+                applicant_email.as_deref().unwrap_or("-"),
+                final_review_note.as_deref().unwrap_or("-")
+            ),
+            None,
+        );
+        let notify_cfg = {
+            let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
+            state.notifier.runtime_config(&conn)
+        };
+        if let Some(smtp_cfg) = notify_cfg.smtp.as_ref() {
+            if !smtp_cfg.to.is_empty() {
+                let subject = format!(
+                    "Auto review result: {} ({})",
+                    if action == "approve" {
+                        "APPROVE"
+                    } else {
+                        "REJECT"
+                    },
+                    site_name
+                );
+                let body = format!(
+                    "Auto review result\napplication_id: {}\naction: {}\nsite: {}\nurl: {}\nemail: {}\nreview_note: {}",
+                    payload.application_id,
+                    action,
+                    site_name,
+                    site_url,
+                    applicant_email.as_deref().unwrap_or("-"),
+                    final_review_note.as_deref().unwrap_or("-")
+                );
+                let html = build_auto_review_admin_html(
+                    payload.application_id,
+                    &action,
+                    &site_name,
+                    &site_url,
+                    applicant_email.as_deref(),
+                    final_review_note.as_deref().unwrap_or("-"),
+                );
+                if let Err(err) = state
+                    .notifier
+                    .send_smtp_rich(Some(smtp_cfg), &subject, &body, Some(&html), None)
+                    .await
+                {
+                    tracing::warn!("auto review admin smtp notify failed: {}", err);
+                }
+            }
+        }
+    }
+
+    Ok(if action == "approve" {
+        format!("已通过并加入友链列表{}", mail_note)
+    } else {
+        format!("已拒绝该申请{}", mail_note)
+    })
+}
+
+async fn links_sort(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkSortPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let mut conn = state.db.lock().unwrap();
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    for item in payload.items {
+        if tx
+            .execute(
+                "UPDATE friend_links SET sort_order = ?1, updated_at = ?2 WHERE id = ?3",
+                params![item.sort_order, now, item.id],
+            )
+            .is_err()
+        {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    if tx.commit().is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    state.cache.invalidate("links");
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: "排序已更新".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+async fn links_update(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkUpdatePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let id = payload.id.trim();
+    if id.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "id 不能为空".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > 32 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "名称长度需在 1-32 字符内".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let url = payload.url.trim();
+    if !is_valid_http_url(url) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "URL 需为 http/https".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let avatar_url = normalize_optional(payload.avatar_url, 255);
+    if avatar_url
+        .as_deref()
+        .is_some_and(|value| !is_valid_http_url(value))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "头像 URL 需为 http/https".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let description = normalize_optional(payload.description, 280);
+    let tags = normalize_optional(payload.tags, 120);
+    let sort_order = payload.sort_order.unwrap_or(0);
+
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let updated = conn
+        .execute(
+            "UPDATE friend_links
+             SET name = ?1, url = ?2, avatar_url = ?3, description = ?4, tags = ?5, sort_order = ?6,
+                 updated_at = ?7
+             WHERE id = ?8",
+            params![
+                name,
+                url,
+                avatar_url,
+                description,
+                tags,
+                sort_order,
+                now,
+                id
+            ],
+        )
+        .unwrap_or(0);
+    if updated == 0 {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiMessage {
+                message: "友链不存在".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    state.cache.invalidate("links");
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: "友链已更新".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+async fn links_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkDeletePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let id = payload.id.trim();
+    if id.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "id 不能为空".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let deleted = conn
+        .execute("DELETE FROM friend_links WHERE id = ?1", params![id])
+        .unwrap_or(0);
+    if deleted == 0 {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiMessage {
+                message: "友链不存在".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    state.cache.invalidate("links");
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: "友链已删除".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+async fn links_review_stage_cancel(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkReviewStageCancelPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let id = payload.id.trim();
+    if id.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "id 不能为空".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let now = now_ts();
+    let conn = state.db.lock().unwrap();
+    let updated = conn
+        .execute(
+            "UPDATE friend_links
+             SET backlink_status = 'manual_skip_backlink', backlink_deadline = NULL,
+                 backlink_checked_at = ?1, updated_at = ?1
+             WHERE id = ?2",
+            params![now, id],
+        )
+        .unwrap_or(0);
+    if updated == 0 {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiMessage {
+                message: "友链不存在".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    state.cache.invalidate("links");
+    (
+        StatusCode::OK,
+        Json(ApiMessage {
+            message: "已取消该站点的 24h 回链审查阶段".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+async fn links_settings_get(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let settings = build_link_settings_response(&state, &conn);
+    (StatusCode::OK, Json(settings)).into_response()
+}
+
+async fn links_settings_set(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LinkSettingsPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let now = now_ts();
+    let mut conn = state.db.lock().unwrap();
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let updates = [
+        (
+            "tg_bot_token",
+            payload
+                .tg_bot_token
+                .map(|v| v.trim().chars().take(256).collect::<String>()),
+        ),
+        (
+            "tg_chat_id",
+            payload
+                .tg_chat_id
+                .map(|v| v.trim().chars().take(128).collect::<String>()),
+        ),
+        (
+            "smtp_host",
+            payload
+                .smtp_host
+                .map(|v| v.trim().chars().take(255).collect::<String>()),
+        ),
+        ("smtp_port", payload.smtp_port.map(|v| v.to_string())),
+        (
+            "smtp_user",
+            payload
+                .smtp_user
+                .map(|v| v.trim().chars().take(255).collect::<String>()),
+        ),
+        ("smtp_pass", payload.smtp_pass.map(|v| v.trim().to_string())),
+        (
+            "smtp_from",
+            payload
+                .smtp_from
+                .map(|v| v.trim().chars().take(255).collect::<String>()),
+        ),
+        (
+            "smtp_to",
+            payload
+                .smtp_to
+                .map(|v| v.trim().chars().take(512).collect::<String>()),
+        ),
+        (
+            "smtp_starttls",
+            payload
+                .smtp_starttls
+                .map(|v| if v { "1".to_string() } else { "0".to_string() }),
+        ),
+        (
+            "captcha_provider",
+            payload
+                .captcha_provider
+                .map(|v| v.trim().chars().take(32).collect::<String>()),
+        ),
+        (
+            "captcha_site_key",
+            payload
+                .captcha_site_key
+                .map(|v| v.trim().chars().take(255).collect::<String>()),
+        ),
+        (
+            "captcha_secret",
+            payload.captcha_secret.map(|v| {
+                let trimmed = v.trim();
+                if trimmed == "-" {
+                    String::new()
+                } else {
+                    trimmed.chars().take(255).collect::<String>()
+                }
+            }),
+        ),
+        (
+            "apply_rate_limit_window_sec",
+            payload
+                .apply_rate_limit_window_sec
+                .map(|v| v.clamp(60, 86400).to_string()),
+        ),
+        (
+            "apply_rate_limit_max",
+            payload
+                .apply_rate_limit_max
+                .map(|v| v.clamp(1, 30).to_string()),
+        ),
+        (
+            "apply_rate_limit_prefix_max",
+            payload
+                .apply_rate_limit_prefix_max
+                .map(|v| v.clamp(1, 80).to_string()),
+        ),
+        (
+            "apply_rate_limit_email_domain_max",
+            payload
+                .apply_rate_limit_email_domain_max
+                .map(|v| v.clamp(1, 60).to_string()),
+        ),
+        (
+            "apply_rate_limit_site_host_max",
+            payload
+                .apply_rate_limit_site_host_max
+                .map(|v| v.clamp(1, 30).to_string()),
+        ),
+        (
+            "block_disposable_email",
+            payload
+                .block_disposable_email
+                .map(|v| if v { "1".to_string() } else { "0".to_string() }),
+        ),
+        (
+            "block_edu_gov_email",
+            payload
+                .block_edu_gov_email
+                .map(|v| if v { "1".to_string() } else { "0".to_string() }),
+        ),
+        (
+            "apply_deny_hosts",
+            payload
+                .apply_deny_hosts
+                .map(|v| v.trim().chars().take(1024).collect::<String>()),
+        ),
+        (
+            "verify_window_minutes",
+            payload
+                .verify_window_minutes
+                .map(|v| v.clamp(10, 1440).to_string()),
+        ),
+        (
+            "public_base_url",
+            payload
+                .public_base_url
+                .map(|v| v.trim().chars().take(255).collect::<String>()),
+        ),
+        (
+            "unreachable_whitelist_hosts",
+            payload
+                .unreachable_whitelist_hosts
+                .map(|v| v.trim().chars().take(1024).collect::<String>()),
+        ),
+    ];
+    for (key, value) in updates {
+        if let Some(value) = value {
+            if value.is_empty() {
+                if tx
+                    .execute(
+                        "DELETE FROM friend_link_settings WHERE key = ?1",
+                        params![key],
+                    )
+                    .is_err()
+                {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            } else if tx
+                .execute(
+                    "INSERT INTO friend_link_settings (key, value, updated_at)
+                     VALUES (?1, ?2, ?3)
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+                    params![key, value, now],
+                )
+                .is_err()
+            {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    if tx.commit().is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let settings = build_link_settings_response(&state, &conn);
+    (StatusCode::OK, Json(settings)).into_response()
+}
+
+async fn links_apply_config(State(state): State<AppState>) -> impl IntoResponse {
+    let (provider, site_key, secret_set) = {
+        let conn = state.db.lock().unwrap();
+        let provider = read_setting(&conn, "captcha_provider")
+            .or_else(|| {
+                state
+                    .anti_abuse
+                    .captcha
+                    .as_ref()
+                    .map(|cfg| cfg.provider.as_str().to_string())
+            })
+            .unwrap_or_default();
+        let site_key = read_setting(&conn, "captcha_site_key")
+            .or_else(|| state.anti_abuse.captcha.as_ref().map(|cfg| cfg.site_key.clone()))
+            .unwrap_or_default();
+        let secret_set = if provider.trim().eq_ignore_ascii_case("altcha") {
+            true
+        } else {
+            read_setting(&conn, "captcha_secret")
+                .or_else(|| state.anti_abuse.captcha.as_ref().map(|cfg| cfg.secret.clone()))
+                .map(|v| !v.is_empty())
+                .unwrap_or(false)
+        };
+        (provider, site_key, secret_set)
+    };
+    let provider = provider.trim().to_string();
+    let site_key = site_key.trim().to_string();
+    let captcha_enabled = !provider.is_empty() && !site_key.is_empty();
+    let response = LinkApplyConfigResponse {
+        captcha_provider: if provider.is_empty() { None } else { Some(provider) },
+        captcha_site_key: if site_key.is_empty() { None } else { Some(site_key) },
+        captcha_enabled,
+        captcha_secret_set: Some(secret_set),
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+async fn links_settings_test_smtp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<SmtpTestPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let recipient = normalize_optional(payload.recipient, 255);
+    let notify_cfg = {
+        let conn = state.db.lock().unwrap();
+        state.notifier.runtime_config(&conn)
+    };
+    if notify_cfg.smtp.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "SMTP 未配置完整，请先保存 SMTP Host/From/To".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    let now_local = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let subject = "SMTP test from Meow Links Admin";
+    let body = format!(
+        "这是一封 SMTP 测试邮件。\n\n发送时间：{}\n服务：status-backend\n说明：若收到此邮件，表示当前 SMTP 配置可用。",
+        now_local
+    );
+    let override_to = recipient.map(|v| vec![v.clone()]);
+    let send_result = state
+        .notifier
+        .send_smtp(notify_cfg.smtp.as_ref(), subject, &body, override_to)
+        .await;
+    match send_result {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "SMTP 测试邮件发送成功".to_string(),
+            }),
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::BAD_GATEWAY,
+            Json(ApiMessage {
+                message: format!("SMTP 测试失败: {}", err),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+fn spawn_telegram_notify(state: &AppState, text: String, review_application_id: Option<i64>) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let notify_cfg = {
+            let conn = state.db.lock().unwrap();
+            state.notifier.runtime_config(&conn)
+        };
+        if notify_cfg.tg_bot_token.is_none() || notify_cfg.tg_chat_id.is_none() {
+            return;
+        }
+        if let Err(err) = state
+            .notifier
+            .send_telegram(&notify_cfg, &text, review_application_id)
+            .await
+        {
+            tracing::warn!("telegram notify failed: {}", err);
+        }
+    });
+}
+
+fn application_telegram_text(
+    conn: &Connection,
+    application_id: i64,
+    headline: &str,
+) -> Option<String> {
+    conn.query_row(
+        "SELECT site_name, site_url, email, description, review_note
+         FROM friend_link_applications WHERE id = ?1",
+        params![application_id],
+        |row| {
+            Ok(format!(
+                "{}\n#{} {}\n{}\n邮箱：{}\n简介：{}\n备注：{}",
+                headline,
+                application_id,
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?
+                    .unwrap_or_else(|| "-".to_string()),
+                row.get::<_, Option<String>>(3)?
+                    .unwrap_or_else(|| "-".to_string()),
+                row.get::<_, Option<String>>(4)?
+                    .unwrap_or_else(|| "-".to_string()),
+            ))
+        },
+    )
+    .ok()
+}
+
+async fn telegram_update_worker(state: AppState) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(TELEGRAM_POLL_TIMEOUT_SECS + 10))
+        .build()
+    {
+        Ok(c) => c,
+        Err(err) => {
+            tracing::error!("telegram client init failed: {}", err);
+            return;
+        }
+    };
+    let mut offset: i64 = 0;
+    loop {
+        let notify_cfg = {
+            let conn = state.db.lock().unwrap();
+            state.notifier.runtime_config(&conn)
+        };
+        let (Some(bot_token), Some(chat_id)) = (notify_cfg.tg_bot_token, notify_cfg.tg_chat_id)
+        else {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            continue;
+        };
+        let updates = state
+            .notifier
+            .telegram_api::<Vec<TelegramUpdate>>(
+                &client,
+                &bot_token,
+                "getUpdates",
+                serde_json::json!({
+                    "offset": offset,
+                    "timeout": TELEGRAM_POLL_TIMEOUT_SECS,
+                    "allowed_updates": ["callback_query"],
+                }),
+            )
+            .await;
+        let updates = match updates {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::warn!("telegram getUpdates failed: {}", err);
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                continue;
+            }
+        };
+        for update in updates {
+            offset = offset.max(update.update_id + 1);
+            if let Some(query) = update.callback_query {
+                handle_telegram_callback(&state, &client, &bot_token, &chat_id, query).await;
+            }
+        }
+    }
+}
+
+async fn handle_telegram_callback(
+    state: &AppState,
+    client: &reqwest::Client,
+    bot_token: &str,
+    chat_id: &str,
+    query: TelegramCallbackQuery,
+) {
+    let answer = |text: String, alert: bool| {
+        state.notifier.telegram_api::<serde_json::Value>(
+            client,
+            bot_token,
+            "answerCallbackQuery",
+            serde_json::json!({
+                "callback_query_id": query.id,
+                "text": text.chars().take(190).collect::<String>(),
+                "show_alert": alert,
+            }),
+        )
+    };
+    let Some(message) = query.message.as_ref() else {
+        let _ = answer("消息已失效".to_string(), true).await;
+        return;
+    };
+    if message.chat.id.to_string() != chat_id.trim() {
+        tracing::warn!(
+            "telegram callback from unauthorized chat: chat_id={} user_id={}",
+            message.chat.id,
+            query.from.id
+        );
+        let _ = answer("无权限".to_string(), true).await;
+        return;
+    }
+    let decision = query
+        .data
+        .as_deref()
+        .and_then(|v| v.split_once(':'))
+        .and_then(|(action, id)| {
+            let id = id.parse::<i64>().ok()?;
+            matches!(action, "approve" | "reject").then(|| (action.to_string(), id))
+        });
+    let Some((action, application_id)) = decision else {
+        let _ = answer("未知操作".to_string(), true).await;
+        return;
+    };
+    let reviewer = query
+        .from
+        .username
+        .as_ref()
+        .map(|v| format!("@{}", v))
+        .or_else(|| query.from.first_name.clone())
+        .unwrap_or_else(|| query.from.id.to_string());
+    tracing::info!(
+        "telegram review decision: application_id={} action={} by={}",
+        application_id,
+        action,
+        reviewer
+    );
+    let result = perform_review_decision(
+        state,
+        LinkReviewPayload {
+            application_id,
+            action,
+            sort_order: None,
+            tags: None,
+            review_note: None,
+        },
+        true,
+        false,
+    )
+    .await;
+    match result {
+        Ok(msg) => {
+            let _ = answer(msg.clone(), false).await;
+            let text = format!(
+                "{}\n\n{} 由 {} 处理：{}",
+                message.text.as_deref().unwrap_or_default(),
+                if msg.starts_with("已通过") {
+                    "✅"
+                } else {
+                    "❌"
+                },
+                reviewer,
+                msg
+            );
+            let _ = state
+                .notifier
+                .telegram_api::<serde_json::Value>(
+                    client,
+                    bot_token,
+                    "editMessageText",
+                    serde_json::json!({
+                        "chat_id": message.chat.id,
+                        "message_id": message.message_id,
+                        "text": text,
+                        "disable_web_page_preview": true,
+                    }),
+                )
+                .await;
+        }
+        Err(err) => {
+            let _ = answer(err, true).await;
+        }
+    }
+}
+
+async fn links_settings_test_telegram(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let notify_cfg = {
+        let conn = state.db.lock().unwrap();
+        state.notifier.runtime_config(&conn)
+    };
+    if notify_cfg.tg_bot_token.is_none() || notify_cfg.tg_chat_id.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "Telegram 未配置完整，请先保存 Bot Token 与 Chat ID".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let now_local = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let text = format!(
+        "这是一条 Telegram 测试消息。\n发送时间：{}\n服务：status-backend",
+        now_local
+    );
+    match state.notifier.send_telegram(&notify_cfg, &text, None).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiMessage {
+                message: "Telegram 测试消息发送成功".to_string(),
+            }),
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::BAD_GATEWAY,
+            Json(ApiMessage {
+                message: format!("Telegram 测试失败: {}", err),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+async fn remove_link_and_notify(
+    state: &AppState,
+    link_id: String,
+    application_id: Option<i64>,
+    app_status: &str,
+    review_note: &str,
+    now: i64,
+    send_email: bool,
+) -> Result<(), String> {
+    let applicant = if let Some(app_id) = application_id {
+        let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
+        conn.query_row(
+            "SELECT email, site_name, site_url FROM friend_link_applications WHERE id = ?1",
+            params![app_id],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            },
+        )
+        .ok()
+    } else {
+        None
+    };
+
+    let removed_text = format!(
+        "友链已下架\n{}\n{}\n原因：{}",
+        applicant
+            .as_ref()
+            .map(|v| v.1.as_str())
+            .unwrap_or(link_id.as_str()),
+        applicant.as_ref().map(|v| v.2.as_str()).unwrap_or("-"),
+        review_note
+    );
+    {
+        let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
+        let _ = conn.execute("DELETE FROM friend_links WHERE id = ?1", params![link_id]);
+        state.cache.invalidate("links");
+        if let Some(app_id) = application_id {
+            let _ = conn.execute(
+                "UPDATE friend_link_applications
+                 SET status = ?1, review_note = ?2, updated_at = ?3
+                 WHERE id = ?4",
+                params![app_status, review_note, now, app_id],
+            );
+        }
+    }
+
+    if send_email {
+        if let Some((email, app_name, app_url)) = applicant {
+            if let Some(email) = normalize_optional(email, 128) {
+                let notify_cfg = {
+                    let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
+                    state.notifier.runtime_config(&conn)
+                };
+                if let Err(err) = state
+                    .notifier
+                    .notify_review_result_email(
+                        notify_cfg.smtp.as_ref(),
+                        &email,
+                        &app_name,
+                        &app_url,
+                        "reject",
+                        Some(review_note),
+                        false,
+                    )
+                    .await
+                {
+                    tracing::warn!("link removal mail failed: {}", err);
+                }
+            }
+        }
+    }
+    spawn_telegram_notify(state, removed_text, None);
+    Ok(())
+}
+
+async fn visitor_visit(
+    State(state): State<AppState>,
+    Json(payload): Json<VisitPayload>,
+) -> impl IntoResponse {
+    let now = now_ts();
+    let today = today_key();
+    let conn = state.db.lock().unwrap();
+    let inserted = conn
+        .execute(
+            "INSERT OR IGNORE INTO visitor_visits (visitor_id, visit_date, created_at)
+             VALUES (?1, ?2, ?3)",
+            params![payload.visitor_id, today, now],
+        )
+        .unwrap_or(0);
+    if inserted > 0 {
+        state.cache.invalidate("visitor");
+    }
+    StatusCode::OK
+}
+
+async fn visitor_stats(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let today = today_key();
+    let key = format!("visitor:{}", today);
+    let snapshot = match state.cache.get(&key) {
+        Some(snapshot) => snapshot,
+        None => {
+            let conn = state.db.lock().unwrap();
+            let last_modified: i64 = conn
+                .query_row(
+                    "SELECT COALESCE(MAX(created_at), 0) FROM visitor_visits",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap_or(0);
+            let stats = load_visitor_stats(&conn, &today, now_ts());
+            state.cache.store(&key, &stats, last_modified)
+        }
+    };
+    cache::respond(&headers, &snapshot, &state.live_cache_control)
+}
+
+fn load_visitor_stats(conn: &Connection, today: &str, now: i64) -> VisitorStats {
+    let month_prefix = month_key();
+    let today_count: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM visitor_visits WHERE visit_date = ?1",
+            params![today],
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+    let month_count: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM visitor_visits WHERE visit_date LIKE ?1",
+            params![format!("{}-%", month_prefix)],
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+    let total_count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM visitor_visits", [], |row| row.get(0))
+        .unwrap_or(0);
+    VisitorStats {
+        today: today_count,
+        month: month_count,
+        total: total_count,
+        updated_at: now,
+    }
+}
+
+#[derive(Deserialize)]
+struct DevicePresenceUpdatePayload {
+    device_id: String,
+    stale_after_secs: Option<i64>,
+    idle_away_secs: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct DevicePresenceDefaults {
+    stale_after_secs: i64,
+    idle_away_secs: Option<i64>,
+}
+
+async fn device_presence_defaults(State(state): State<AppState>) -> impl IntoResponse {
+    Json(DevicePresenceDefaults {
+        stale_after_secs: state.presence.stale_after_secs,
+        idle_away_secs: state.presence.idle_away_secs,
+    })
+}
+
+async fn device_presence_update(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DevicePresenceUpdatePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let stale_after_secs = payload
+        .stale_after_secs
+        .map(|v| v.clamp(MIN_DEVICE_STALE_SECS, MAX_DEVICE_STALE_SECS));
+    let idle_away_secs = payload.idle_away_secs.map(|v| v.clamp(0, 7 * 24 * 3600));
+    let conn = state.db.lock().unwrap();
+    let changed = conn
+        .execute(
+            "UPDATE device_status SET stale_after_secs = ?1, idle_away_secs = ?2 WHERE device_id = ?3",
+            params![stale_after_secs, idle_away_secs, payload.device_id.trim()],
+        )
+        .unwrap_or(0);
+    if changed == 0 {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiMessage {
+                message: "设备不存在".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    state.hub.publish_changes(&conn, &state.presence);
+    StatusCode::OK.into_response()
+}
+
+#[derive(Deserialize)]
+struct DeviceEnrollPayload {
+    device_id: String,
+    device_name: Option<String>,
+    ttl_secs: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct DeviceEnrollResponse {
+    device_id: String,
+    code: String,
+    expires_at: i64,
+}
+
+#[derive(Deserialize)]
+struct DeviceEnrollRedeemPayload {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct DeviceEnrollRedeemResponse {
+    device_id: String,
+    device_name: Option<String>,
+    token: String,
+    signing_secret: String,
+}
+
+#[derive(Serialize)]
+struct DeviceCredentialInfo {
+    device_id: String,
+    created_at: i64,
+    last_used_at: Option<i64>,
+    revoked_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct DeviceCredentialRevokePayload {
+    device_id: String,
+}
+
+async fn device_enroll(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DeviceEnrollPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(device_id) = normalize_optional(Some(payload.device_id), 128) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "device_id 不能为空".to_string(),
+            }),
+        )
+            .into_response();
     };
+    let device_name = normalize_optional(payload.device_name, 128);
+    let ttl = payload
+        .ttl_secs
+        .unwrap_or(ENROLLMENT_CODE_DEFAULT_TTL_SECS)
+        .clamp(60, 7 * 24 * 3600);
+    let now = now_ts();
+    let code = generate_enrollment_code();
+    let conn = state.db.lock().unwrap();
+    let _ = conn.execute(
+        "DELETE FROM device_enrollment_codes WHERE expires_at < ?1 OR used_at IS NOT NULL",
+        params![now],
+    );
+    if conn
+        .execute(
+            "INSERT INTO device_enrollment_codes (code_hash, device_id, device_name, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![hash_secret(&code), device_id, device_name, now, now + ttl],
+        )
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        StatusCode::OK,
+        Json(DeviceEnrollResponse {
+            device_id,
+            code,
+            expires_at: now + ttl,
+        }),
+    )
+        .into_response()
+}
 
-    let active_links = {
-        let mut stmt = match conn.prepare(
-            "SELECT id, url, application_id, backlink_deadline
-             FROM friend_links
-             ORDER BY created_at ASC",
-        ) {
-            Ok(stmt) => stmt,
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        };
-        let rows = match stmt.query_map([], |row| {
-            Ok(ActiveLinkTask {
-                id: row.get(0)?,
-                url: row.get(1)?,
-                application_id: row.get(2)?,
-                backlink_deadline: row.get(3)?,
-            })
-        }) {
-            Ok(rows) => rows,
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        };
-        rows.filter_map(Result::ok).collect::<Vec<_>>()
+async fn device_enroll_redeem(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DeviceEnrollRedeemPayload>,
+) -> impl IntoResponse {
+    let code = payload.code.trim().to_uppercase();
+    let now = now_ts();
+    let ip = client_ip(&headers).unwrap_or_else(|| "unknown".to_string());
+    let conn = state.db.lock().unwrap();
+    let _ = conn.execute(
+        "DELETE FROM device_enroll_failures WHERE created_at < ?1",
+        params![now - ENROLL_REDEEM_WINDOW_SECS],
+    );
+    let (ip_failures, failures): (i64, i64) = conn
+        .query_row(
+            "SELECT COALESCE(SUM(ip = ?1), 0), COUNT(*) FROM device_enroll_failures",
+            params![ip],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap_or((0, 0));
+    if ip_failures >= ENROLL_REDEEM_IP_MAX_FAILURES || failures >= ENROLL_REDEEM_MAX_FAILURES {
+        tracing::warn!("enrollment redeem throttled: ip={}", ip);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiMessage {
+                message: "尝试过于频繁，请稍后再试".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let found = conn
+        .query_row(
+            "SELECT device_id, device_name FROM device_enrollment_codes
+             WHERE code_hash = ?1 AND used_at IS NULL AND expires_at >= ?2",
+            params![hash_secret(&code), now],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+        )
+        .ok();
+    let Some((device_id, device_name)) = found else {
+        let _ = conn.execute(
+            "INSERT INTO device_enroll_failures (ip, created_at) VALUES (?1, ?2)",
+            params![ip, now],
+        );
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiMessage {
+                message: "注册码无效或已过期".to_string(),
+            }),
+        )
+            .into_response();
     };
-
-    let unreachable_whitelist_raw = read_setting(&conn, "unreachable_whitelist_hosts");
-    let unreachable_whitelist = parse_host_list(unreachable_whitelist_raw);
-
+    let _ = conn.execute(
+        "UPDATE device_enrollment_codes SET used_at = ?1 WHERE code_hash = ?2",
+        params![now, hash_secret(&code)],
+    );
+    let token = generate_device_secret("dt");
+    let signing_secret = generate_device_secret("ds");
+    if conn
+        .execute(
+            "INSERT INTO device_credentials (device_id, token_hash, signing_secret, created_at, last_used_at, revoked_at)
+             VALUES (?1, ?2, ?3, ?4, NULL, NULL)
+             ON CONFLICT(device_id) DO UPDATE SET
+               token_hash = excluded.token_hash,
+               signing_secret = excluded.signing_secret,
+               created_at = excluded.created_at,
+               last_used_at = NULL,
+               revoked_at = NULL",
+            params![device_id, hash_secret(&token), signing_secret, now],
+        )
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    tracing::info!("device enrolled: device_id={}", device_id);
     (
         StatusCode::OK,
-        Json(ReviewTasksResponse {
-            pending_applications,
-            active_links,
-            now_ts: now_ts(),
-            backlink_target: std::env::var("LINK_BACKLINK_TARGET")
-                .ok()
-                .filter(|v| !v.trim().is_empty())
-                .unwrap_or_else(|| "https://www.meowra.cn/".to_string()),
-            backlink_enforce_hours: state.auto_review.backlink_window_secs / 3600,
-            unreachable_enforce_hours: std::env::var("LINK_UNREACHABLE_ENFORCE_HOURS")
-                .ok()
-                .and_then(|v| v.parse::<i64>().ok())
-                .unwrap_or(72),
-            unreachable_whitelist,
+        Json(DeviceEnrollRedeemResponse {
+            device_id,
+            device_name,
+            token,
+            signing_secret,
         }),
     )
         .into_response()
 }
 
-async fn links_review_report_removal(
+async fn device_credentials_list(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<ReviewRemovalReportPayload>,
 ) -> impl IntoResponse {
-    if !authorized(&headers, &state.review_report_token) {
+    if !authorized(&headers, &state.token) {
         return StatusCode::UNAUTHORIZED.into_response();
     }
-    let now = now_ts();
-    let app_status = payload
-        .app_status
-        .unwrap_or_else(|| "removed_external_review".to_string());
-    let reason = payload
-        .reason
-        .unwrap_or_else(|| "由内网审查服务判定下架".to_string());
-    match remove_link_and_notify(
-        &state,
-        payload.link_id,
-        payload.application_id,
-        &app_status,
-        &reason,
-        now,
-        payload.send_email.unwrap_or(true),
-    )
-    .await
-    {
-        Ok(_) => (
-            StatusCode::OK,
+    let conn = state.db.lock().unwrap();
+    let items = conn
+        .prepare(
+            "SELECT device_id, created_at, last_used_at, revoked_at
+             FROM device_credentials
+             ORDER BY device_id ASC",
+        )
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map([], |row| {
+                Ok(DeviceCredentialInfo {
+                    device_id: row.get(0)?,
+                    created_at: row.get(1)?,
+                    last_used_at: row.get(2)?,
+                    revoked_at: row.get(3)?,
+                })
+            })?;
+            Ok(rows.filter_map(Result::ok).collect::<Vec<_>>())
+        })
+        .unwrap_or_default();
+    Json(items).into_response()
+}
+
+async fn device_credentials_revoke(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DeviceCredentialRevokePayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let changed = conn
+        .execute(
+            "UPDATE device_credentials SET revoked_at = ?1
+             WHERE device_id = ?2 AND revoked_at IS NULL",
+            params![now_ts(), payload.device_id.trim()],
+        )
+        .unwrap_or(0);
+    if changed == 0 {
+        return (
+            StatusCode::NOT_FOUND,
             Json(ApiMessage {
-                message: "已执行下架并同步状态".to_string(),
+                message: "未找到有效的设备凭据".to_string(),
             }),
         )
-            .into_response(),
-        Err(err) => (
+            .into_response();
+    }
+    tracing::info!(
+        "device credential revoked: device_id={}",
+        payload.device_id.trim()
+    );
+    StatusCode::OK.into_response()
+}
+
+async fn devices_list(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    Json(load_device_admin_items(&conn, &state.presence, now_ts())).into_response()
+}
+
+async fn device_patch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(device_id): axum::extract::Path<String>,
+    Json(payload): Json<DevicePatchPayload>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let bad_request = |message: &str| {
+        (
             StatusCode::BAD_REQUEST,
             Json(ApiMessage {
-                message: format!("下架失败: {}", err),
+                message: message.to_string(),
             }),
         )
-            .into_response(),
+            .into_response()
+    };
+    let kind = payload.kind.map(|v| v.trim().to_lowercase());
+    if let Some(kind) = kind.as_deref() {
+        if !kind.is_empty() && !DEVICE_KINDS.contains(&kind) {
+            return bad_request("设备类型只能是 pc / phone / server / tablet");
+        }
+    }
+    let icon = payload.icon.map(|v| v.trim().to_string());
+    if let Some(icon) = icon.as_deref() {
+        if icon.chars().count() > 512 || (icon.contains("://") && !is_valid_http_url(icon)) {
+            return bad_request("图标需为 Emoji 或 http/https 地址");
+        }
+    }
+    let display_name = payload.display_name.map(|v| v.trim().to_string());
+    if display_name
+        .as_deref()
+        .is_some_and(|v| v.chars().count() > 64)
+    {
+        return bad_request("显示名称最长 64 字");
+    }
+    let conn = state.db.lock().unwrap();
+    let existing = conn
+        .query_row(
+            "SELECT display_name, kind, icon, sort_order, hidden FROM device_status
+             WHERE device_id = ?1",
+            params![device_id],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i32>(4)? == 1,
+                ))
+            },
+        )
+        .ok();
+    let Some((old_display_name, old_kind, old_icon, old_sort_order, old_hidden)) = existing else {
+        return device_not_found();
+    };
+    let merge = |value: Option<String>, old: Option<String>| match value {
+        Some(v) if v.is_empty() => None,
+        Some(v) => Some(v),
+        None => old,
+    };
+    let _ = conn.execute(
+        "UPDATE device_status
+         SET display_name = ?1, kind = ?2, icon = ?3, sort_order = ?4, hidden = ?5
+         WHERE device_id = ?6",
+        params![
+            merge(display_name, old_display_name),
+            merge(kind, old_kind),
+            merge(icon, old_icon),
+            payload.sort_order.unwrap_or(old_sort_order),
+            payload.hidden.unwrap_or(old_hidden) as i32,
+            device_id,
+        ],
+    );
+    tracing::info!("device metadata updated: device_id={}", device_id);
+    state.hub.publish_changes(&conn, &state.presence);
+    match load_device_admin_items(&conn, &state.presence, now_ts())
+        .into_iter()
+        .find(|v| v.status.device_id == device_id)
+    {
+        Some(item) => Json(item).into_response(),
+        None => device_not_found(),
+    }
+}
+
+async fn device_delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(device_id): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &state.token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let conn = state.db.lock().unwrap();
+    let removed = conn
+        .execute(
+            "DELETE FROM device_status WHERE device_id = ?1",
+            params![device_id],
+        )
+        .unwrap_or(0);
+    if removed == 0 {
+        return device_not_found();
     }
+    tracing::info!("device removed: device_id={}", device_id);
+    state.hub.publish_changes(&conn, &state.presence);
+    StatusCode::OK.into_response()
 }
 
-async fn perform_review_decision(
-    state: &AppState,
-    payload: LinkReviewPayload,
-    send_email: bool,
-    send_admin_smtp_notify: bool,
-) -> Result<String, String> {
-    let action = payload.action.trim().to_lowercase();
-    if action != "approve" && action != "reject" {
-        return Err("action 仅支持 approve/reject".to_string());
-    }
+fn load_device_admin_items(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    now: i64,
+) -> Vec<DeviceAdminItem> {
+    let names: HashMap<String, (String, Option<String>)> = conn
+        .prepare("SELECT device_id, device_name, display_name FROM device_status")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+                .map(|rows| rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+    load_all_device_statuses(conn, presence, now)
+        .into_iter()
+        .map(|status| {
+            let (reported_name, display_name) = names
+                .get(&status.device_id)
+                .cloned()
+                .unwrap_or_else(|| (status.device_name.clone(), None));
+            DeviceAdminItem {
+                hidden: status.hidden,
+                status,
+                reported_name,
+                display_name,
+            }
+        })
+        .collect()
+}
 
-    let now = now_ts();
-    let incoming_review_note = normalize_optional(payload.review_note, 280);
-    let manual_note_provided = incoming_review_note.is_some();
-    let (site_name, site_url, applicant_email, final_review_note) = {
-        let mut conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
-        let tx = match conn.transaction() {
-            Ok(tx) => tx,
-            Err(_) => return Err("db transaction failed".to_string()),
-        };
-        let app_row = tx
-            .query_row(
-                "SELECT site_name, site_url, avatar_url, description, email, status, review_note
-                 FROM friend_link_applications WHERE id = ?1",
-                params![payload.application_id],
-                |row| {
-                    Ok((
-                        row.get::<_, String>(0)?,
-                        row.get::<_, String>(1)?,
-                        row.get::<_, Option<String>>(2)?,
-                        row.get::<_, Option<String>>(3)?,
-                        row.get::<_, Option<String>>(4)?,
-                        row.get::<_, String>(5)?,
-                        row.get::<_, Option<String>>(6)?,
-                    ))
-                },
-            )
-            .ok();
-        let (
-            site_name,
-            site_url,
-            avatar_url,
-            description,
-            applicant_email,
-            status,
-            existing_review_note,
-        ) =
-            match app_row {
-                Some(v) => v,
-                None => return Err("申请记录不存在".to_string()),
-            };
-        if action == "approve" && status == "verify_pending" {
-            return Err("该申请尚未验证，请先验证或手动放行进入自动审查".to_string());
+fn prune_inactive_devices(conn: &Connection, prune_days: i64, now: i64) {
+    let pruned = conn
+        .execute(
+            "DELETE FROM device_status WHERE last_seen < ?1",
+            params![now - prune_days * 24 * 3600],
+        )
+        .unwrap_or(0);
+    if pruned > 0 {
+        tracing::info!("pruned {} devices unseen for {} days", pruned, prune_days);
+    }
+}
+
+impl StatusHub {
+    fn new(
+        initial: Vec<DeviceStatus>,
+        manual_offline: bool,
+        activity: Option<StatusActivity>,
+        schedule: Option<ScheduleStatus>,
+        cache: Arc<ResponseCache>,
+    ) -> Self {
+        let (tx, _) = broadcast::channel(64);
+        let last = initial
+            .into_iter()
+            .map(|item| (item.device_id.clone(), item))
+            .collect();
+        Self {
+            tx,
+            cache,
+            last: Mutex::new(last),
+            last_manual_offline: Mutex::new(manual_offline),
+            last_activity: Mutex::new(activity),
+            last_schedule: Mutex::new(schedule),
+            webhook_wake: Notify::new(),
         }
-        let existing_review_note = normalize_optional(existing_review_note, 560);
-        let final_review_note = match (existing_review_note, incoming_review_note.clone()) {
-            (Some(existing), Some(incoming)) => {
-                if existing == incoming {
-                    Some(existing)
-                } else {
-                    Some(format!("{}\n人工复核：{}", existing, incoming))
-                }
-            }
-            (Some(existing), None) => Some(existing),
-            (None, Some(incoming)) => Some(incoming),
-            (None, None) => None,
-        };
+    }
 
-        if action == "approve" {
-            let link_id = format!(
-                "link-{}-{}",
-                slugify_ascii(&site_name),
-                payload.application_id
-            );
-            let manual_tags = normalize_optional(payload.tags, 120);
-            let tags = if send_admin_smtp_notify {
-                merge_link_tags(
-                    manual_tags,
-                    extract_auto_link_tags(
-                        &site_name,
-                        &site_url,
-                        description.as_deref(),
-                        final_review_note.as_deref(),
-                    ),
-                    120,
-                )
-            } else {
-                manual_tags
-            };
-            let sort_order = payload.sort_order.unwrap_or(now);
-            let backlink_window = state.auto_review.backlink_window_secs.max(3600);
-            if tx
-                .execute(
-                    "INSERT INTO friend_links (
-                        id, name, url, avatar_url, description, tags, sort_order, created_at,
-                        application_id, backlink_status, backlink_deadline, backlink_checked_at, unreachable_since
-                     )
-                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 'pending', ?10, NULL, NULL)
-                     ON CONFLICT(id) DO UPDATE SET
-                       name = excluded.name,
-                       url = excluded.url,
-                       avatar_url = excluded.avatar_url,
-                       description = excluded.description,
-                       tags = excluded.tags,
-                       sort_order = excluded.sort_order,
-                       application_id = excluded.application_id,
-                       backlink_status = excluded.backlink_status,
-                       backlink_deadline = excluded.backlink_deadline,
-                       backlink_checked_at = excluded.backlink_checked_at,
-                       unreachable_since = excluded.unreachable_since",
-                    params![
-                        link_id,
-                        site_name,
-                        site_url,
-                        avatar_url,
-                        description,
-                        tags,
-                        sort_order,
-                        now,
-                        payload.application_id,
-                        now + backlink_window,
-                    ],
-                )
-                .is_err()
-            {
-                return Err("写入友链失败".to_string());
+    fn publish_changes(&self, conn: &Connection, presence: &PresenceConfig) {
+        let now = now_ts();
+        let current = load_device_statuses(conn, presence, now);
+        let mut webhook_events = Vec::new();
+        let manual = manual_offline_state(conn, now);
+        {
+            let mut last_manual = self.last_manual_offline.lock().unwrap();
+            if *last_manual != manual.is_some() {
+                *last_manual = manual.is_some();
+                webhook_events.push((
+                    "manual_offline.toggled",
+                    serde_json::json!({ "enabled": manual.is_some(), "active": manual }),
+                ));
             }
         }
-
-        if tx
-            .execute(
-                "UPDATE friend_link_applications
-                 SET status = ?1, review_note = ?2, updated_at = ?3
-                 WHERE id = ?4",
-                params![
-                    action,
-                    final_review_note.clone(),
-                    now,
-                    payload.application_id
-                ],
-            )
-            .is_err()
+        let mut changed = !webhook_events.is_empty();
+        let schedule = active_schedule_status(conn, now);
+        let activity = current_activity(conn, schedule.as_ref(), now);
         {
-            return Err("更新申请状态失败".to_string());
+            let mut last_schedule = self.last_schedule.lock().unwrap();
+            if *last_schedule != schedule {
+                changed = true;
+                *last_schedule = schedule;
+            }
         }
-        if tx.commit().is_err() {
-            return Err("事务提交失败".to_string());
+        {
+            let mut last_activity = self.last_activity.lock().unwrap();
+            if *last_activity != activity {
+                changed = true;
+                *last_activity = activity.clone();
+                let _ = self.tx.send(StatusEvent::Activity(activity));
+            }
         }
-        (site_name, site_url, applicant_email, final_review_note)
-    };
-
-    let applicant_email = normalize_optional(applicant_email, 128);
-    let mail_note: String;
-    if let Some(email) = applicant_email.as_deref() {
-        if !send_email {
-            mail_note = "（邮件通知已跳过）".to_string();
-        } else {
-            let notify_cfg = {
-                let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
-                state.notifier.runtime_config(&conn)
-            };
-            if let Err(err) = state
-                .notifier
-                .notify_review_result_email(
-                    notify_cfg.smtp.as_ref(),
-                    email,
-                    &site_name,
-                    &site_url,
-                    &action,
-                    final_review_note.as_deref(),
-                    !send_admin_smtp_notify && manual_note_provided,
-                )
-                .await
-            {
-                tracing::warn!("review result mail failed: {}", err);
-                mail_note = "（邮件通知失败）".to_string();
-            } else {
-                mail_note = "（邮件通知已发送）".to_string();
+        let mut last = self.last.lock().unwrap();
+        let mut seen = HashSet::new();
+        for item in current {
+            seen.insert(item.device_id.clone());
+            let previous = last.get(&item.device_id);
+            if previous != Some(&item) {
+                changed = true;
+                webhook_events.extend(device_webhook_events(previous, &item));
+                let _ = self.tx.send(StatusEvent::Device(Box::new(item.clone())));
+                last.insert(item.device_id.clone(), item);
             }
         }
-    } else {
-        mail_note = "（申请方未提供邮箱）".to_string();
+        if enqueue_webhook_events(conn, &webhook_events, now) > 0 {
+            self.webhook_wake.notify_one();
+        }
+        let removed: Vec<String> = last
+            .keys()
+            .filter(|id| !seen.contains(*id))
+            .cloned()
+            .collect();
+        for device_id in removed {
+            changed = true;
+            last.remove(&device_id);
+            let _ = self.tx.send(StatusEvent::Removed(device_id));
+        }
+        if changed {
+            self.cache.invalidate("status");
+        }
     }
+}
 
-    if send_admin_smtp_notify {
-        let notify_cfg = {
-            let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
-            state.notifier.runtime_config(&conn)
+impl StatusEvent {
+    fn to_sse(&self) -> Event {
+        let event = match self {
+            StatusEvent::Device(device) => Event::default().event("device").json_data(device),
+            StatusEvent::Removed(device_id) => {
+                Event::default()
+                    .event("removed")
+                    .json_data(DeviceRemovedEvent {
+                        device_id: device_id.clone(),
+                    })
+            }
+            StatusEvent::Activity(activity) => {
+                Event::default().event("activity").json_data(activity)
+            }
         };
-        if let Some(smtp_cfg) = notify_cfg.smtp.as_ref() {
-            if !smtp_cfg.to.is_empty() {
-                let subject = format!(
-                    "Auto review result: {} ({})",
-                    if action == "approve" {
-                        "APPROVE"
-                    } else {
-                        "REJECT"
-                    },
-                    site_name
-                );
-                let body = format!(
-                    "Auto review result\napplication_id: {}\naction: {}\nsite: {}\nurl: {}\nemail: {}\nreview_note: {}",
-                    payload.application_id,
-                    action,
-                    site_name,
-                    site_url,
-                    applicant_email.as_deref().unwrap_or("-"),
-                    final_review_note.as_deref().unwrap_or("-")
-                );
-                let html = build_auto_review_admin_html(
-                    payload.application_id,
-                    &action,
-                    &site_name,
-                    &site_url,
-                    applicant_email.as_deref(),
-                    final_review_note.as_deref().unwrap_or("-"),
-                );
-                if let Err(err) = state
-                    .notifier
-                    .send_smtp_rich(Some(smtp_cfg), &subject, &body, Some(&html), None)
-                    .await
-                {
-                    tracing::warn!("auto review admin smtp notify failed: {}", err);
-                }
+        event.unwrap_or_else(|_| Event::default().event("resync"))
+    }
+}
+
+fn device_webhook_events(
+    previous: Option<&DeviceStatus>,
+    current: &DeviceStatus,
+) -> Vec<(&'static str, serde_json::Value)> {
+    let mut events = Vec::new();
+    let data = || serde_json::json!({ "device": current, "previous": previous });
+    let was_online = previous.map(|v| v.online).unwrap_or(false);
+    if current.online != was_online && (previous.is_some() || current.online) {
+        let event = if current.online {
+            "device.online"
+        } else {
+            "device.offline"
+        };
+        events.push((event, data()));
+    }
+    let was_playing = previous.map(|v| v.music_playing).unwrap_or(false);
+    let track_changed = previous
+        .map(|v| v.music_title != current.music_title || v.music_artist != current.music_artist)
+        .unwrap_or(true);
+    if current.music_playing && (!was_playing || track_changed) {
+        events.push(("music.start", data()));
+    } else if was_playing && !current.music_playing {
+        events.push(("music.stop", data()));
+    }
+    events
+}
+
+fn webhook_body(event: &str, data: serde_json::Value, now: i64) -> String {
+    serde_json::json!({ "event": event, "timestamp": now, "data": data }).to_string()
+}
+
+fn load_webhooks(conn: &Connection) -> Vec<WebhookItem> {
+    conn.prepare(
+        "SELECT id, url, secret, events, enabled, created_at, updated_at
+         FROM webhooks
+         ORDER BY id ASC",
+    )
+    .and_then(|mut stmt| {
+        stmt.query_map([], |row| {
+            let events: String = row.get(3)?;
+            Ok(WebhookItem {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                secret: row.get(2)?,
+                events: events
+                    .split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect(),
+                enabled: row.get::<_, i32>(4)? == 1,
+                created_at: row.get(5)?,
+                updated_at: row.get(6)?,
+            })
+        })
+        .map(|rows| rows.filter_map(Result::ok).collect())
+    })
+    .unwrap_or_default()
+}
+
+fn enqueue_webhook_events(
+    conn: &Connection,
+    events: &[(&'static str, serde_json::Value)],
+    now: i64,
+) -> usize {
+    if events.is_empty() {
+        return 0;
+    }
+    let webhooks: Vec<WebhookItem> = load_webhooks(conn)
+        .into_iter()
+        .filter(|v| v.enabled)
+        .collect();
+    let mut queued = 0;
+    for (event, data) in events {
+        let body = webhook_body(event, data.clone(), now);
+        for webhook in &webhooks {
+            if !webhook.events.is_empty() && !webhook.events.iter().any(|v| v == event) {
+                continue;
             }
+            queued += conn
+                .execute(
+                    "INSERT INTO webhook_deliveries
+                     (webhook_id, event, payload, status, attempts, next_attempt_at, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, 'pending', 0, ?4, ?4, ?4)",
+                    params![webhook.id, event, body, now],
+                )
+                .unwrap_or(0);
         }
     }
+    queued
+}
 
-    Ok(if action == "approve" {
-        format!("已通过并加入友链列表{}", mail_note)
-    } else {
-        format!("已拒绝该申请{}", mail_note)
+fn load_due_webhook_deliveries(conn: &Connection, now: i64) -> Vec<PendingWebhookDelivery> {
+    conn.prepare(
+        "SELECT d.id, d.event, w.url, w.secret, d.payload, d.attempts
+         FROM webhook_deliveries d
+         JOIN webhooks w ON w.id = d.webhook_id
+         WHERE d.status = 'pending' AND w.enabled = 1 AND d.next_attempt_at <= ?1
+         ORDER BY d.next_attempt_at ASC, d.id ASC
+         LIMIT 20",
+    )
+    .and_then(|mut stmt| {
+        stmt.query_map(params![now], |row| {
+            Ok(PendingWebhookDelivery {
+                id: row.get(0)?,
+                event: row.get(1)?,
+                url: row.get(2)?,
+                secret: row.get(3)?,
+                payload: row.get(4)?,
+                attempts: row.get(5)?,
+            })
+        })
+        .map(|rows| rows.filter_map(Result::ok).collect())
     })
+    .unwrap_or_default()
 }
 
-async fn links_sort(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<LinkSortPayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+async fn send_webhook(
+    client: &reqwest::Client,
+    delivery: &PendingWebhookDelivery,
+) -> Result<u16, (Option<u16>, String)> {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(delivery.secret.as_bytes()) else {
+        return Err((None, "invalid secret".to_string()));
+    };
+    mac.update(delivery.payload.as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let resp = client
+        .post(&delivery.url)
+        .header("content-type", "application/json")
+        .header("user-agent", "status-backend-webhook")
+        .header("x-webhook-event", &delivery.event)
+        .header("x-webhook-delivery", delivery.id.to_string())
+        .header("x-webhook-signature", format!("sha256={}", signature))
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|err| (None, err.to_string()))?;
+    let status = resp.status().as_u16();
+    if resp.status().is_success() {
+        Ok(status)
+    } else {
+        Err((Some(status), format!("HTTP {}", status)))
     }
-    let mut conn = state.db.lock().unwrap();
-    let tx = match conn.transaction() {
-        Ok(tx) => tx,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+}
+
+async fn webhook_delivery_worker(state: AppState) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(c) => c,
+        Err(err) => {
+            tracing::error!("webhook client init failed: {}", err);
+            return;
+        }
     };
-    for item in payload.items {
-        if tx
-            .execute(
-                "UPDATE friend_links SET sort_order = ?1 WHERE id = ?2",
-                params![item.sort_order, item.id],
-            )
-            .is_err()
-        {
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    loop {
+        let due = {
+            let conn = state.db.lock().unwrap();
+            load_due_webhook_deliveries(&conn, now_ts())
+        };
+        for delivery in due {
+            let result = send_webhook(&client, &delivery).await;
+            let attempts = delivery.attempts + 1;
+            let now = now_ts();
+            let conn = state.db.lock().unwrap();
+            match result {
+                Ok(code) => {
+                    let _ = conn.execute(
+                        "UPDATE webhook_deliveries
+                         SET status = 'delivered', attempts = ?2, next_attempt_at = NULL,
+                             response_status = ?3, last_error = NULL, updated_at = ?4
+                         WHERE id = ?1",
+                        params![delivery.id, attempts, code, now],
+                    );
+                }
+                Err((code, err)) => {
+                    tracing::warn!(
+                        "webhook delivery failed: id={} event={} attempt={} err={}",
+                        delivery.id,
+                        delivery.event,
+                        attempts,
+                        err
+                    );
+                    let (status, next_attempt_at) = if attempts >= state.webhook_max_attempts {
+                        ("failed", None)
+                    } else {
+                        let delay = WEBHOOK_RETRY_BASE_SECS
+                            .saturating_mul(1 << (attempts - 1).min(16))
+                            .min(WEBHOOK_RETRY_MAX_SECS);
+                        ("pending", Some(now + delay))
+                    };
+                    let _ = conn.execute(
+                        "UPDATE webhook_deliveries
+                         SET status = ?2, attempts = ?3, next_attempt_at = ?4,
+                             response_status = ?5, last_error = ?6, updated_at = ?7
+                         WHERE id = ?1",
+                        params![
+                            delivery.id,
+                            status,
+                            attempts,
+                            next_attempt_at,
+                            code,
+                            err,
+                            now
+                        ],
+                    );
+                }
+            }
+        }
+        tokio::select! {
+            _ = state.hub.webhook_wake.notified() => {}
+            _ = tokio::time::sleep(Duration::from_secs(WEBHOOK_POLL_INTERVAL_SECS)) => {}
         }
     }
-    if tx.commit().is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-    }
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: "排序已更新".to_string(),
-        }),
-    )
-        .into_response()
 }
 
-async fn links_update(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<LinkUpdatePayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
-    }
-    let id = payload.id.trim();
-    if id.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "id 不能为空".to_string(),
-            }),
-        )
-            .into_response();
+/// Finds reminders that are due and records them before anything is sent, so
+/// a reminder is delivered at most once even across restarts. When several
+/// offsets of one occurrence are due together only the closest one is sent.
+fn claim_due_reminders(conn: &Connection, now: i64) -> Vec<(ScheduleItem, i64)> {
+    let items: Vec<ScheduleItem> = conn
+        .prepare(&format!(
+            "SELECT {} FROM schedule_items WHERE reminders IS NOT NULL",
+            SCHEDULE_COLUMNS
+        ))
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map([], schedule_item_from_row)?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+    let Some(max_offset) = items.iter().flat_map(|item| item.reminders.iter()).max() else {
+        return Vec::new();
+    };
+    let from = now - SCHEDULE_REMINDER_GRACE_SECS;
+    let mut due = Vec::new();
+    for item in expand_schedule(&items, from, now + max_offset * 60 + 1, false) {
+        let Some(start) = item.start_ts else {
+            continue;
+        };
+        let mut queued = false;
+        for offset in &item.reminders {
+            let fire_at = start - offset * 60;
+            if fire_at > now || fire_at <= from {
+                continue;
+            }
+            let status = if queued { "skipped" } else { "sending" };
+            let claimed = conn
+                .execute(
+                    "INSERT OR IGNORE INTO schedule_reminders
+                     (item_id, occurrence_start, offset_minutes, title, status, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+                    params![item.id, start, offset, item.title, status, now],
+                )
+                .unwrap_or(0);
+            if claimed > 0 && !queued {
+                queued = true;
+                due.push((item.clone(), *offset));
+            }
+        }
     }
-    let name = payload.name.trim();
-    if name.is_empty() || name.chars().count() > 32 {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "名称长度需在 1-32 字符内".to_string(),
-            }),
-        )
-            .into_response();
+    due
+}
+
+fn schedule_reminder_text(item: &ScheduleItem, now: i64) -> String {
+    let minutes = (item.start_ts.unwrap_or(now) - now + 59).max(0) / 60;
+    let when = match minutes {
+        0 => "现在开始".to_string(),
+        m if m % 1440 == 0 => format!("{} 天后开始", m / 1440),
+        m if m % 60 == 0 => format!("{} 小时后开始", m / 60),
+        m => format!("{} 分钟后开始", m),
+    };
+    let mut lines = vec![format!("⏰ 日程提醒：{}（{}）", item.title, when)];
+    if !item.time.trim().is_empty() {
+        lines.push(format!("时间：{}", item.time));
     }
-    let url = payload.url.trim();
-    if !is_valid_http_url(url) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "URL 需为 http/https".to_string(),
-            }),
-        )
-            .into_response();
+    if let Some(location) = item.location.as_deref().filter(|v| !v.trim().is_empty()) {
+        lines.push(format!("地点：{}", location));
     }
-    let avatar_url = normalize_optional(payload.avatar_url, 255);
-    if avatar_url
-        .as_deref()
-        .is_some_and(|value| !is_valid_http_url(value))
-    {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "头像 URL 需为 http/https".to_string(),
-            }),
-        )
-            .into_response();
+    if let Some(note) = item.note.as_deref().filter(|v| !v.trim().is_empty()) {
+        lines.push(format!("备注：{}", note));
     }
-    let description = normalize_optional(payload.description, 280);
-    let tags = normalize_optional(payload.tags, 120);
-    let sort_order = payload.sort_order.unwrap_or(0);
+    lines.join("\n")
+}
 
-    let conn = state.db.lock().unwrap();
-    let updated = conn
-        .execute(
-            "UPDATE friend_links
-             SET name = ?1, url = ?2, avatar_url = ?3, description = ?4, tags = ?5, sort_order = ?6
-             WHERE id = ?7",
-            params![name, url, avatar_url, description, tags, sort_order, id],
-        )
-        .unwrap_or(0);
-    if updated == 0 {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ApiMessage {
-                message: "友链不存在".to_string(),
-            }),
-        )
-            .into_response();
+async fn schedule_reminder_worker(state: AppState) {
+    loop {
+        let now = now_ts();
+        let (due, notify_cfg) = {
+            let conn = state.db.lock().unwrap();
+            let _ = conn.execute(
+                "DELETE FROM schedule_reminders WHERE created_at < ?1",
+                params![now - SCHEDULE_REMINDER_RETENTION_SECS],
+            );
+            (
+                claim_due_reminders(&conn, now),
+                state.notifier.runtime_config(&conn),
+            )
+        };
+        for (item, offset) in due {
+            let text = schedule_reminder_text(&item, now);
+            let mut channels = Vec::new();
+            let mut errors = Vec::new();
+            if notify_cfg.tg_bot_token.is_some() && notify_cfg.tg_chat_id.is_some() {
+                match state.notifier.send_telegram(&notify_cfg, &text, None).await {
+                    Ok(()) => channels.push("telegram"),
+                    Err(err) => errors.push(format!("telegram: {}", err)),
+                }
+            }
+            if notify_cfg.smtp.is_some() {
+                let subject = format!("日程提醒：{}", item.title);
+                match state
+                    .notifier
+                    .send_smtp(notify_cfg.smtp.as_ref(), &subject, &text, None)
+                    .await
+                {
+                    Ok(()) => channels.push("email"),
+                    Err(err) => errors.push(format!("email: {}", err)),
+                }
+            }
+            let status = if !channels.is_empty() {
+                "sent"
+            } else if errors.is_empty() {
+                "skipped"
+            } else {
+                "failed"
+            };
+            if !errors.is_empty() {
+                tracing::warn!(
+                    "schedule reminder failed: item={} offset={} err={}",
+                    item.id,
+                    offset,
+                    errors.join("; ")
+                );
+            }
+            let conn = state.db.lock().unwrap();
+            let _ = conn.execute(
+                "UPDATE schedule_reminders
+                 SET status = ?4, channels = ?5, last_error = ?6, updated_at = ?7
+                 WHERE item_id = ?1 AND occurrence_start = ?2 AND offset_minutes = ?3",
+                params![
+                    item.id,
+                    item.start_ts,
+                    offset,
+                    status,
+                    (!channels.is_empty()).then(|| channels.join(",")),
+                    (!errors.is_empty()).then(|| errors.join("; ")),
+                    now_ts()
+                ],
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(SCHEDULE_REMINDER_INTERVAL_SECS)).await;
     }
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: "友链已更新".to_string(),
-        }),
-    )
-        .into_response()
 }
 
-async fn links_delete(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<LinkDeletePayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+fn presence_state(
+    online: bool,
+    idle_seconds: Option<u64>,
+    manual_offline: bool,
+    rules: &DevicePresenceRules,
+) -> &'static str {
+    if manual_offline {
+        "offline"
+    } else if online && !rules.is_away(idle_seconds) {
+        "online"
+    } else if idle_seconds.is_some() {
+        "idle"
+    } else {
+        "offline"
     }
-    let id = payload.id.trim();
-    if id.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "id 不能为空".to_string(),
-            }),
+}
+
+fn record_presence(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    device_id: &str,
+    state: &str,
+    ts: i64,
+) {
+    let stale_secs = presence.device_rules(conn, device_id).stale_after_secs;
+    let open = conn
+        .query_row(
+            "SELECT id, state, last_seen FROM device_presence_sessions
+             WHERE device_id = ?1 AND ended_at IS NULL
+             ORDER BY started_at DESC
+             LIMIT 1",
+            params![device_id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            },
         )
-            .into_response();
+        .ok();
+    if let Some((id, open_state, last_seen)) = open {
+        let stale = ts.saturating_sub(last_seen) > stale_secs;
+        if !stale && open_state == state {
+            let _ = conn.execute(
+                "UPDATE device_presence_sessions SET last_seen = MAX(last_seen, ?1) WHERE id = ?2",
+                params![ts, id],
+            );
+            return;
+        }
+        let ended_at = if stale { last_seen } else { ts };
+        let _ = conn.execute(
+            "UPDATE device_presence_sessions SET ended_at = ?1 WHERE id = ?2",
+            params![ended_at, id],
+        );
     }
-    let conn = state.db.lock().unwrap();
-    let deleted = conn
-        .execute("DELETE FROM friend_links WHERE id = ?1", params![id])
-        .unwrap_or(0);
-    if deleted == 0 {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ApiMessage {
-                message: "友链不存在".to_string(),
-            }),
+    let resumed = conn
+        .execute(
+            "UPDATE device_presence_sessions
+             SET ended_at = NULL, last_seen = ?1
+             WHERE id = (
+               SELECT id FROM device_presence_sessions
+               WHERE device_id = ?2 AND ended_at IS NOT NULL
+               ORDER BY started_at DESC
+               LIMIT 1
+             )
+               AND state = ?3
+               AND ended_at <= ?1
+               AND ended_at >= ?1 - ?4",
+            params![ts, device_id, state, stale_secs],
         )
-            .into_response();
+        .unwrap_or(0);
+    if resumed > 0 {
+        return;
     }
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: "友链已删除".to_string(),
-        }),
-    )
-        .into_response()
+    let _ = conn.execute(
+        "INSERT INTO device_presence_sessions (device_id, state, started_at, last_seen, ended_at)
+         VALUES (?1, ?2, ?3, ?3, NULL)",
+        params![device_id, state, ts],
+    );
 }
 
-async fn links_review_stage_cancel(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<LinkReviewStageCancelPayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+fn close_stale_presence_sessions(conn: &Connection, presence: &PresenceConfig, now: i64) {
+    let _ = conn.execute(
+        "UPDATE device_presence_sessions
+         SET ended_at = last_seen
+         WHERE ended_at IS NULL
+           AND last_seen < ?1 - COALESCE(
+             (SELECT stale_after_secs FROM device_status d
+              WHERE d.device_id = device_presence_sessions.device_id),
+             ?2
+           )",
+        params![now, presence.stale_after_secs],
+    );
+}
+
+fn load_presence_history(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    device_id: String,
+    from: i64,
+    to: i64,
+    now: i64,
+) -> DevicePresenceHistory {
+    let stale_secs = presence.device_rules(conn, &device_id).stale_after_secs;
+    let segments: Vec<(String, i64, i64)> = conn
+        .prepare(
+            "SELECT state, started_at, last_seen, ended_at FROM device_presence_sessions
+             WHERE device_id = ?1
+               AND started_at < ?3
+               AND (ended_at IS NULL OR ended_at > ?2)
+             ORDER BY started_at ASC, id ASC",
+        )
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![device_id, from, to], |row| {
+                let state: String = row.get(0)?;
+                let started_at: i64 = row.get(1)?;
+                let last_seen: i64 = row.get(2)?;
+                let ended_at: Option<i64> = row.get(3)?;
+                let ended_at = ended_at.unwrap_or(if now - last_seen > stale_secs {
+                    last_seen
+                } else {
+                    now
+                });
+                Ok((state, started_at, ended_at))
+            })?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+
+    let mut sessions: Vec<PresenceSession> = Vec::new();
+    let mut push = |state: &str, start: i64, end: i64| {
+        if end <= start {
+            return;
+        }
+        if let Some(last) = sessions.last_mut() {
+            if last.state == state && last.ended_at >= start {
+                last.ended_at = last.ended_at.max(end);
+                last.duration = last.ended_at - last.started_at;
+                return;
+            }
+        }
+        sessions.push(PresenceSession {
+            state: state.to_string(),
+            started_at: start,
+            ended_at: end,
+            duration: end - start,
+        });
+    };
+    let mut cursor = from;
+    for (state, started_at, ended_at) in segments {
+        let start = started_at.max(cursor);
+        let end = ended_at.min(to);
+        if end <= start {
+            continue;
+        }
+        if start > cursor {
+            push("offline", cursor, start);
+        }
+        push(&state, start, end);
+        cursor = end;
     }
-    let id = payload.id.trim();
-    if id.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "id 不能为空".to_string(),
-            }),
+    if cursor < to {
+        push("offline", cursor, to);
+    }
+
+    let mut totals = PresenceTotals::default();
+    for session in &sessions {
+        match session.state.as_str() {
+            "online" => totals.online += session.duration,
+            "idle" => totals.idle += session.duration,
+            _ => totals.offline += session.duration,
+        }
+    }
+    DevicePresenceHistory {
+        device_id,
+        from,
+        to,
+        sessions,
+        totals,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record_music_play(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    device_id: &str,
+    playing: bool,
+    title: Option<&str>,
+    artist: Option<&str>,
+    source: Option<&str>,
+    ts: i64,
+) {
+    let music = apply_music_rules(
+        &load_music_rules(conn),
+        device_id,
+        MusicFields {
+            playing,
+            title: title.map(str::to_string),
+            artist: artist.map(str::to_string),
+            album: None,
+            artwork: None,
+            source: source.map(str::to_string),
+            source_icon: None,
+        },
+    );
+    let (title, artist, source) = (
+        music.title.as_deref(),
+        music.artist.as_deref(),
+        music.source.as_deref(),
+    );
+    let playing = music.playing && (title.is_some() || artist.is_some());
+    let stale_secs = presence.device_rules(conn, device_id).stale_after_secs;
+    let open = conn
+        .query_row(
+            "SELECT id, title, artist, last_seen FROM music_plays
+             WHERE device_id = ?1 AND ended_at IS NULL
+             ORDER BY started_at DESC
+             LIMIT 1",
+            params![device_id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            },
         )
-            .into_response();
+        .ok();
+    if let Some((id, open_title, open_artist, last_seen)) = open {
+        let stale = ts.saturating_sub(last_seen) > stale_secs;
+        let same_track = open_title.as_deref() == title && open_artist.as_deref() == artist;
+        let elapsed = if stale {
+            0
+        } else {
+            ts.saturating_sub(last_seen).max(0)
+        };
+        if playing && same_track && !stale {
+            let _ = conn.execute(
+                "UPDATE music_plays
+                 SET listened_secs = listened_secs + ?1, last_seen = MAX(last_seen, ?2)
+                 WHERE id = ?3",
+                params![elapsed, ts, id],
+            );
+            return;
+        }
+        let ended_at = if stale { last_seen } else { ts };
+        // Only pauses and stalls may be resumed; a different track means this
+        // one played out (or was skipped), so replaying it is a new play.
+        let end_reason = if stale {
+            "stale"
+        } else if !playing {
+            "pause"
+        } else {
+            "finished"
+        };
+        let _ = conn.execute(
+            "UPDATE music_plays
+             SET listened_secs = listened_secs + ?1, ended_at = ?2, last_seen = MAX(last_seen, ?2),
+                 end_reason = ?4
+             WHERE id = ?3",
+            params![elapsed, ended_at, id, end_reason],
+        );
     }
-    let now = now_ts();
-    let conn = state.db.lock().unwrap();
-    let updated = conn
+    if !playing {
+        return;
+    }
+    let resumed = conn
         .execute(
-            "UPDATE friend_links
-             SET backlink_status = 'manual_skip_backlink', backlink_deadline = NULL, backlink_checked_at = ?1
-             WHERE id = ?2",
-            params![now, id],
+            "UPDATE music_plays
+             SET ended_at = NULL, end_reason = NULL, last_seen = ?1
+             WHERE id = (
+               SELECT id FROM music_plays
+               WHERE device_id = ?2 AND ended_at IS NOT NULL
+               ORDER BY started_at DESC
+               LIMIT 1
+             )
+               AND title IS ?3 AND artist IS ?4
+               AND end_reason IN ('pause', 'stale')
+               AND ended_at >= ?5",
+            params![ts, device_id, title, artist, ts - MUSIC_RESUME_GRACE_SECS],
         )
         .unwrap_or(0);
-    if updated == 0 {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ApiMessage {
-                message: "友链不存在".to_string(),
-            }),
-        )
-            .into_response();
+    if resumed > 0 {
+        return;
     }
-    (
-        StatusCode::OK,
-        Json(ApiMessage {
-            message: "已取消该站点的 24h 回链审查阶段".to_string(),
-        }),
-    )
-        .into_response()
+    let _ = conn.execute(
+        "INSERT INTO music_plays (device_id, title, artist, source, started_at, last_seen, ended_at, listened_secs)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?5, NULL, 0)",
+        params![device_id, title, artist, source, ts],
+    );
 }
 
-async fn links_settings_get(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
-    }
-    let conn = state.db.lock().unwrap();
-    let settings = build_link_settings_response(&state, &conn);
-    (StatusCode::OK, Json(settings)).into_response()
+fn close_stale_music_plays(conn: &Connection, presence: &PresenceConfig, now: i64) {
+    let _ = conn.execute(
+        "UPDATE music_plays
+         SET ended_at = last_seen, end_reason = 'stale'
+         WHERE ended_at IS NULL
+           AND last_seen < ?1 - COALESCE(
+             (SELECT stale_after_secs FROM device_status d
+              WHERE d.device_id = music_plays.device_id),
+             ?2
+           )",
+        params![now, presence.stale_after_secs],
+    );
 }
 
-async fn links_settings_set(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<LinkSettingsPayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
-    }
-    let now = now_ts();
-    let mut conn = state.db.lock().unwrap();
-    let tx = match conn.transaction() {
-        Ok(tx) => tx,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-    };
-    let updates = [
-        (
-            "tg_bot_token",
-            payload
-                .tg_bot_token
-                .map(|v| v.trim().chars().take(256).collect::<String>()),
-        ),
-        (
-            "tg_chat_id",
-            payload
-                .tg_chat_id
-                .map(|v| v.trim().chars().take(128).collect::<String>()),
-        ),
-        (
-            "smtp_host",
-            payload
-                .smtp_host
-                .map(|v| v.trim().chars().take(255).collect::<String>()),
-        ),
-        ("smtp_port", payload.smtp_port.map(|v| v.to_string())),
-        (
-            "smtp_user",
-            payload
-                .smtp_user
-                .map(|v| v.trim().chars().take(255).collect::<String>()),
-        ),
-        ("smtp_pass", payload.smtp_pass.map(|v| v.trim().to_string())),
-        (
-            "smtp_from",
-            payload
-                .smtp_from
-                .map(|v| v.trim().chars().take(255).collect::<String>()),
-        ),
-        (
-            "smtp_to",
-            payload
-                .smtp_to
-                .map(|v| v.trim().chars().take(512).collect::<String>()),
-        ),
-        (
-            "smtp_starttls",
-            payload
-                .smtp_starttls
-                .map(|v| if v { "1".to_string() } else { "0".to_string() }),
-        ),
-        (
-            "captcha_provider",
-            payload
-                .captcha_provider
-                .map(|v| v.trim().chars().take(32).collect::<String>()),
-        ),
-        (
-            "captcha_site_key",
-            payload
-                .captcha_site_key
-                .map(|v| v.trim().chars().take(255).collect::<String>()),
-        ),
-        (
-            "captcha_secret",
-            payload.captcha_secret.map(|v| {
-                let trimmed = v.trim();
-                if trimmed == "-" {
-                    String::new()
-                } else {
-                    trimmed.chars().take(255).collect::<String>()
-                }
-            }),
-        ),
-        (
-            "apply_rate_limit_window_sec",
-            payload
-                .apply_rate_limit_window_sec
-                .map(|v| v.clamp(60, 86400).to_string()),
-        ),
-        (
-            "apply_rate_limit_max",
-            payload
-                .apply_rate_limit_max
-                .map(|v| v.clamp(1, 30).to_string()),
-        ),
-        (
-            "apply_rate_limit_prefix_max",
-            payload
-                .apply_rate_limit_prefix_max
-                .map(|v| v.clamp(1, 80).to_string()),
-        ),
-        (
-            "apply_rate_limit_email_domain_max",
-            payload
-                .apply_rate_limit_email_domain_max
-                .map(|v| v.clamp(1, 60).to_string()),
-        ),
-        (
-            "apply_rate_limit_site_host_max",
-            payload
-                .apply_rate_limit_site_host_max
-                .map(|v| v.clamp(1, 30).to_string()),
-        ),
-        (
-            "block_disposable_email",
-            payload
-                .block_disposable_email
-                .map(|v| if v { "1".to_string() } else { "0".to_string() }),
-        ),
-        (
-            "block_edu_gov_email",
-            payload
-                .block_edu_gov_email
-                .map(|v| if v { "1".to_string() } else { "0".to_string() }),
-        ),
-        (
-            "apply_deny_hosts",
-            payload
-                .apply_deny_hosts
-                .map(|v| v.trim().chars().take(1024).collect::<String>()),
-        ),
-        (
-            "verify_window_minutes",
-            payload
-                .verify_window_minutes
-                .map(|v| v.clamp(10, 1440).to_string()),
-        ),
-        (
-            "public_base_url",
-            payload
-                .public_base_url
-                .map(|v| v.trim().chars().take(255).collect::<String>()),
-        ),
-        (
-            "unreachable_whitelist_hosts",
-            payload
-                .unreachable_whitelist_hosts
-                .map(|v| v.trim().chars().take(1024).collect::<String>()),
-        ),
-    ];
-    for (key, value) in updates {
-        if let Some(value) = value {
-            if value.is_empty() {
-                if tx
-                    .execute(
-                        "DELETE FROM friend_link_settings WHERE key = ?1",
-                        params![key],
-                    )
-                    .is_err()
-                {
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            } else if tx
-                .execute(
-                    "INSERT INTO friend_link_settings (key, value, updated_at)
-                     VALUES (?1, ?2, ?3)
-                     ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
-                    params![key, value, now],
-                )
-                .is_err()
-            {
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+/// A run of backfilled samples with the same key, ended at `end` for `reason`.
+struct LateSegment<K> {
+    key: K,
+    start: i64,
+    end: i64,
+    reason: &'static str,
+}
+
+/// Groups time-sorted samples (`None` = nothing to record) into segments the
+/// way the live path would have, skipping samples inside a recorded span.
+/// A segment runs up to the next sample or recorded span within `stale_secs`.
+fn late_segments<K: PartialEq>(
+    samples: Vec<(i64, Option<K>)>,
+    spans: &[(i64, i64)],
+    stale_secs: i64,
+) -> Vec<LateSegment<K>> {
+    let mut iter = samples
+        .into_iter()
+        .filter(|(ts, _)| !spans.iter().any(|(start, end)| start <= ts && ts <= end))
+        .peekable();
+    let mut out = Vec::new();
+    let mut open: Option<i64> = None;
+    while let Some((ts, key)) = iter.next() {
+        let Some(key) = key else {
+            continue;
+        };
+        let start = open.take().unwrap_or(ts);
+        let span_start = spans
+            .iter()
+            .map(|(start, _)| *start)
+            .filter(|start| *start > ts)
+            .min();
+        let next = iter
+            .peek()
+            .filter(|(next_ts, _)| span_start.is_none_or(|span| *next_ts < span));
+        let (end, reason) = match next {
+            Some((next_ts, _)) if next_ts - ts > stale_secs => (ts, "stale"),
+            Some((_, Some(next_key))) if *next_key == key => {
+                open = Some(start);
+                continue;
             }
-        }
-    }
-    if tx.commit().is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            Some((next_ts, None)) => (*next_ts, "pause"),
+            Some((next_ts, Some(_))) => (*next_ts, "finished"),
+            None => match span_start {
+                Some(span) if span - ts <= stale_secs => (span, "stale"),
+                _ => (ts, "stale"),
+            },
+        };
+        out.push(LateSegment {
+            key,
+            start,
+            end,
+            reason,
+        });
     }
-
-    let settings = build_link_settings_response(&state, &conn);
-    (StatusCode::OK, Json(settings)).into_response()
+    out
 }
 
-async fn links_apply_config(State(state): State<AppState>) -> impl IntoResponse {
-    let (provider, site_key, secret_set) = {
-        let conn = state.db.lock().unwrap();
-        let provider = read_setting(&conn, "captcha_provider")
-            .or_else(|| {
-                state
-                    .anti_abuse
-                    .captcha
-                    .as_ref()
-                    .map(|cfg| cfg.provider.as_str().to_string())
-            })
-            .unwrap_or_default();
-        let site_key = read_setting(&conn, "captcha_site_key")
-            .or_else(|| state.anti_abuse.captcha.as_ref().map(|cfg| cfg.site_key.clone()))
-            .unwrap_or_default();
-        let secret_set = if provider.trim().eq_ignore_ascii_case("altcha") {
-            true
-        } else {
-            read_setting(&conn, "captcha_secret")
-                .or_else(|| state.anti_abuse.captcha.as_ref().map(|cfg| cfg.secret.clone()))
-                .map(|v| !v.is_empty())
-                .unwrap_or(false)
-        };
-        (provider, site_key, secret_set)
-    };
-    let provider = provider.trim().to_string();
-    let site_key = site_key.trim().to_string();
-    let captcha_enabled = !provider.is_empty() && !site_key.is_empty();
-    let response = LinkApplyConfigResponse {
-        captcha_provider: if provider.is_empty() { None } else { Some(provider) },
-        captcha_site_key: if site_key.is_empty() { None } else { Some(site_key) },
-        captcha_enabled,
-        captcha_secret_set: Some(secret_set),
-    };
-    (StatusCode::OK, Json(response)).into_response()
+/// A backfilled track; like the live path, only title and artist tell
+/// tracks apart.
+struct LateTrack {
+    title: Option<String>,
+    artist: Option<String>,
+    source: Option<String>,
 }
 
-async fn links_settings_test_smtp(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<SmtpTestPayload>,
-) -> impl IntoResponse {
-    if !authorized(&headers, &state.token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+impl PartialEq for LateTrack {
+    fn eq(&self, other: &Self) -> bool {
+        self.title == other.title && self.artist == other.artist
     }
-    let recipient = normalize_optional(payload.recipient, 255);
-    let notify_cfg = {
-        let conn = state.db.lock().unwrap();
-        state.notifier.runtime_config(&conn)
+}
+
+/// Records backfilled samples older than the device's latest heartbeat into
+/// the presence and music history without touching `device_status`. Samples
+/// already covered by a recorded session are skipped, and the new sessions
+/// are merged with adjacent ones as the live path would have resumed them.
+fn record_late_samples(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    device_id: &str,
+    samples: Vec<HeartbeatSample>,
+) {
+    let (Some(lo), Some(hi)) = (samples.first().map(|v| v.ts), samples.last().map(|v| v.ts)) else {
+        return;
     };
-    if notify_cfg.smtp.is_none() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiMessage {
-                message: "SMTP 未配置完整，请先保存 SMTP Host/From/To".to_string(),
-            }),
+    let rules = presence.device_rules(conn, device_id);
+    let stale_secs = rules.stale_after_secs;
+    let manual_offline = conn
+        .query_row(
+            "SELECT manual_offline FROM device_status WHERE device_id = ?1",
+            params![device_id],
+            |row| row.get::<_, i32>(0),
         )
-            .into_response();
-    }
+        .map(|v| v == 1)
+        .unwrap_or(false);
+    let music_rules = load_music_rules(conn);
+    let mut states = Vec::with_capacity(samples.len());
+    let mut tracks = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let state = presence_state(sample.online, sample.idle_seconds, manual_offline, &rules);
+        states.push((sample.ts, Some(state)));
+        let music = apply_music_rules(
+            &music_rules,
+            device_id,
+            MusicFields {
+                playing: sample.music_playing.unwrap_or(false),
+                title: sample.music_title,
+                artist: sample.music_artist,
+                album: None,
+                artwork: None,
+                source: sample.music_source,
+                source_icon: None,
+            },
+        );
+        let playing = music.playing && (music.title.is_some() || music.artist.is_some());
+        tracks.push((
+            sample.ts,
+            playing.then_some(LateTrack {
+                title: music.title,
+                artist: music.artist,
+                source: music.source,
+            }),
+        ));
+    }
+    let spans = |table: &str| -> Vec<(i64, i64)> {
+        conn.prepare(&format!(
+            "SELECT started_at, COALESCE(ended_at, last_seen) FROM {}
+             WHERE device_id = ?1
+               AND COALESCE(ended_at, last_seen) >= ?2 - ?4
+               AND started_at <= ?3 + ?4",
+            table
+        ))
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![device_id, lo, hi, stale_secs], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default()
+    };
 
-    let now_local = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    let subject = "SMTP test from Meow Links Admin";
-    let body = format!(
-        "这是一封 SMTP 测试邮件。\n\n发送时间：{}\n服务：status-backend\n说明：若收到此邮件，表示当前 SMTP 配置可用。",
-        now_local
+    let presence_spans = spans("device_presence_sessions");
+    for segment in late_segments(states, &presence_spans, stale_secs) {
+        // Like a live heartbeat, a sample shortly after a closed session
+        // takes over from where that session ended.
+        let start = presence_spans
+            .iter()
+            .map(|(_, end)| *end)
+            .filter(|end| *end <= segment.start && segment.start - *end <= stale_secs)
+            .max()
+            .unwrap_or(segment.start);
+        let _ = conn.execute(
+            "INSERT INTO device_presence_sessions (device_id, state, started_at, last_seen, ended_at)
+             VALUES (?1, ?2, ?3, ?4, ?4)",
+            params![device_id, segment.key, start, segment.end],
+        );
+    }
+    merge_presence_sessions(
+        conn,
+        device_id,
+        lo - stale_secs,
+        hi + stale_secs,
+        stale_secs,
     );
-    let override_to = recipient.map(|v| vec![v.clone()]);
-    let send_result = state
-        .notifier
-        .send_smtp(notify_cfg.smtp.as_ref(), subject, &body, override_to)
-        .await;
-    match send_result {
-        Ok(()) => (
-            StatusCode::OK,
-            Json(ApiMessage {
-                message: "SMTP 测试邮件发送成功".to_string(),
-            }),
-        )
-            .into_response(),
-        Err(err) => (
-            StatusCode::BAD_GATEWAY,
-            Json(ApiMessage {
-                message: format!("SMTP 测试失败: {}", err),
-            }),
-        )
-            .into_response(),
+
+    let music_spans = spans("music_plays");
+    for segment in late_segments(tracks, &music_spans, stale_secs) {
+        let _ = conn.execute(
+            "INSERT INTO music_plays (
+                device_id, title, artist, source, started_at, last_seen, ended_at, listened_secs,
+                end_reason
+             )
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?6 - ?5, ?7)",
+            params![
+                device_id,
+                segment.key.title,
+                segment.key.artist,
+                segment.key.source,
+                segment.start,
+                segment.end,
+                segment.reason,
+            ],
+        );
     }
+    merge_music_plays(conn, device_id, lo - stale_secs, hi + stale_secs);
 }
 
-async fn remove_link_and_notify(
-    state: &AppState,
-    link_id: String,
-    application_id: Option<i64>,
-    app_status: &str,
-    review_note: &str,
-    now: i64,
-    send_email: bool,
-) -> Result<(), String> {
-    let applicant = if let Some(app_id) = application_id {
-        let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
-        conn.query_row(
-            "SELECT email, site_name, site_url FROM friend_link_applications WHERE id = ?1",
-            params![app_id],
-            |row| {
+/// Joins consecutive same-state sessions in `[from, to]` that are at most
+/// `stale_secs` apart, as [`record_presence`] resumes them live.
+fn merge_presence_sessions(
+    conn: &Connection,
+    device_id: &str,
+    from: i64,
+    to: i64,
+    stale_secs: i64,
+) {
+    let sessions: Vec<(i64, String, i64, i64, Option<i64>)> = conn
+        .prepare(
+            "SELECT id, state, started_at, last_seen, ended_at FROM device_presence_sessions
+             WHERE device_id = ?1 AND COALESCE(ended_at, last_seen) >= ?2 AND started_at <= ?3
+             ORDER BY started_at ASC, id ASC",
+        )
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![device_id, from, to], |row| {
                 Ok((
-                    row.get::<_, Option<String>>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
                 ))
-            },
+            })?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+    let mut prev: Option<(i64, String, i64, Option<i64>)> = None;
+    for (id, state, started_at, last_seen, ended_at) in sessions {
+        match prev.as_mut() {
+            Some((prev_id, prev_state, prev_last_seen, Some(prev_end)))
+                if *prev_state == state && started_at - *prev_end <= stale_secs =>
+            {
+                *prev_last_seen = (*prev_last_seen).max(last_seen);
+                let _ = conn.execute(
+                    "UPDATE device_presence_sessions SET ended_at = ?1, last_seen = ?2 WHERE id = ?3",
+                    params![ended_at, *prev_last_seen, *prev_id],
+                );
+                let _ = conn.execute(
+                    "DELETE FROM device_presence_sessions WHERE id = ?1",
+                    params![id],
+                );
+                prev = prev.map(|(prev_id, prev_state, prev_last_seen, _)| {
+                    (prev_id, prev_state, prev_last_seen, ended_at)
+                });
+            }
+            _ => prev = Some((id, state, last_seen, ended_at)),
+        }
+    }
+}
+
+/// Joins a play that was paused or went stale with the next play of the same
+/// track within [`MUSIC_RESUME_GRACE_SECS`], as [`record_music_play`]
+/// resumes them live.
+fn merge_music_plays(conn: &Connection, device_id: &str, from: i64, to: i64) {
+    type Play = (
+        i64,
+        Option<String>,
+        Option<String>,
+        i64,
+        i64,
+        Option<i64>,
+        i64,
+        Option<String>,
+    );
+    let plays: Vec<Play> = conn
+        .prepare(
+            "SELECT id, title, artist, started_at, last_seen, ended_at, listened_secs, end_reason
+             FROM music_plays
+             WHERE device_id = ?1 AND COALESCE(ended_at, last_seen) >= ?2 AND started_at <= ?3
+             ORDER BY started_at ASC, id ASC",
         )
-        .ok()
-    } else {
-        None
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![device_id, from, to], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                ))
+            })?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+    let mut prev: Option<Play> = None;
+    for play in plays {
+        let resumes = prev.as_ref().is_some_and(|prev| {
+            prev.1 == play.1
+                && prev.2 == play.2
+                && matches!(prev.7.as_deref(), Some("pause") | Some("stale"))
+                && prev
+                    .5
+                    .is_some_and(|end| play.3 - end <= MUSIC_RESUME_GRACE_SECS)
+        });
+        let Some(prev) = prev.as_mut().filter(|_| resumes) else {
+            prev = Some(play);
+            continue;
+        };
+        prev.4 = prev.4.max(play.4);
+        prev.5 = play.5;
+        prev.6 += play.6;
+        prev.7 = play.7;
+        let _ = conn.execute(
+            "UPDATE music_plays
+             SET last_seen = ?1, ended_at = ?2, listened_secs = ?3, end_reason = ?4
+             WHERE id = ?5",
+            params![prev.4, prev.5, prev.6, prev.7, prev.0],
+        );
+        let _ = conn.execute("DELETE FROM music_plays WHERE id = ?1", params![play.0]);
+    }
+}
+
+fn music_period_start(period: &str) -> Option<i64> {
+    let today = chrono::Local::now().date_naive();
+    let start = match period {
+        "day" => today,
+        "week" => today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64),
+        "month" => today.with_day(1)?,
+        _ => return None,
     };
+    start
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .map(|v| v.timestamp())
+}
+
+fn load_music_rules(conn: &Connection) -> Vec<MusicRule> {
+    conn.prepare(
+        "SELECT id, kind, device_id, source_pattern, title_pattern, display_name, icon, sort_order
+         FROM music_rules
+         ORDER BY sort_order ASC, id ASC",
+    )
+    .and_then(|mut stmt| {
+        let rows = stmt.query_map([], |row| {
+            Ok(MusicRule {
+                id: row.get(0)?,
+                kind: row.get(1)?,
+                device_id: row.get(2)?,
+                source_pattern: row.get(3)?,
+                title_pattern: row.get(4)?,
+                display_name: row.get(5)?,
+                icon: row.get(6)?,
+                sort_order: row.get(7)?,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    })
+    .unwrap_or_default()
+}
 
-    {
-        let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
-        let _ = conn.execute("DELETE FROM friend_links WHERE id = ?1", params![link_id]);
-        if let Some(app_id) = application_id {
-            let _ = conn.execute(
-                "UPDATE friend_link_applications
-                 SET status = ?1, review_note = ?2, updated_at = ?3
-                 WHERE id = ?4",
-                params![app_status, review_note, now, app_id],
-            );
+fn apply_music_rules(rules: &[MusicRule], device_id: &str, mut music: MusicFields) -> MusicFields {
+    let matches = |pattern: &Option<String>, value: Option<&str>| match pattern {
+        Some(pattern) => value.is_some_and(|v| wildcard_match(pattern, v)),
+        None => true,
+    };
+    let device_matches = |rule: &MusicRule| {
+        rule.device_id
+            .as_deref()
+            .map(|v| v == device_id)
+            .unwrap_or(true)
+    };
+    let raw_source = music.source.clone();
+    for rule in rules {
+        if !device_matches(rule) || !matches(&rule.source_pattern, raw_source.as_deref()) {
+            continue;
         }
-    }
-
-    if send_email {
-        if let Some((email, app_name, app_url)) = applicant {
-            if let Some(email) = normalize_optional(email, 128) {
-                let notify_cfg = {
-                    let conn = state.db.lock().map_err(|_| "db lock failed".to_string())?;
-                    state.notifier.runtime_config(&conn)
+        match rule.kind.as_str() {
+            "hide" => {
+                return MusicFields {
+                    playing: false,
+                    title: None,
+                    artist: None,
+                    album: None,
+                    artwork: None,
+                    source: None,
+                    source_icon: None,
                 };
-                if let Err(err) = state
-                    .notifier
-                    .notify_review_result_email(
-                        notify_cfg.smtp.as_ref(),
-                        &email,
-                        &app_name,
-                        &app_url,
-                        "reject",
-                        Some(review_note),
-                        false,
-                    )
-                    .await
-                {
-                    tracing::warn!("link removal mail failed: {}", err);
+            }
+            "redact" if matches(&rule.title_pattern, music.title.as_deref()) => {
+                music.title = rule.display_name.clone();
+                music.artist = None;
+                music.album = None;
+                music.artwork = None;
+            }
+            "source_map" if music.source_icon.is_none() && music.source == raw_source => {
+                if let Some(name) = rule.display_name.clone() {
+                    music.source = Some(name);
                 }
+                music.source_icon = rule.icon.clone();
             }
+            _ => {}
         }
     }
-    Ok(())
+    music
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let value: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let (mut star, mut mark) = (None, 0);
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p);
+            mark = v;
+            p += 1;
+        } else if let Some(star_pos) = star {
+            p = star_pos + 1;
+            mark += 1;
+            v = mark;
+        } else {
+            return false;
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
 }
 
-async fn visitor_visit(
-    State(state): State<AppState>,
-    Json(payload): Json<VisitPayload>,
-) -> impl IntoResponse {
-    let now = now_ts();
-    let today = today_key();
-    let conn = state.db.lock().unwrap();
+fn load_status_activity(conn: &Connection, now: i64) -> Option<StatusActivity> {
+    conn.query_row(
+        "SELECT text, emoji, link, expires_at, updated_at FROM status_activity
+         WHERE id = 1 AND (expires_at IS NULL OR expires_at > ?1)",
+        params![now],
+        |row| {
+            Ok(StatusActivity {
+                text: row.get(0)?,
+                emoji: row.get(1)?,
+                link: row.get(2)?,
+                expires_at: row.get(3)?,
+                updated_at: row.get(4)?,
+            })
+        },
+    )
+    .ok()
+}
+
+/// Picks the strongest status effect among schedule items in progress at `now`.
+fn active_schedule_status(conn: &Connection, now: i64) -> Option<ScheduleStatus> {
+    let items: Vec<ScheduleItem> = conn
+        .prepare(&format!(
+            "SELECT {} FROM schedule_items WHERE status_effect IS NOT NULL",
+            SCHEDULE_COLUMNS
+        ))
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map([], schedule_item_from_row)?;
+            Ok(rows.filter_map(Result::ok).collect())
+        })
+        .unwrap_or_default();
+    if items.is_empty() {
+        return None;
+    }
+    expand_schedule(&items, now, now + 1, false)
+        .into_iter()
+        .filter(|item| item.end_ts.is_some_and(|end| end > now))
+        .filter_map(|item| {
+            let rank = SCHEDULE_STATUS_EFFECTS
+                .iter()
+                .position(|v| item.status_effect.as_deref() == Some(*v))?;
+            Some((rank, item))
+        })
+        .min_by_key(|(rank, item)| (*rank, std::cmp::Reverse(item.start_ts)))
+        .map(|(_, item)| ScheduleStatus {
+            effect: item.status_effect.clone().unwrap_or_default(),
+            text: item
+                .status_text
+                .clone()
+                .unwrap_or_else(|| item.title.clone()),
+            started_at: item.start_ts.unwrap_or(now),
+            ends_at: item.end_ts.unwrap_or(now),
+            item_id: item.id,
+        })
+}
+
+/// A schedule item with the `activity` effect takes precedence over the
+/// manually set activity while it is in progress.
+fn current_activity(
+    conn: &Connection,
+    schedule: Option<&ScheduleStatus>,
+    now: i64,
+) -> Option<StatusActivity> {
+    match schedule.filter(|v| v.effect == "activity") {
+        Some(schedule) => Some(StatusActivity {
+            text: schedule.text.clone(),
+            emoji: None,
+            link: None,
+            expires_at: Some(schedule.ends_at),
+            updated_at: schedule.started_at,
+        }),
+        None => load_status_activity(conn, now),
+    }
+}
+
+fn load_current_activity(conn: &Connection, now: i64) -> Option<StatusActivity> {
+    current_activity(conn, active_schedule_status(conn, now).as_ref(), now)
+}
+
+fn clear_expired_activity(conn: &Connection, now: i64) {
     let _ = conn.execute(
-        "INSERT OR IGNORE INTO visitor_visits (visitor_id, visit_date, created_at)
-         VALUES (?1, ?2, ?3)",
-        params![payload.visitor_id, today, now],
+        "DELETE FROM status_activity WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+        params![now],
     );
-    StatusCode::OK
 }
 
-async fn visitor_stats(State(state): State<AppState>) -> impl IntoResponse {
-    let now = now_ts();
-    let today = today_key();
-    let month_prefix = month_key();
-    let conn = state.db.lock().unwrap();
-    let today_count: i64 = conn
+fn load_summary_rules(conn: &Connection) -> SummaryRules {
+    let mut rules = conn
         .query_row(
-            "SELECT COUNT(*) FROM visitor_visits WHERE visit_date = ?1",
-            params![today],
-            |row| row.get(0),
+            "SELECT rules FROM status_summary_rules WHERE id = 1",
+            [],
+            |row| row.get::<_, String>(0),
         )
-        .unwrap_or(0);
-    let month_count: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM visitor_visits WHERE visit_date LIKE ?1",
-            params![format!("{}-%", month_prefix)],
-            |row| row.get(0),
+        .ok()
+        .and_then(|v| serde_json::from_str::<SummaryRules>(&v).ok())
+        .unwrap_or(SummaryRules {
+            state_priority: Vec::new(),
+            device_priority: Vec::new(),
+        });
+    for item in SUMMARY_STATES {
+        if !rules.state_priority.iter().any(|v| v == item) {
+            rules.state_priority.push(item.to_string());
+        }
+    }
+    rules
+}
+
+fn build_status_summary(conn: &Connection, presence: &PresenceConfig, now: i64) -> StatusSummary {
+    let rules = load_summary_rules(conn);
+    let schedule = active_schedule_status(conn, now);
+    let activity = current_activity(conn, schedule.as_ref(), now);
+    let devices: Vec<DeviceSummaryState> = load_device_statuses(conn, presence, now)
+        .into_iter()
+        .map(|device| {
+            let stale_after_secs = presence
+                .resolve(device.stale_after_secs, device.idle_away_secs)
+                .stale_after_secs;
+            let stale = now.saturating_sub(device.last_seen) > stale_after_secs;
+            let schedule_offline = device
+                .schedule
+                .as_ref()
+                .is_some_and(|v| v.effect == "offline");
+            let state = if device.global_manual_offline
+                || device.manual_offline
+                || schedule_offline
+                || stale
+            {
+                "offline"
+            } else if device.music_playing {
+                "listening"
+            } else if device.online {
+                "active"
+            } else if device.idle_seconds.is_some() {
+                "idle"
+            } else {
+                "offline"
+            };
+            let since = match state {
+                "listening" => conn
+                    .query_row(
+                        "SELECT started_at FROM music_plays
+                         WHERE device_id = ?1 AND ended_at IS NULL
+                         ORDER BY started_at DESC
+                         LIMIT 1",
+                        params![device.device_id],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .ok(),
+                "offline" => Some(device.last_seen),
+                _ => conn
+                    .query_row(
+                        "SELECT started_at FROM device_presence_sessions
+                         WHERE device_id = ?1 AND ended_at IS NULL AND state = ?2
+                         ORDER BY started_at DESC
+                         LIMIT 1",
+                        params![
+                            device.device_id,
+                            if state == "idle" { "idle" } else { "online" }
+                        ],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .ok(),
+            };
+            DeviceSummaryState {
+                device_id: device.device_id,
+                device_name: device.device_name,
+                state: state.to_string(),
+                since,
+            }
+        })
+        .collect();
+
+    if let Some(manual) = manual_offline_state(conn, now) {
+        return StatusSummary {
+            state: "dnd".to_string(),
+            device_id: None,
+            device_name: None,
+            since: manual.since,
+            activity,
+            schedule,
+            devices,
+        };
+    }
+    if let Some(effect) = schedule
+        .as_ref()
+        .map(|v| v.effect.as_str())
+        .filter(|v| *v != "activity")
+    {
+        return StatusSummary {
+            state: effect.to_string(),
+            device_id: None,
+            device_name: None,
+            since: schedule.as_ref().map(|v| v.started_at),
+            activity,
+            schedule,
+            devices,
+        };
+    }
+
+    let rank =
+        |list: &[String], value: &str| list.iter().position(|v| v == value).unwrap_or(list.len());
+    let driver = devices.iter().min_by_key(|device| {
+        (
+            rank(&rules.state_priority, &device.state),
+            rank(&rules.device_priority, &device.device_id),
+            std::cmp::Reverse(device.since.unwrap_or(0)),
         )
-        .unwrap_or(0);
-    let total_count: i64 = conn
-        .query_row("SELECT COUNT(*) FROM visitor_visits", [], |row| row.get(0))
-        .unwrap_or(0);
-    Json(VisitorStats {
-        today: today_count,
-        month: month_count,
-        total: total_count,
-        updated_at: now,
-    })
+    });
+    match driver {
+        Some(device) if device.state != "offline" => StatusSummary {
+            state: device.state.clone(),
+            device_id: Some(device.device_id.clone()),
+            device_name: Some(device.device_name.clone()),
+            since: device.since,
+            activity,
+            schedule,
+            devices,
+        },
+        _ => {
+            let latest = devices
+                .iter()
+                .max_by_key(|device| device.since.unwrap_or(0));
+            StatusSummary {
+                state: "offline".to_string(),
+                device_id: latest.map(|v| v.device_id.clone()),
+                device_name: latest.map(|v| v.device_name.clone()),
+                since: latest.and_then(|v| v.since),
+                activity,
+                schedule,
+                devices,
+            }
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct DeleteQuery {
-    id: String,
-    token: String,
+fn default_telemetry_version() -> u32 {
+    TELEMETRY_VERSION
 }
 
-async fn delete_device(
-    State(state): State<AppState>,
-    Query(q): Query<DeleteQuery>,
-) -> impl IntoResponse {
-    if q.token != state.token {
-        return StatusCode::UNAUTHORIZED;
+fn normalize_telemetry(telemetry: DeviceTelemetry) -> DeviceTelemetry {
+    let clean = |value: Option<String>| {
+        value
+            .map(|v| v.trim().chars().take(64).collect::<String>())
+            .filter(|v| !v.is_empty())
+    };
+    DeviceTelemetry {
+        version: telemetry.version.max(1),
+        battery_level: telemetry.battery_level.map(|v| v.clamp(0, 100)),
+        battery_charging: telemetry.battery_charging,
+        network_type: clean(telemetry.network_type).map(|v| v.to_lowercase()),
+        os: clean(telemetry.os),
+        platform: clean(telemetry.platform).map(|v| v.to_lowercase()),
+        client_version: clean(telemetry.client_version),
+        updated_at: None,
     }
-    let conn = state.db.lock().unwrap();
-    let _ = conn.execute(
-        "DELETE FROM device_status WHERE device_id = ?1",
-        params![q.id],
-    );
-    StatusCode::OK
+}
+
+fn load_device_statuses(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    now: i64,
+) -> Vec<DeviceStatus> {
+    load_all_device_statuses(conn, presence, now)
+        .into_iter()
+        .filter(|device| !device.hidden)
+        .collect()
+}
+
+fn load_all_device_statuses(
+    conn: &Connection,
+    presence: &PresenceConfig,
+    now: i64,
+) -> Vec<DeviceStatus> {
+    let global_manual_offline = is_global_manual_offline(conn);
+    let schedule = active_schedule_status(conn, now);
+    let schedule_offline = schedule.as_ref().is_some_and(|v| v.effect == "offline");
+    let music_rules = load_music_rules(conn);
+    let mut stmt = match conn.prepare(
+        "SELECT device_id, COALESCE(NULLIF(display_name, ''), device_name), online, last_seen,
+                idle_seconds, manual_offline,
+                music_playing, music_title, music_artist, music_source, music_updated_at,
+                stale_after_secs, idle_away_secs,
+                telemetry_version, battery_level, battery_charging, network_type, os, platform,
+                client_version, telemetry_updated_at,
+                kind, icon, sort_order, hidden,
+                music_album, music_duration_ms, music_position_ms, music_position_at, music_artwork
+         FROM device_status
+         ORDER BY sort_order ASC, device_id ASC",
+    ) {
+        Ok(stmt) => stmt,
+        Err(_) => return Vec::new(),
+    };
+
+    let rows = match stmt.query_map([], |row| {
+        let last_seen: i64 = row.get(3)?;
+        let online_flag: i32 = row.get(2)?;
+        let manual_offline: i32 = row.get(5)?;
+        let music_playing: i32 = row.get(6)?;
+        let stale_after_secs: Option<i64> = row.get(11)?;
+        let idle_away_secs: Option<i64> = row.get(12)?;
+        let rules = presence.resolve(stale_after_secs, idle_away_secs);
+        let idle_seconds = row.get::<_, Option<i64>>(4)?.map(|v| v as u64);
+        let stale = now.saturating_sub(last_seen) > rules.stale_after_secs;
+        let device_manual_offline = manual_offline == 1;
+        let online = !global_manual_offline
+            && !schedule_offline
+            && !device_manual_offline
+            && online_flag == 1
+            && !stale
+            && !rules.is_away(idle_seconds);
+        let device_id: String = row.get(0)?;
+        let music = apply_music_rules(
+            &music_rules,
+            &device_id,
+            MusicFields {
+                playing: music_playing == 1,
+                title: row.get(7)?,
+                artist: row.get(8)?,
+                album: row.get(25)?,
+                artwork: row.get(29)?,
+                source: row.get(9)?,
+                source_icon: None,
+            },
+        );
+        let has_track = music.title.is_some() || music.artist.is_some();
+        let (music_duration_ms, music_position_ms, music_position_at) = if has_track {
+            (row.get(26)?, row.get(27)?, row.get(28)?)
+        } else {
+            (None, None, None)
+        };
+        let music_artwork_url = music.artwork.map(|v| {
+            if is_artwork_hash(&v) {
+                format!("/music/artwork/{}", v)
+            } else {
+                v
+            }
+        });
+        let telemetry_updated_at: Option<i64> = row.get(20)?;
+        let telemetry = match telemetry_updated_at {
+            Some(updated_at) => Some(DeviceTelemetry {
+                version: row.get::<_, Option<u32>>(13)?.unwrap_or(TELEMETRY_VERSION),
+                battery_level: row.get(14)?,
+                battery_charging: row.get::<_, Option<i32>>(15)?.map(|v| v == 1),
+                network_type: row.get(16)?,
+                os: row.get(17)?,
+                platform: row.get(18)?,
+                client_version: row.get(19)?,
+                updated_at: Some(updated_at),
+            }),
+            None => None,
+        };
+        Ok(DeviceStatus {
+            device_id,
+            device_name: row.get(1)?,
+            online,
+            last_seen,
+            idle_seconds,
+            manual_offline: device_manual_offline,
+            global_manual_offline,
+            music_playing: music.playing,
+            music_title: music.title,
+            music_artist: music.artist,
+            music_source: music.source,
+            music_source_icon: music.source_icon,
+            music_album: music.album,
+            music_duration_ms,
+            music_position_ms,
+            music_position_at,
+            music_artwork_url,
+            music_updated_at: row.get(10)?,
+            stale_after_secs,
+            idle_away_secs,
+            kind: row.get(21)?,
+            icon: row.get(22)?,
+            sort_order: row.get(23)?,
+            hidden: row.get::<_, i32>(24)? == 1,
+            telemetry,
+            schedule: schedule.clone(),
+        })
+    }) {
+        Ok(rows) => rows,
+        Err(_) => return Vec::new(),
+    };
+
+    rows.filter_map(Result::ok).collect()
 }
 
 impl Notifier {
     fn from_env() -> Self {
         let tg_bot_token = normalize_env("LINK_TG_BOT_TOKEN");
         let tg_chat_id = normalize_env("LINK_TG_CHAT_ID");
+        let tg_api_base = normalize_env("LINK_TG_API_BASE")
+            .map(|v| v.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| "https://api.telegram.org".to_string());
 
         let smtp = {
             let host = normalize_env("LINK_SMTP_HOST");
@@ -3572,10 +10007,17 @@ impl Notifier {
             }
         };
 
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
         Self {
             tg_bot_token,
             tg_chat_id,
+            tg_api_base,
             smtp,
+            http,
         }
     }
 
@@ -3624,6 +10066,7 @@ impl Notifier {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn notify_review_result_email(
         &self,
         smtp_cfg: Option<&SmtpConfig>,
@@ -3688,6 +10131,61 @@ impl Notifier {
         .await
     }
 
+    async fn telegram_api<T: serde::de::DeserializeOwned>(
+        &self,
+        client: &reqwest::Client,
+        bot_token: &str,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T, String> {
+        let url = format!("{}/bot{}/{}", self.tg_api_base, bot_token, method);
+        let resp = client
+            .post(url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        let data = resp
+            .json::<TelegramResponse<T>>()
+            .await
+            .map_err(|err| err.to_string())?;
+        match (data.ok, data.result) {
+            (true, Some(result)) => Ok(result),
+            _ => Err(data
+                .description
+                .unwrap_or_else(|| format!("telegram {} failed", method))),
+        }
+    }
+
+    async fn send_telegram(
+        &self,
+        cfg: &RuntimeNotifyConfig,
+        text: &str,
+        review_application_id: Option<i64>,
+    ) -> Result<(), String> {
+        let (Some(bot_token), Some(chat_id)) =
+            (cfg.tg_bot_token.as_deref(), cfg.tg_chat_id.as_deref())
+        else {
+            return Err("telegram not configured".to_string());
+        };
+        let mut body = serde_json::json!({
+            "chat_id": chat_id,
+            "text": text,
+            "disable_web_page_preview": true,
+        });
+        if let Some(application_id) = review_application_id {
+            body["reply_markup"] = serde_json::json!({
+                "inline_keyboard": [[
+                    { "text": "✅ 通过", "callback_data": format!("approve:{}", application_id) },
+                    { "text": "❌ 拒绝", "callback_data": format!("reject:{}", application_id) },
+                ]]
+            });
+        }
+        self.telegram_api::<serde_json::Value>(&self.http, bot_token, "sendMessage", body)
+            .await
+            .map(|_| ())
+    }
+
     async fn send_smtp(
         &self,
         cfg: Option<&SmtpConfig>,
@@ -3706,6 +10204,21 @@ impl Notifier {
         plain_message: &str,
         html_message: Option<&str>,
         override_to: Option<Vec<String>>,
+    ) -> Result<(), String> {
+        let result = self
+            .send_smtp_message(cfg, subject, plain_message, html_message, override_to)
+            .await;
+        METRICS.record_smtp(result.is_ok());
+        result
+    }
+
+    async fn send_smtp_message(
+        &self,
+        cfg: Option<&SmtpConfig>,
+        subject: &str,
+        plain_message: &str,
+        html_message: Option<&str>,
+        override_to: Option<Vec<String>>,
     ) -> Result<(), String> {
         let cfg = cfg.ok_or_else(|| "smtp config missing".to_string())?;
         let recipients = override_to.unwrap_or_else(|| cfg.to.clone());
@@ -3896,6 +10409,59 @@ impl AutoReviewConfig {
     }
 }
 
+impl PresenceConfig {
+    fn from_env() -> Self {
+        let stale_after_secs = std::env::var("STATUS_DEVICE_STALE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_DEVICE_STALE_SECS)
+            .clamp(MIN_DEVICE_STALE_SECS, MAX_DEVICE_STALE_SECS);
+        let idle_away_secs = std::env::var("STATUS_IDLE_AWAY_SECS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0);
+        Self {
+            stale_after_secs,
+            idle_away_secs,
+        }
+    }
+
+    fn resolve(
+        &self,
+        stale_after_secs: Option<i64>,
+        idle_away_secs: Option<i64>,
+    ) -> DevicePresenceRules {
+        DevicePresenceRules {
+            stale_after_secs: stale_after_secs.unwrap_or(self.stale_after_secs),
+            idle_away_secs: match idle_away_secs {
+                Some(v) if v > 0 => Some(v),
+                Some(_) => None,
+                None => self.idle_away_secs,
+            },
+        }
+    }
+
+    fn device_rules(&self, conn: &Connection, device_id: &str) -> DevicePresenceRules {
+        let (stale_after_secs, idle_away_secs) = conn
+            .query_row(
+                "SELECT stale_after_secs, idle_away_secs FROM device_status WHERE device_id = ?1",
+                params![device_id],
+                |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+            )
+            .unwrap_or((None, None));
+        self.resolve(stale_after_secs, idle_away_secs)
+    }
+}
+
+impl DevicePresenceRules {
+    fn is_away(&self, idle_seconds: Option<u64>) -> bool {
+        match (self.idle_away_secs, idle_seconds) {
+            (Some(limit), Some(idle)) => idle as i64 >= limit,
+            _ => false,
+        }
+    }
+}
+
 impl AntiAbuseConfig {
     fn from_env() -> Self {
         let provider = normalize_env("LINK_CAPTCHA_PROVIDER")
@@ -4118,7 +10684,7 @@ async fn verify_captcha(config: &CaptchaConfig, token: &str, remote_ip: Option<&
 fn load_deny_hosts(extra: Option<&str>) -> HashSet<String> {
     let mut deny_hosts = default_deny_hosts();
     if let Some(extra) = extra {
-        for host in extra.split(|c| c == ',' || c == '，' || c == ';' || c == '；') {
+        for host in extra.split([',', '，', ';', '；']) {
             let normalized = normalize_host(host);
             if !normalized.is_empty() {
                 deny_hosts.insert(normalized);
@@ -4494,7 +11060,7 @@ fn normalize_host(raw: &str) -> String {
 fn parse_host_list(raw: Option<String>) -> Vec<String> {
     let mut out = HashSet::new();
     if let Some(raw) = raw {
-        for host in raw.split(|c| c == ',' || c == '，' || c == ';' || c == '；') {
+        for host in raw.split([',', '，', ';', '；']) {
             let normalized = normalize_host(host);
             if !normalized.is_empty() {
                 out.insert(normalized);
@@ -4618,6 +11184,172 @@ fn ip_prefix_key(ip: &str) -> Option<String> {
     }
 }
 
+fn device_authorized(
+    state: &AppState,
+    conn: &Connection,
+    headers: &HeaderMap,
+    device_id: &str,
+    body: &[u8],
+) -> Result<(), StatusCode> {
+    let Some(token) = request_token(headers) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let owner = conn
+        .query_row(
+            "SELECT device_id, signing_secret FROM device_credentials
+             WHERE token_hash = ?1 AND revoked_at IS NULL",
+            params![hash_secret(token)],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+        )
+        .ok();
+    if let Some((owner, signing_secret)) = owner {
+        if owner != device_id {
+            tracing::warn!(
+                "device token mismatch: token_device={} payload_device={}",
+                owner,
+                device_id
+            );
+            return Err(StatusCode::FORBIDDEN);
+        }
+        // Once a device has a signing secret every request must be signed, so a
+        // captured request cannot be replayed by stripping its signature headers.
+        match signing_secret {
+            Some(secret) => verify_request_signature(state, conn, headers, &owner, &secret, body)?,
+            None if state.require_signature => return Err(StatusCode::UNAUTHORIZED),
+            None => {}
+        }
+        let _ = conn.execute(
+            "UPDATE device_credentials SET last_used_at = ?1 WHERE device_id = ?2",
+            params![now_ts(), owner],
+        );
+        return Ok(());
+    }
+    let registered = conn
+        .query_row(
+            "SELECT 1 FROM device_credentials WHERE device_id = ?1",
+            params![device_id],
+            |_| Ok(()),
+        )
+        .is_ok();
+    if !registered
+        && state.legacy_device_token
+        && token == state.token
+        && !headers.contains_key("x-signature")
+    {
+        return Ok(());
+    }
+    Err(StatusCode::UNAUTHORIZED)
+}
+
+fn verify_request_signature(
+    state: &AppState,
+    conn: &Connection,
+    headers: &HeaderMap,
+    device_id: &str,
+    secret: &str,
+    body: &[u8],
+) -> Result<(), StatusCode> {
+    let header = |key: &str| {
+        headers
+            .get(key)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+    };
+    let (Some(timestamp), Some(nonce), Some(signature)) = (
+        header("x-timestamp"),
+        header("x-nonce"),
+        header("x-signature"),
+    ) else {
+        tracing::warn!("unsigned request rejected: device_id={}", device_id);
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let Ok(ts) = timestamp.parse::<i64>() else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if (now_ts() - ts).abs() > state.signature_max_skew_secs {
+        tracing::warn!(
+            "signed request outside clock skew: device_id={} ts={}",
+            device_id,
+            ts
+        );
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    if nonce.len() > 128 {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let Some(expected) = decode_hex(signature) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+    if mac.verify_slice(&expected).is_err() {
+        tracing::warn!("bad request signature: device_id={}", device_id);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let inserted = conn
+        .execute(
+            "INSERT OR IGNORE INTO device_request_nonces (device_id, nonce, seen_at)
+             VALUES (?1, ?2, ?3)",
+            params![device_id, nonce, now_ts()],
+        )
+        .unwrap_or(0);
+    if inserted == 0 {
+        tracing::warn!("replayed request nonce: device_id={}", device_id);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn request_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(value) = headers.get("x-token").and_then(|v| v.to_str().ok()) {
+        return Some(value);
+    }
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+fn hash_secret(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn generate_device_secret(prefix: &str) -> String {
+    let bytes: [u8; 32] = rand::random();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", prefix, hex)
+}
+
+fn generate_enrollment_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let bytes: [u8; 8] = rand::random();
+    let chars: String = bytes
+        .iter()
+        .map(|b| ALPHABET[(*b as usize) % ALPHABET.len()] as char)
+        .collect();
+    format!("{}-{}", &chars[..4], &chars[4..])
+}
+
 fn authorized(headers: &HeaderMap, token: &str) -> bool {
     if let Some(value) = headers.get("x-token") {
         if value.to_str().ok() == Some(token) {
@@ -4635,13 +11367,174 @@ fn authorized(headers: &HeaderMap, token: &str) -> bool {
 }
 
 fn is_global_manual_offline(conn: &Connection) -> bool {
-    conn.query_row(
-        "SELECT global_manual_offline FROM status_control WHERE id = 1",
-        [],
-        |row| row.get::<_, i32>(0),
+    manual_offline_state(conn, now_ts()).is_some()
+}
+
+fn manual_offline_state(conn: &Connection, now: i64) -> Option<ManualOfflineActive> {
+    let (manual, updated_at, offline_until) = conn
+        .query_row(
+            "SELECT global_manual_offline, updated_at, offline_until FROM status_control WHERE id = 1",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i32>(0)? == 1,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                ))
+            },
+        )
+        .unwrap_or((false, 0, None));
+    if manual {
+        return Some(ManualOfflineActive {
+            source: "manual".to_string(),
+            rule_id: None,
+            label: None,
+            since: Some(updated_at),
+            ends_at: None,
+        });
+    }
+    if let Some(until) = offline_until.filter(|v| *v > now) {
+        return Some(ManualOfflineActive {
+            source: "until".to_string(),
+            rule_id: None,
+            label: None,
+            since: Some(updated_at),
+            ends_at: Some(until),
+        });
+    }
+    load_quiet_hours(conn)
+        .into_iter()
+        .filter(|rule| rule.enabled)
+        .find_map(|rule| {
+            let (start, end) = quiet_hours_window(&rule, now)?;
+            Some(ManualOfflineActive {
+                source: "quiet_hours".to_string(),
+                rule_id: Some(rule.id),
+                label: rule.label,
+                since: Some(start),
+                ends_at: Some(end),
+            })
+        })
+}
+
+fn build_manual_status(conn: &Connection, now: i64) -> ManualStatusResponse {
+    let (enabled, updated_at, offline_until) = conn
+        .query_row(
+            "SELECT global_manual_offline, updated_at, offline_until FROM status_control WHERE id = 1",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i32>(0)? == 1,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                ))
+            },
+        )
+        .unwrap_or((false, 0, None));
+    ManualStatusResponse {
+        enabled,
+        updated_at,
+        offline_until: offline_until.filter(|v| *v > now),
+        quiet_hours: load_quiet_hours(conn),
+        active: manual_offline_state(conn, now),
+    }
+}
+
+fn load_quiet_hours(conn: &Connection) -> Vec<QuietHoursRule> {
+    conn.prepare(
+        "SELECT id, label, weekdays, start_time, end_time, timezone, enabled
+         FROM status_quiet_hours
+         ORDER BY id ASC",
     )
-    .map(|v| v == 1)
-    .unwrap_or(false)
+    .and_then(|mut stmt| {
+        let rows = stmt.query_map([], |row| {
+            let weekdays: String = row.get(2)?;
+            Ok(QuietHoursRule {
+                id: row.get(0)?,
+                label: row.get(1)?,
+                weekdays: weekdays
+                    .split(',')
+                    .filter_map(|v| v.trim().parse().ok())
+                    .collect(),
+                start: row.get(3)?,
+                end: row.get(4)?,
+                timezone: row.get(5)?,
+                enabled: row.get::<_, i32>(6)? == 1,
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    })
+    .unwrap_or_default()
+}
+
+fn validate_quiet_hours(input: &QuietHoursInput) -> Result<QuietHoursRule, String> {
+    let mut weekdays: Vec<u32> = input.weekdays.clone();
+    weekdays.sort_unstable();
+    weekdays.dedup();
+    if weekdays.is_empty() || weekdays.iter().any(|v| !(1..=7).contains(v)) {
+        return Err("weekdays 需为 1-7（周一为 1）".to_string());
+    }
+    let start = input.start.trim();
+    let end = input.end.trim();
+    if NaiveTime::parse_from_str(start, "%H:%M").is_err()
+        || NaiveTime::parse_from_str(end, "%H:%M").is_err()
+    {
+        return Err("时间格式需为 HH:MM".to_string());
+    }
+    let timezone = input.timezone.trim();
+    if timezone.parse::<chrono_tz::Tz>().is_err() {
+        return Err(format!("未知时区：{}", timezone));
+    }
+    Ok(QuietHoursRule {
+        id: 0,
+        label: normalize_optional(input.label.clone(), 64),
+        weekdays,
+        start: start.to_string(),
+        end: end.to_string(),
+        timezone: timezone.to_string(),
+        enabled: input.enabled.unwrap_or(true),
+    })
+}
+
+fn quiet_hours_window(rule: &QuietHoursRule, now: i64) -> Option<(i64, i64)> {
+    let tz: chrono_tz::Tz = rule.timezone.parse().ok()?;
+    let start = NaiveTime::parse_from_str(&rule.start, "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(&rule.end, "%H:%M").ok()?;
+    let today = Utc
+        .timestamp_opt(now, 0)
+        .single()?
+        .with_timezone(&tz)
+        .date_naive();
+    let to_ts = |value: chrono::NaiveDateTime| local_to_ts(tz, value);
+    for offset in [0, 1] {
+        let day = today - chrono::Duration::days(offset);
+        if !rule.weekdays.contains(&day.weekday().number_from_monday()) {
+            continue;
+        }
+        let end_day = if end > start {
+            day
+        } else {
+            day + chrono::Duration::days(1)
+        };
+        let window_start = to_ts(day.and_time(start))?;
+        let window_end = to_ts(end_day.and_time(end))?;
+        if window_start <= now && now < window_end {
+            return Some((window_start, window_end));
+        }
+    }
+    None
+}
+
+/// Local wall-clock time to unix seconds; times inside a DST gap are shifted
+/// forward by an hour.
+fn local_to_ts(tz: chrono_tz::Tz, value: chrono::NaiveDateTime) -> Option<i64> {
+    tz.from_local_datetime(&value)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(value + chrono::Duration::hours(1)))
+                .earliest()
+        })
+        .map(|v| v.timestamp())
 }
 
 fn now_ts() -> i64 {
@@ -4668,6 +11561,17 @@ fn normalize_env(key: &str) -> Option<String> {
         .filter(|v| !v.is_empty())
 }
 
+fn cache_control_env(key: &str) -> String {
+    match normalize_env(key) {
+        Some(v) if header::HeaderValue::from_str(&v).is_ok() => v,
+        Some(v) => {
+            tracing::warn!("invalid {} ignored: {}", key, v);
+            "no-cache".to_string()
+        }
+        None => "no-cache".to_string(),
+    }
+}
+
 fn normalize_optional(value: Option<String>, max_len: usize) -> Option<String> {
     value
         .map(|v| v.trim().to_string())
@@ -4726,7 +11630,7 @@ fn read_setting(conn: &Connection, key: &str) -> Option<String> {
 }
 
 fn split_recipients(raw: &str) -> Vec<String> {
-    raw.split(|c| c == ',' || c == '，' || c == ';' || c == '；' || c == '\n' || c == '\r')
+    raw.split([',', '，', ';', '；', '\n', '\r'])
         .map(|v| v.trim().to_string())
         .filter(|v| !v.is_empty())
         .collect()
@@ -4854,3 +11758,219 @@ fn smtp_mode_label(mode: SmtpMode) -> &'static str {
         SmtpMode::Plain => "plain",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import_event(body: &str) -> ScheduleItem {
+        let input = format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n{}END:VEVENT\r\nEND:VCALENDAR\r\n",
+            body
+        );
+        let events = ics::parse_components(&input, "VEVENT");
+        let input = ics_event_input(&events[0], "test", None, "test", chrono_tz::UTC).unwrap();
+        validate_schedule_item(input, 0, 0, chrono_tz::UTC).unwrap()
+    }
+
+    fn utc(value: &str) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn parses_ics_durations() {
+        let secs = |v: &str| parse_ics_duration(v).map(|d| d.num_seconds());
+        assert_eq!(secs("PT1H30M"), Some(5400));
+        assert_eq!(secs("+P1DT2H"), Some(93600));
+        assert_eq!(secs("P2W"), Some(14 * 86400));
+        assert_eq!(secs("PT45S"), Some(45));
+        assert_eq!(secs("P1H"), None);
+        assert_eq!(secs("PT1H5"), None);
+        assert_eq!(secs("PT"), Some(0));
+        assert_eq!(secs("1H"), None);
+    }
+
+    #[test]
+    fn duration_sets_end() {
+        let item = import_event(
+            "DTSTART;TZID=Europe/Berlin:20260105T090000\r\nDURATION:PT1H30M\r\nSUMMARY:a\r\n",
+        );
+        assert_eq!(item.start_at.as_deref(), Some("2026-01-05T09:00"));
+        assert_eq!(item.end_at.as_deref(), Some("2026-01-05T10:30"));
+        let item = import_event("DTSTART;VALUE=DATE:20260105\r\nDURATION:P2D\r\nSUMMARY:a\r\n");
+        assert_eq!(item.end_at.as_deref(), Some("2026-01-06"));
+    }
+
+    #[test]
+    fn exdate_in_other_tzid_is_moved_to_event_zone() {
+        let item = import_event(
+            "DTSTART;TZID=Europe/Berlin:20260105T090000\r\n\
+             DTEND;TZID=Europe/Berlin:20260105T100000\r\n\
+             RRULE:FREQ=DAILY;COUNT=4\r\n\
+             EXDATE;TZID=America/New_York:20260107T030000\r\n\
+             EXDATE:20260106T080000Z\r\n\
+             SUMMARY:a\r\n",
+        );
+        assert_eq!(item.exdates, ["2026-01-06T09:00", "2026-01-07T09:00"]);
+        let starts: Vec<_> = expand_schedule(
+            &[item],
+            utc("2026-01-01T00:00:00Z"),
+            utc("2026-02-01T00:00:00Z"),
+            false,
+        )
+        .into_iter()
+        .filter_map(|item| item.start_at)
+        .collect();
+        assert_eq!(starts, ["2026-01-05T09:00", "2026-01-08T09:00"]);
+    }
+
+    #[test]
+    fn occurrence_in_dst_gap_moves_forward() {
+        let item = import_event(
+            "DTSTART;TZID=Europe/Berlin:20260327T023000\r\n\
+             DTEND;TZID=Europe/Berlin:20260327T033000\r\n\
+             RRULE:FREQ=DAILY\r\n\
+             SUMMARY:a\r\n",
+        );
+        let occurrences = expand_schedule(
+            &[item],
+            utc("2026-03-28T00:00:00Z"),
+            utc("2026-03-30T00:00:00Z"),
+            false,
+        );
+        let starts: Vec<_> = occurrences.iter().map(|item| item.start_ts).collect();
+        assert_eq!(
+            starts,
+            [
+                Some(utc("2026-03-28T01:30:00Z")),
+                Some(utc("2026-03-29T01:30:00Z")),
+            ]
+        );
+        assert_eq!(
+            occurrences[1].recurrence_id.as_deref(),
+            Some("2026-03-29T02:30")
+        );
+    }
+
+    #[test]
+    fn expansion_keeps_occurrences_overlapping_from() {
+        let item = import_event(
+            "DTSTART;TZID=Asia/Shanghai:19900101T230000\r\n\
+             DTEND;TZID=Asia/Shanghai:19900102T010000\r\n\
+             RRULE:FREQ=DAILY\r\n\
+             SUMMARY:a\r\n",
+        );
+        let occurrences = expand_schedule(
+            &[item],
+            utc("2026-03-01T16:30:00Z"),
+            utc("2026-03-01T17:30:00Z"),
+            false,
+        );
+        assert_eq!(occurrences.len(), 1);
+        assert_eq!(
+            occurrences[0].recurrence_id.as_deref(),
+            Some("2026-03-01T23:00")
+        );
+    }
+
+    fn test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn
+    }
+
+    const PRESENCE: PresenceConfig = PresenceConfig {
+        stale_after_secs: 120,
+        idle_away_secs: None,
+    };
+
+    fn live(conn: &Connection, ts: i64) {
+        apply_heartbeat(
+            conn,
+            &PRESENCE,
+            Heartbeat {
+                device_id: "pc".to_string(),
+                device_name: "PC".to_string(),
+                online: true,
+                idle_seconds: None,
+                music_playing: Some(true),
+                music_title: Some("Song".to_string()),
+                music_artist: Some("Artist".to_string()),
+                music_source: None,
+                music_album: None,
+                music_duration_ms: None,
+                music_position_ms: None,
+                music_position_at: None,
+                music_artwork: None,
+                telemetry: None,
+            },
+            ts,
+        );
+    }
+
+    fn backfill(conn: &Connection, timestamps: impl Iterator<Item = i64>) -> usize {
+        let samples = timestamps
+            .map(|ts| HeartbeatSample {
+                ts,
+                online: true,
+                idle_seconds: None,
+                music_playing: Some(true),
+                music_title: Some("Song".to_string()),
+                music_artist: Some("Artist".to_string()),
+                music_source: None,
+            })
+            .collect();
+        let payload = HeartbeatBackfill {
+            device_id: "pc".to_string(),
+            device_name: "PC".to_string(),
+            samples,
+        };
+        apply_backfill(conn, &PRESENCE, payload, 3600, 2000).accepted
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn backfill_after_newer_live_heartbeat_fills_history() {
+        let conn = test_db();
+        live(&conn, 1000);
+        // The reconnect's live heartbeat lands before the buffered samples.
+        live(&conn, 1630);
+        assert_eq!(backfill(&conn, (1030..=1600).step_by(30)), 20);
+
+        let history = load_presence_history(&conn, &PRESENCE, "pc".to_string(), 900, 1640, 1640);
+        let online: Vec<_> = history
+            .sessions
+            .iter()
+            .filter(|v| v.state == "online")
+            .map(|v| (v.started_at, v.ended_at))
+            .collect();
+        assert_eq!(online, [(1000, 1640)]);
+        assert_eq!(count(&conn, "device_presence_sessions"), 1);
+        assert_eq!(count(&conn, "music_plays"), 1);
+        let last_seen: i64 = conn
+            .query_row("SELECT last_seen FROM device_status", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(last_seen, 1630);
+    }
+
+    #[test]
+    fn backfill_keeps_samples_from_before_manual_offline() {
+        let conn = test_db();
+        conn.execute(
+            "UPDATE status_control SET global_manual_offline = 1, updated_at = 1300",
+            [],
+        )
+        .unwrap();
+        assert_eq!(backfill(&conn, (1000..=1600).step_by(100)), 3);
+        let history = load_presence_history(&conn, &PRESENCE, "pc".to_string(), 1000, 1600, 1600);
+        assert_eq!(history.totals.online, 200);
+    }
+}
@@ -0,0 +1,291 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{LazyLock, LockResult, Mutex, MutexGuard},
+    time::Instant,
+};
+
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const LOCK_WAIT_BUCKETS: [f64; 9] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        if self.buckets.len() != bounds.len() {
+            self.buckets = vec![0; bounds.len()];
+        }
+        for (idx, bound) in bounds.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[idx] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str, bounds: &[f64]) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (idx, bound) in bounds.iter().enumerate() {
+            let count = self.buckets.get(idx).copied().unwrap_or(0);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, sep, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, sep, self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<HashMap<(String, String, u16), u64>>,
+    latency: Mutex<HashMap<(String, String), Histogram>>,
+    heartbeats: Mutex<HashMap<String, u64>>,
+    smtp: Mutex<HashMap<&'static str, u64>>,
+    captcha: Mutex<HashMap<(String, &'static str), u64>>,
+    db_wait: Mutex<Histogram>,
+}
+
+impl Metrics {
+    fn record_request(&self, route: String, method: String, status: u16, secs: f64) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((route.clone(), method.clone(), status))
+            .or_insert(0) += 1;
+        self.latency
+            .lock()
+            .unwrap()
+            .entry((route, method))
+            .or_default()
+            .observe(&LATENCY_BUCKETS, secs);
+    }
+
+    pub fn record_heartbeat(&self, device_id: &str) {
+        *self
+            .heartbeats
+            .lock()
+            .unwrap()
+            .entry(device_id.to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub fn record_smtp(&self, ok: bool) {
+        let result = if ok { "success" } else { "failure" };
+        *self.smtp.lock().unwrap().entry(result).or_insert(0) += 1;
+    }
+
+    pub fn record_captcha(&self, provider: &str, ok: bool) {
+        let result = if ok { "success" } else { "failure" };
+        *self
+            .captcha
+            .lock()
+            .unwrap()
+            .entry((provider.to_string(), result))
+            .or_insert(0) += 1;
+    }
+
+    fn record_db_wait(&self, secs: f64) {
+        self.db_wait
+            .lock()
+            .unwrap()
+            .observe(&LOCK_WAIT_BUCKETS, secs);
+    }
+
+    pub fn render(&self, out: &mut String) {
+        header(
+            out,
+            "status_backend_http_requests_total",
+            "counter",
+            "HTTP requests by route, method and status.",
+        );
+        let mut requests: Vec<_> = self
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect();
+        requests.sort();
+        for ((route, method, status), count) in requests {
+            let _ = writeln!(
+                out,
+                "status_backend_http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(&route),
+                method,
+                status,
+                count
+            );
+        }
+
+        header(
+            out,
+            "status_backend_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by route and method.",
+        );
+        let latency = self.latency.lock().unwrap();
+        let mut keys: Vec<_> = latency.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(&key.0), key.1);
+            latency[&key].render(
+                out,
+                "status_backend_http_request_duration_seconds",
+                &labels,
+                &LATENCY_BUCKETS,
+            );
+        }
+        drop(latency);
+
+        header(
+            out,
+            "status_backend_heartbeats_total",
+            "counter",
+            "Accepted heartbeats by device.",
+        );
+        let mut heartbeats: Vec<_> = self
+            .heartbeats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect();
+        heartbeats.sort();
+        for (device_id, count) in heartbeats {
+            let _ = writeln!(
+                out,
+                "status_backend_heartbeats_total{{device_id=\"{}\"}} {}",
+                escape(&device_id),
+                count
+            );
+        }
+
+        header(
+            out,
+            "status_backend_smtp_send_total",
+            "counter",
+            "SMTP sends by result.",
+        );
+        let smtp = self.smtp.lock().unwrap();
+        for result in ["success", "failure"] {
+            let _ = writeln!(
+                out,
+                "status_backend_smtp_send_total{{result=\"{}\"}} {}",
+                result,
+                smtp.get(result).copied().unwrap_or(0)
+            );
+        }
+        drop(smtp);
+
+        header(
+            out,
+            "status_backend_captcha_verify_total",
+            "counter",
+            "Captcha verifications by provider and result.",
+        );
+        let mut captcha: Vec<_> = self
+            .captcha
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect();
+        captcha.sort();
+        for ((provider, result), count) in captcha {
+            let _ = writeln!(
+                out,
+                "status_backend_captcha_verify_total{{provider=\"{}\",result=\"{}\"}} {}",
+                escape(&provider),
+                result,
+                count
+            );
+        }
+
+        header(
+            out,
+            "status_backend_db_lock_wait_seconds",
+            "histogram",
+            "Time spent waiting for the SQLite connection mutex.",
+        );
+        self.db_wait.lock().unwrap().render(
+            out,
+            "status_backend_db_lock_wait_seconds",
+            "",
+            &LOCK_WAIT_BUCKETS,
+        );
+    }
+}
+
+pub struct TimedMutex<T>(Mutex<T>);
+
+impl<T> TimedMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self(Mutex::new(value))
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let start = Instant::now();
+        let guard = self.0.lock();
+        METRICS.record_db_wait(start.elapsed().as_secs_f64());
+        guard
+    }
+}
+
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|v| v.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let resp = next.run(req).await;
+    METRICS.record_request(
+        route,
+        method,
+        resp.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+    resp
+}
+
+pub fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
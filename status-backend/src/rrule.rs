@@ -0,0 +1,496 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use std::{collections::VecDeque, fmt};
+
+/// Consecutive periods without a match before a rule is treated as exhausted
+/// (e.g. BYMONTHDAY=31 with INTERVAL=12 starting in February never matches).
+const MAX_EMPTY_PERIODS: u32 = 1000;
+/// COUNT rules are always walked from DTSTART, so the count bounds that walk.
+const MAX_COUNT: u32 = 10000;
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Clone, Copy)]
+pub enum Until {
+    Date(NaiveDate),
+    /// UTC unix seconds.
+    Instant(i64),
+}
+
+/// The subset of RFC 5545 RRULE we support: FREQ, INTERVAL, COUNT, UNTIL,
+/// BYDAY (ordinals only for MONTHLY) and BYMONTHDAY (MONTHLY only).
+#[derive(Clone)]
+pub struct Rule {
+    pub freq: Freq,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<Until>,
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+}
+
+impl Rule {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let raw = raw.strip_prefix("RRULE:").unwrap_or(raw);
+        let mut freq = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        let mut by_day = Vec::new();
+        let mut by_month_day = Vec::new();
+        for part in raw.split(';').map(str::trim).filter(|v| !v.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("无法解析：{}", part))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        "YEARLY" => Freq::Yearly,
+                        _ => return Err(format!("不支持的 FREQ：{}", value)),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|v| (1..=1000).contains(v))
+                        .ok_or_else(|| format!("INTERVAL 无效：{}", value))?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|v| (1..=MAX_COUNT).contains(v))
+                            .ok_or_else(|| format!("COUNT 无效：{}", value))?,
+                    )
+                }
+                "UNTIL" => until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    for item in value.split(',') {
+                        by_day.push(parse_by_day(item)?);
+                    }
+                }
+                "BYMONTHDAY" => {
+                    for item in value.split(',') {
+                        let day = item
+                            .trim()
+                            .parse::<i32>()
+                            .ok()
+                            .filter(|v| *v != 0 && (-31..=31).contains(v))
+                            .ok_or_else(|| format!("BYMONTHDAY 无效：{}", item))?;
+                        by_month_day.push(day);
+                    }
+                }
+                "WKST" => {}
+                _ => return Err(format!("不支持的 RRULE 字段：{}", key)),
+            }
+        }
+        let freq = freq.ok_or_else(|| "RRULE 缺少 FREQ".to_string())?;
+        if count.is_some() && until.is_some() {
+            return Err("COUNT 与 UNTIL 不能同时使用".to_string());
+        }
+        match freq {
+            Freq::Weekly if by_day.iter().any(|(ord, _)| ord.is_some()) => {
+                return Err("WEEKLY 的 BYDAY 不支持序号".to_string())
+            }
+            Freq::Monthly if !by_day.is_empty() && !by_month_day.is_empty() => {
+                return Err("BYDAY 与 BYMONTHDAY 不能同时使用".to_string())
+            }
+            Freq::Daily | Freq::Yearly if !by_day.is_empty() => {
+                return Err("BYDAY 仅支持 WEEKLY / MONTHLY".to_string())
+            }
+            Freq::Monthly => {}
+            _ if !by_month_day.is_empty() => return Err("BYMONTHDAY 仅支持 MONTHLY".to_string()),
+            _ => {}
+        }
+        by_day.sort_by_key(|(ord, day)| (*ord, day.num_days_from_monday()));
+        by_day.dedup();
+        by_month_day.sort_unstable();
+        by_month_day.dedup();
+        Ok(Self {
+            freq,
+            interval,
+            count,
+            until,
+            by_day,
+            by_month_day,
+        })
+    }
+
+    /// Dates matching the rule on or after `start`, ignoring COUNT and UNTIL
+    /// (those depend on the event's time zone and are applied by the caller).
+    pub fn dates(&self, start: NaiveDate) -> Dates<'_> {
+        self.dates_from(start, start)
+    }
+
+    /// Like [`Rule::dates`], but starts at the period containing `from`
+    /// instead of walking every period since `start`. Only meaningful
+    /// without COUNT, which has to count the skipped occurrences.
+    pub fn dates_from(&self, start: NaiveDate, from: NaiveDate) -> Dates<'_> {
+        Dates {
+            rule: self,
+            start,
+            period: self.period_index(start, from),
+            empty: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn period_index(&self, start: NaiveDate, date: NaiveDate) -> i64 {
+        let span = match self.freq {
+            Freq::Daily => (date - start).num_days(),
+            Freq::Weekly => {
+                let monday =
+                    |d: NaiveDate| d - Duration::days(d.weekday().num_days_from_monday() as i64);
+                (monday(date) - monday(start)).num_weeks()
+            }
+            Freq::Monthly => {
+                (date.year() as i64 * 12 + date.month0() as i64)
+                    - (start.year() as i64 * 12 + start.month0() as i64)
+            }
+            Freq::Yearly => (date.year() - start.year()) as i64,
+        };
+        span.max(0) / self.interval as i64
+    }
+
+    fn period_dates(&self, start: NaiveDate, period: i64) -> Vec<NaiveDate> {
+        let step = period * self.interval as i64;
+        let mut dates = match self.freq {
+            Freq::Daily => vec![start + Duration::days(step)],
+            Freq::Weekly => {
+                let monday = start - Duration::days(start.weekday().num_days_from_monday() as i64)
+                    + Duration::weeks(step);
+                if self.by_day.is_empty() {
+                    vec![monday + Duration::days(start.weekday().num_days_from_monday() as i64)]
+                } else {
+                    self.by_day
+                        .iter()
+                        .map(|(_, day)| monday + Duration::days(day.num_days_from_monday() as i64))
+                        .collect()
+                }
+            }
+            Freq::Monthly => {
+                let months = start.year() as i64 * 12 + start.month0() as i64 + step;
+                let (year, month) = ((months / 12) as i32, (months % 12) as u32 + 1);
+                if !self.by_day.is_empty() {
+                    self.by_day
+                        .iter()
+                        .flat_map(|(ord, day)| month_weekdays(year, month, *ord, *day))
+                        .collect()
+                } else if !self.by_month_day.is_empty() {
+                    self.by_month_day
+                        .iter()
+                        .filter_map(|day| month_day(year, month, *day))
+                        .collect()
+                } else {
+                    month_day(year, month, start.day() as i32)
+                        .into_iter()
+                        .collect()
+                }
+            }
+            Freq::Yearly => {
+                NaiveDate::from_ymd_opt(start.year() + step as i32, start.month(), start.day())
+                    .into_iter()
+                    .collect()
+            }
+        };
+        dates.retain(|date| *date >= start);
+        dates.sort_unstable();
+        dates.dedup();
+        dates
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Freq::Daily => "DAILY",
+            Freq::Weekly => "WEEKLY",
+            Freq::Monthly => "MONTHLY",
+            Freq::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", freq)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        match self.until {
+            Some(Until::Date(date)) => write!(f, ";UNTIL={}", date.format("%Y%m%d"))?,
+            Some(Until::Instant(ts)) => {
+                if let Some(dt) = chrono::DateTime::from_timestamp(ts, 0) {
+                    write!(f, ";UNTIL={}", dt.format("%Y%m%dT%H%M%SZ"))?;
+                }
+            }
+            None => {}
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|(ord, day)| {
+                    let code = WEEKDAYS
+                        .iter()
+                        .find(|(_, wd)| wd == day)
+                        .map(|(code, _)| *code)
+                        .unwrap_or("MO");
+                    match ord {
+                        Some(ord) => format!("{}{}", ord, code),
+                        None => code.to_string(),
+                    }
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(|v| v.to_string()).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Dates<'a> {
+    rule: &'a Rule,
+    start: NaiveDate,
+    period: i64,
+    empty: u32,
+    pending: VecDeque<NaiveDate>,
+}
+
+impl Iterator for Dates<'_> {
+    type Item = NaiveDate;
+
+    fn next(&mut self) -> Option<NaiveDate> {
+        while self.pending.is_empty() {
+            if self.empty >= MAX_EMPTY_PERIODS {
+                return None;
+            }
+            self.pending = self.rule.period_dates(self.start, self.period).into();
+            self.period += 1;
+            self.empty = if self.pending.is_empty() {
+                self.empty + 1
+            } else {
+                0
+            };
+        }
+        self.pending.pop_front()
+    }
+}
+
+fn parse_until(value: &str) -> Result<Until, String> {
+    let value = value.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Ok(Until::Date(date));
+    }
+    let naive = value.strip_suffix('Z').unwrap_or(value);
+    NaiveDateTime::parse_from_str(naive, "%Y%m%dT%H%M%S")
+        .map(|dt| Until::Instant(dt.and_utc().timestamp()))
+        .map_err(|_| format!("UNTIL 无效：{}", value))
+}
+
+fn parse_by_day(item: &str) -> Result<(Option<i32>, Weekday), String> {
+    let item = item.trim().to_ascii_uppercase();
+    if item.len() < 2 {
+        return Err(format!("BYDAY 无效：{}", item));
+    }
+    let (ord, code) = item.split_at(item.len() - 2);
+    let day = WEEKDAYS
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map(|(_, day)| *day)
+        .ok_or_else(|| format!("BYDAY 无效：{}", item))?;
+    if ord.is_empty() {
+        return Ok((None, day));
+    }
+    let ord = ord
+        .trim_start_matches('+')
+        .parse::<i32>()
+        .ok()
+        .filter(|v| *v != 0 && (-5..=5).contains(v))
+        .ok_or_else(|| format!("BYDAY 无效：{}", item))?;
+    Ok((Some(ord), day))
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|v| v.pred_opt())
+        .map(|v| v.day())
+        .unwrap_or(28)
+}
+
+fn month_day(year: i32, month: u32, day: i32) -> Option<NaiveDate> {
+    let last = days_in_month(year, month) as i32;
+    let day = if day < 0 { last + day + 1 } else { day };
+    if day < 1 || day > last {
+        return None;
+    }
+    NaiveDate::from_ymd_opt(year, month, day as u32)
+}
+
+fn month_weekdays(year: i32, month: u32, ord: Option<i32>, weekday: Weekday) -> Vec<NaiveDate> {
+    let all: Vec<NaiveDate> = (1..=days_in_month(year, month))
+        .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+        .filter(|date| date.weekday() == weekday)
+        .collect();
+    match ord {
+        None => all,
+        Some(ord) if ord > 0 => all.get(ord as usize - 1).copied().into_iter().collect(),
+        Some(ord) => all
+            .len()
+            .checked_sub(ord.unsigned_abs() as usize)
+            .and_then(|idx| all.get(idx).copied())
+            .into_iter()
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn first(rule: &str, start: NaiveDate, n: usize) -> Vec<NaiveDate> {
+        Rule::parse(rule).unwrap().dates(start).take(n).collect()
+    }
+
+    #[test]
+    fn negative_byday_ordinal_picks_last_weekday() {
+        assert_eq!(
+            first("FREQ=MONTHLY;BYDAY=-1FR", ymd(2026, 1, 1), 3),
+            [ymd(2026, 1, 30), ymd(2026, 2, 27), ymd(2026, 3, 27)]
+        );
+        assert_eq!(
+            first("FREQ=MONTHLY;BYDAY=-2MO", ymd(2026, 1, 1), 2),
+            [ymd(2026, 1, 19), ymd(2026, 2, 16)]
+        );
+    }
+
+    #[test]
+    fn fifth_weekday_skips_months_without_one() {
+        assert_eq!(
+            first("FREQ=MONTHLY;BYDAY=5SU", ymd(2026, 1, 1), 2),
+            [ymd(2026, 3, 29), ymd(2026, 5, 31)]
+        );
+    }
+
+    #[test]
+    fn bymonthday_31_skips_short_months() {
+        assert_eq!(
+            first("FREQ=MONTHLY;BYMONTHDAY=31", ymd(2026, 1, 31), 4),
+            [
+                ymd(2026, 1, 31),
+                ymd(2026, 3, 31),
+                ymd(2026, 5, 31),
+                ymd(2026, 7, 31)
+            ]
+        );
+        assert_eq!(
+            first("FREQ=MONTHLY;BYMONTHDAY=-1", ymd(2024, 1, 31), 3),
+            [ymd(2024, 1, 31), ymd(2024, 2, 29), ymd(2024, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn yearly_feb_29_only_in_leap_years() {
+        assert_eq!(
+            first("FREQ=YEARLY", ymd(2096, 2, 29), 2),
+            [ymd(2096, 2, 29), ymd(2104, 2, 29)]
+        );
+    }
+
+    #[test]
+    fn weekly_byday_excludes_days_before_start() {
+        assert_eq!(
+            first("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR", ymd(2026, 1, 7), 3),
+            [ymd(2026, 1, 9), ymd(2026, 1, 19), ymd(2026, 1, 23)]
+        );
+    }
+
+    #[test]
+    fn never_matching_rule_ends() {
+        let rule = Rule::parse("FREQ=MONTHLY;INTERVAL=12;BYMONTHDAY=30").unwrap();
+        assert_eq!(rule.dates(ymd(2026, 2, 1)).next(), None);
+    }
+
+    #[test]
+    fn dates_from_matches_full_walk() {
+        let start = ymd(1990, 1, 31);
+        let from = ymd(2026, 3, 15);
+        for raw in [
+            "FREQ=DAILY;INTERVAL=3",
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,SU",
+            "FREQ=MONTHLY;BYMONTHDAY=31",
+            "FREQ=MONTHLY;INTERVAL=5;BYDAY=-1FR",
+            "FREQ=YEARLY;INTERVAL=2",
+        ] {
+            let rule = Rule::parse(raw).unwrap();
+            let walked: Vec<_> = rule
+                .dates(start)
+                .skip_while(|date| *date < from)
+                .take(5)
+                .collect();
+            let jumped: Vec<_> = rule
+                .dates_from(start, from)
+                .skip_while(|date| *date < from)
+                .take(5)
+                .collect();
+            assert_eq!(walked.len(), 5, "{}", raw);
+            assert_eq!(walked, jumped, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn long_daily_series_keeps_going() {
+        let rule = Rule::parse("FREQ=DAILY").unwrap();
+        let from = ymd(2090, 6, 1);
+        assert_eq!(rule.dates_from(ymd(1970, 1, 1), from).next(), Some(from));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_combinations() {
+        assert!(Rule::parse("FREQ=DAILY;COUNT=3;UNTIL=20260101").is_err());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=1MO").is_err());
+        assert!(Rule::parse("FREQ=DAILY;BYMONTHDAY=1").is_err());
+        assert!(Rule::parse("FREQ=MONTHLY;BYDAY=6MO").is_err());
+        assert!(Rule::parse("INTERVAL=2").is_err());
+        assert!(Rule::parse("FREQ=DAILY;COUNT=0").is_err());
+        assert!(Rule::parse("FREQ=DAILY;COUNT=10000").is_ok());
+        assert!(Rule::parse("FREQ=DAILY;COUNT=10001").is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let raw = "FREQ=MONTHLY;INTERVAL=2;UNTIL=20261231T120000Z;BYDAY=-1FR,1MO";
+        let rule = Rule::parse(&format!("RRULE:{}", raw)).unwrap();
+        assert_eq!(rule.to_string(), raw);
+    }
+}
@@ -1,12 +1,17 @@
 #![cfg_attr(windows, windows_subsystem = "windows")]
 
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::{
     fs,
     fs::OpenOptions,
     io::Write,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     thread,
     time::Duration,
 };
@@ -25,6 +30,42 @@ struct Heartbeat {
     music_title: Option<String>,
     music_artist: Option<String>,
     music_source: Option<String>,
+    music_album: Option<String>,
+    music_duration_ms: Option<i64>,
+    music_position_ms: Option<i64>,
+    music_position_at: Option<i64>,
+    music_artwork: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    telemetry: Option<Telemetry>,
+}
+
+#[derive(Serialize)]
+struct BackfillSample {
+    ts: u64,
+    online: bool,
+    idle_seconds: Option<u64>,
+    music_playing: bool,
+    music_title: Option<String>,
+    music_artist: Option<String>,
+    music_source: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BackfillRequest<'a> {
+    device_id: &'a str,
+    device_name: &'a str,
+    samples: &'a [BackfillSample],
+}
+
+#[derive(Serialize, Debug)]
+struct Telemetry {
+    version: u32,
+    battery_level: Option<u8>,
+    battery_charging: Option<bool>,
+    network_type: Option<String>,
+    os: Option<String>,
+    platform: String,
+    client_version: String,
 }
 
 #[derive(Default, Clone, PartialEq, Eq, Debug)]
@@ -33,40 +74,86 @@ struct MusicState {
     title: Option<String>,
     artist: Option<String>,
     source: Option<String>,
+    album: Option<String>,
+    duration_ms: Option<i64>,
+    position_ms: Option<i64>,
+    art_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ArtworkUploadResponse {
+    hash: String,
 }
 
 #[derive(Default, Deserialize)]
 struct ConfigFile {
     endpoint: Option<String>,
     token: Option<String>,
+    signing_secret: Option<String>,
+    enrollment_code: Option<String>,
     device_id: Option<String>,
     device_name: Option<String>,
     idle_timeout_secs: Option<u64>,
     heartbeat_interval_secs: Option<u64>,
     music_poll_interval_secs: Option<u64>,
     music_push_min_interval_secs: Option<u64>,
+    telemetry: Option<bool>,
+    backfill_max_samples: Option<usize>,
     log_file: Option<String>,
     log_max_bytes: Option<u64>,
 }
 
+/// Device credentials issued by enrollment, kept apart from the hand-edited
+/// config so it is never rewritten.
+#[derive(Default, Deserialize, Serialize)]
+struct CredentialsFile {
+    device_id: Option<String>,
+    token: Option<String>,
+    signing_secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EnrollRedeemResponse {
+    device_id: String,
+    token: String,
+    signing_secret: Option<String>,
+}
+
+static NONCE_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Set while heartbeats are buffered for backfill; music pushes wait for the
+/// buffer to be flushed so the backend sees the samples in order.
+static BACKFILL_PENDING: AtomicBool = AtomicBool::new(false);
+/// Last local artwork URL and its uploaded hash (`None` while uploading or
+/// after a failed upload, so each URL is uploaded at most once).
+static ARTWORK_CACHE: Mutex<Option<(String, Option<String>)>> = Mutex::new(None);
+const ARTWORK_MAX_BYTES: u64 = 1024 * 1024;
+const BACKFILL_BATCH_SIZE: usize = 200;
+
 #[derive(Clone)]
 struct Config {
     endpoint: String,
     token: String,
+    signing_secret: Option<String>,
+    enrollment_code: Option<String>,
     device_id: String,
     device_name: String,
     idle_timeout_secs: u64,
     heartbeat_interval_secs: u64,
     music_poll_interval_secs: u64,
     music_push_min_interval_secs: u64,
+    telemetry: bool,
+    backfill_max_samples: usize,
     log_file: String,
     log_max_bytes: u64,
 }
 
 fn main() {
-    let cfg = load_config();
+    let mut cfg = load_config();
     let (_log_guard, log_path) = init_logging(&cfg);
     tracing::info!("status-client starting: log={}", log_path.display());
+    if cfg.enrollment_code.is_some() {
+        redeem_enrollment_code(&mut cfg);
+    }
 
     let status = Arc::new(Mutex::new(String::from("starting")));
 
@@ -85,11 +172,34 @@ fn main() {
 
 fn heartbeat_loop(cfg: Config, status: Arc<Mutex<String>>) {
     let client = reqwest::blocking::Client::new();
+    let mut pending: Vec<BackfillSample> = Vec::new();
     loop {
         enforce_log_size(&resolve_path(&cfg.log_file), cfg.log_max_bytes);
         let tick = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            let payload = build_payload(&cfg);
-            send_payload(&client, &cfg, &status, payload, "heartbeat");
+            let mut payload = build_payload(&cfg);
+            if cfg.telemetry {
+                payload.telemetry = Some(current_telemetry());
+            }
+            let sample = BackfillSample {
+                ts: chrono_like_ts(),
+                online: payload.online,
+                idle_seconds: payload.idle_seconds,
+                music_playing: payload.music_playing,
+                music_title: payload.music_title.clone(),
+                music_artist: payload.music_artist.clone(),
+                music_source: payload.music_source.clone(),
+            };
+            let delivered = (pending.is_empty() || flush_backfill(&client, &cfg, &mut pending))
+                && send_payload(&client, &cfg, &status, payload, "heartbeat");
+            if !delivered && cfg.backfill_max_samples > 0 {
+                pending.push(sample);
+                if pending.len() > cfg.backfill_max_samples {
+                    let overflow = pending.len() - cfg.backfill_max_samples;
+                    pending.drain(..overflow);
+                }
+                tracing::info!("heartbeat buffered for backfill: pending={}", pending.len());
+            }
+            BACKFILL_PENDING.store(!pending.is_empty(), Ordering::Relaxed);
         }));
         if tick.is_err() {
             tracing::error!("heartbeat loop panic recovered");
@@ -112,10 +222,14 @@ fn music_push_loop(cfg: Config, status: Arc<Mutex<String>>) {
                 title: payload.music_title.clone(),
                 artist: payload.music_artist.clone(),
                 source: payload.music_source.clone(),
+                album: payload.music_album.clone(),
+                duration_ms: payload.music_duration_ms,
+                position_ms: None,
+                art_url: payload.music_artwork.clone(),
             };
             let changed = last_music.as_ref().map(|v| v != &current).unwrap_or(true);
             let now = chrono_like_ts();
-            if changed {
+            if changed && !BACKFILL_PENDING.load(Ordering::Relaxed) {
                 let can_push = last_push_ts == 0
                     || now.saturating_sub(last_push_ts) >= cfg.music_push_min_interval_secs;
                 if can_push {
@@ -145,23 +259,191 @@ fn build_payload(cfg: &Config) -> Heartbeat {
         music_title: None,
         music_artist: None,
         music_source: None,
+        music_album: None,
+        music_duration_ms: None,
+        music_position_ms: None,
+        music_position_at: None,
+        music_artwork: None,
+        telemetry: None,
     };
     if let Some(music) = current_music() {
         payload.music_playing = music.playing;
         payload.music_title = music.title;
         payload.music_artist = music.artist;
         payload.music_source = music.source;
+        payload.music_album = music.album;
+        payload.music_duration_ms = music.duration_ms;
+        payload.music_position_at = music.position_ms.map(|_| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(0)
+        });
+        payload.music_position_ms = music.position_ms;
+        payload.music_artwork = music
+            .art_url
+            .as_deref()
+            .and_then(|url| resolve_artwork(cfg, url));
     }
     payload
 }
 
+fn resolve_artwork(cfg: &Config, art_url: &str) -> Option<String> {
+    if art_url.starts_with("http://") || art_url.starts_with("https://") {
+        return Some(art_url.to_string());
+    }
+    let path = art_url.strip_prefix("file://")?;
+    let mut cache = ARTWORK_CACHE.lock().ok()?;
+    if let Some((cached_url, hash)) = cache.as_ref() {
+        if cached_url == art_url {
+            return hash.clone();
+        }
+    }
+    // Upload in the background so heartbeats are never held up; the hash is
+    // reported by the next payload once the upload has finished.
+    *cache = Some((art_url.to_string(), None));
+    drop(cache);
+    let cfg = cfg.clone();
+    let art_url = art_url.to_string();
+    let path = percent_decode(path);
+    thread::spawn(move || upload_artwork(&cfg, &art_url, &path));
+    None
+}
+
+fn upload_artwork(cfg: &Config, art_url: &str, path: &str) {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() > ARTWORK_MAX_BYTES => {
+            tracing::info!("artwork too large, skipped: {}", path);
+            return;
+        }
+        Ok(_) => {}
+        Err(_) => return,
+    }
+    let Ok(body) = fs::read(path) else {
+        return;
+    };
+    let base = cfg.endpoint.trim_end_matches('/');
+    let base = base.strip_suffix("/heartbeat").unwrap_or(base);
+    let url = format!("{}/music/artwork", base);
+    let client = reqwest::blocking::Client::new();
+    let uploaded = post_signed(
+        &client,
+        cfg,
+        &url,
+        &[("device_id", cfg.device_id.as_str())],
+        body,
+    )
+    .and_then(|resp| resp.error_for_status())
+    .and_then(|resp| resp.json::<ArtworkUploadResponse>());
+    match uploaded {
+        Ok(v) => {
+            tracing::info!("artwork uploaded: {}", v.hash);
+            if let Ok(mut cache) = ARTWORK_CACHE.lock() {
+                if cache.as_ref().is_some_and(|(url, _)| url == art_url) {
+                    *cache = Some((art_url.to_string(), Some(v.hash)));
+                }
+            }
+        }
+        Err(err) => tracing::warn!("artwork upload failed: {}", err),
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[idx + 1..idx + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                out.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn flush_backfill(
+    client: &reqwest::blocking::Client,
+    cfg: &Config,
+    pending: &mut Vec<BackfillSample>,
+) -> bool {
+    let base = cfg.endpoint.trim_end_matches('/');
+    let base = base.strip_suffix("/heartbeat").unwrap_or(base);
+    let url = format!("{}/heartbeat/backfill", base);
+    while !pending.is_empty() {
+        let count = pending.len().min(BACKFILL_BATCH_SIZE);
+        let body = match serde_json::to_vec(&BackfillRequest {
+            device_id: &cfg.device_id,
+            device_name: &cfg.device_name,
+            samples: &pending[..count],
+        }) {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::warn!("backfill encode error: {}", err);
+                pending.clear();
+                return true;
+            }
+        };
+        match post_signed(client, cfg, &url, &[], body) {
+            Ok(resp) => {
+                if resp.status().is_success() {
+                    tracing::info!("backfill sent: samples={} status={}", count, resp.status());
+                } else {
+                    tracing::warn!(
+                        "backfill rejected, dropping {} samples: status={}",
+                        count,
+                        resp.status()
+                    );
+                }
+                pending.drain(..count);
+            }
+            Err(err) => {
+                tracing::warn!("backfill request error: {}", err);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn post_signed(
+    client: &reqwest::blocking::Client,
+    cfg: &Config,
+    url: &str,
+    query: &[(&str, &str)],
+    body: Vec<u8>,
+) -> reqwest::Result<reqwest::blocking::Response> {
+    let mut req = client
+        .post(url)
+        .query(query)
+        .header("x-token", &cfg.token)
+        .header("content-type", "application/json");
+    if let Some(secret) = cfg.signing_secret.as_deref() {
+        let timestamp = chrono_like_ts().to_string();
+        let nonce = request_nonce();
+        req = req
+            .header("x-timestamp", &timestamp)
+            .header("x-nonce", &nonce)
+            .header(
+                "x-signature",
+                sign_request(secret, &timestamp, &nonce, &body),
+            );
+    }
+    req.body(body).send()
+}
+
 fn send_payload(
     client: &reqwest::blocking::Client,
     cfg: &Config,
     status: &Arc<Mutex<String>>,
     payload: Heartbeat,
     reason: &str,
-) {
+) -> bool {
     tracing::info!(
         "{} build: online={} idle={:?} music_playing={} title={:?} artist={:?} source={:?}",
         reason,
@@ -172,11 +454,17 @@ fn send_payload(
         payload.music_artist,
         payload.music_source
     );
-    let res = client
-        .post(&cfg.endpoint)
-        .header("x-token", &cfg.token)
-        .json(&payload)
-        .send();
+    if let Some(telemetry) = payload.telemetry.as_ref() {
+        tracing::info!("{} telemetry: {:?}", reason, telemetry);
+    }
+    let body = match serde_json::to_vec(&payload) {
+        Ok(v) => v,
+        Err(err) => {
+            tracing::warn!("{} encode error: {}", reason, err);
+            return true;
+        }
+    };
+    let res = post_signed(client, cfg, &cfg.endpoint, &[], body);
     match res {
         Ok(resp) => {
             let label: &str = if resp.status().is_success() { "online" } else { "error" };
@@ -187,31 +475,50 @@ fn send_payload(
             if !resp.status().is_success() {
                 tracing::warn!("{} failed: status={}", reason, resp.status());
             }
+            true
         }
         Err(err) => {
             if let Ok(mut s) = status.lock() {
                 *s = "error".to_string();
             }
             tracing::warn!("{} request error: {}", reason, err);
+            false
         }
     }
 }
 
 fn load_config() -> Config {
     let file_cfg = read_config_file();
+    let creds = read_credentials_file();
 
     let endpoint = std::env::var("STATUS_ENDPOINT")
         .ok()
         .or(file_cfg.endpoint)
         .unwrap_or_else(|| "http://xxx.com:7999/heartbeat".to_string());
 
+    let enrolled = creds.token.is_some();
     let token = std::env::var("STATUS_TOKEN")
         .ok()
+        .or(creds.token)
         .or(file_cfg.token)
         .unwrap_or_else(|| "you_token".to_string());
 
+    let signing_secret = std::env::var("STATUS_SIGNING_SECRET")
+        .ok()
+        .or(creds.signing_secret)
+        .or(file_cfg.signing_secret)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+
+    let enrollment_code = std::env::var("STATUS_ENROLL_CODE")
+        .ok()
+        .or(file_cfg.enrollment_code)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty() && !enrolled);
+
     let device_id = std::env::var("DEVICE_ID")
         .ok()
+        .or(creds.device_id)
         .or(file_cfg.device_id)
         .unwrap_or_else(hostname);
 
@@ -241,6 +548,16 @@ fn load_config() -> Config {
         .and_then(|v| v.parse().ok())
         .or(file_cfg.music_push_min_interval_secs)
         .unwrap_or(6);
+    let telemetry = std::env::var("STATUS_TELEMETRY")
+        .ok()
+        .map(|v| v != "0" && v.to_lowercase() != "false")
+        .or(file_cfg.telemetry)
+        .unwrap_or(false);
+    let backfill_max_samples = std::env::var("BACKFILL_MAX_SAMPLES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .or(file_cfg.backfill_max_samples)
+        .unwrap_or(1440);
 
     let log_file = std::env::var("LOG_FILE")
         .ok()
@@ -255,27 +572,120 @@ fn load_config() -> Config {
     Config {
         endpoint,
         token,
+        signing_secret,
+        enrollment_code,
         device_id,
         device_name,
         idle_timeout_secs,
         heartbeat_interval_secs,
         music_poll_interval_secs,
         music_push_min_interval_secs,
+        telemetry,
+        backfill_max_samples,
         log_file,
         log_max_bytes,
     }
 }
 
 fn read_config_file() -> ConfigFile {
-    let path = std::env::var("STATUS_CONFIG")
+    let data = fs::read_to_string(config_file_path()).unwrap_or_default();
+    toml::from_str(&data).unwrap_or_default()
+}
+
+fn config_file_path() -> PathBuf {
+    std::env::var("STATUS_CONFIG")
         .ok()
         .map(PathBuf::from)
-        .unwrap_or_else(|| config_path());
+        .unwrap_or_else(config_path)
+}
 
-    let data = fs::read_to_string(path).unwrap_or_default();
+fn read_credentials_file() -> CredentialsFile {
+    let data = fs::read_to_string(credentials_file_path()).unwrap_or_default();
     toml::from_str(&data).unwrap_or_default()
 }
 
+fn credentials_file_path() -> PathBuf {
+    std::env::var("STATUS_CREDENTIALS")
+        .ok()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config_file_path().with_file_name("status-client.credentials.toml"))
+}
+
+fn write_credentials_file(path: &std::path::Path, creds: &CredentialsFile) -> Result<(), String> {
+    let text = toml::to_string(creds).map_err(|err| err.to_string())?;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|err| err.to_string())?;
+    // `mode` only applies to newly created files.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(|err| err.to_string())?;
+    }
+    file.write_all(text.as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+fn redeem_enrollment_code(cfg: &mut Config) {
+    let Some(code) = cfg.enrollment_code.take() else {
+        return;
+    };
+    let base = cfg.endpoint.trim_end_matches('/');
+    let base = base.strip_suffix("/heartbeat").unwrap_or(base);
+    let url = format!("{}/device/enroll/redeem", base);
+    let client = reqwest::blocking::Client::new();
+    let res = client
+        .post(&url)
+        .json(&serde_json::json!({ "code": code }))
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.json::<EnrollRedeemResponse>());
+    let redeemed = match res {
+        Ok(v) => v,
+        Err(err) => {
+            tracing::warn!("enrollment failed: {}", err);
+            return;
+        }
+    };
+    if redeemed.device_id != cfg.device_id {
+        tracing::warn!(
+            "enrollment code is for device_id={}, overriding configured device_id={}",
+            redeemed.device_id,
+            cfg.device_id
+        );
+        cfg.device_id = redeemed.device_id.clone();
+    }
+    cfg.token = redeemed.token;
+    if redeemed.signing_secret.is_some() {
+        cfg.signing_secret = redeemed.signing_secret;
+    }
+    tracing::info!("enrolled as device_id={}", cfg.device_id);
+
+    let path = credentials_file_path();
+    let creds = CredentialsFile {
+        device_id: Some(cfg.device_id.clone()),
+        token: Some(cfg.token.clone()),
+        signing_secret: cfg.signing_secret.clone(),
+    };
+    match write_credentials_file(&path, &creds) {
+        Ok(()) => tracing::info!(
+            "device credentials saved to {}; enrollment_code can be removed from the config",
+            path.display()
+        ),
+        Err(err) => tracing::warn!(
+            "failed to save device credentials to {}: {}",
+            path.display(),
+            err
+        ),
+    }
+}
+
 fn config_path() -> PathBuf {
     if let Ok(exe) = std::env::current_exe() {
         if let Some(dir) = exe.parent() {
@@ -319,6 +729,30 @@ fn init_logging(cfg: &Config) -> (tracing_appender::non_blocking::WorkerGuard, P
     (guard, log_path)
 }
 
+fn sign_request(secret: &str, timestamp: &str, nonce: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn request_nonce() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let counter = NONCE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}-{:x}", nanos, std::process::id(), counter)
+}
+
 fn chrono_like_ts() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -428,16 +862,20 @@ fn current_music() -> Option<MusicState> {
 
         let title_owned = media.Title().ok().map(|s| s.to_string());
         let artist_owned = media.Artist().ok().map(|s| s.to_string());
+        let album_owned = media.AlbumTitle().ok().map(|s| s.to_string());
         let source_owned = session.SourceAppUserModelId().ok().map(|s| s.to_string());
 
         let title = clean_text(title_owned.as_deref());
         let artist = clean_text(artist_owned.as_deref());
+        let album = clean_text(album_owned.as_deref());
         let source = clean_text(source_owned.as_deref());
         Ok(Some(MusicState {
             playing,
             title,
             artist,
             source,
+            album,
+            ..Default::default()
         }))
     });
 
@@ -454,7 +892,13 @@ fn current_music() -> Option<MusicState> {
     let playing = state.playing;
     if title.is_none() && artist.is_none() {
         tracing::info!("smtc found session but no title/artist");
-        return Some(MusicState { playing: false, title, artist, source });
+        return Some(MusicState {
+            playing: false,
+            title,
+            artist,
+            source,
+            ..Default::default()
+        });
     }
     tracing::info!(
         "smtc ok: playing={} title={:?} artist={:?} source={:?}",
@@ -469,7 +913,12 @@ fn current_music() -> Option<MusicState> {
 #[cfg(target_os = "linux")]
 fn current_music() -> Option<MusicState> {
     let out = Command::new("playerctl")
-        .args(["-a", "metadata", "--format", "{{status}}\t{{title}}\t{{artist}}\t{{playerName}}"])
+        .args([
+            "-a",
+            "metadata",
+            "--format",
+            "{{status}}\t{{title}}\t{{artist}}\t{{playerName}}\t{{album}}\t{{mpris:length}}\t{{mpris:artUrl}}\t{{position}}",
+        ])
         .output()
         .ok()?;
     if !out.status.success() {
@@ -483,12 +932,25 @@ fn current_music() -> Option<MusicState> {
         let title = clean_text(parts.next());
         let artist = clean_text(parts.next());
         let source = clean_text(parts.next());
+        let album = clean_text(parts.next());
+        let micros = |value: Option<&str>| {
+            value
+                .and_then(|v| v.trim().parse::<i64>().ok())
+                .map(|v| v / 1000)
+        };
+        let duration_ms = micros(parts.next()).filter(|v| *v > 0);
+        let art_url = clean_text(parts.next());
+        let position_ms = micros(parts.next());
         let playing = status.eq_ignore_ascii_case("playing");
         let state = MusicState {
             playing,
             title,
             artist,
             source,
+            album,
+            duration_ms,
+            position_ms,
+            art_url,
         };
         if state.playing {
             return Some(state);
@@ -505,6 +967,109 @@ fn current_music() -> Option<MusicState> {
     None
 }
 
+fn current_telemetry() -> Telemetry {
+    let (battery_level, battery_charging) = battery_state().unwrap_or((None, None));
+    Telemetry {
+        version: 1,
+        battery_level,
+        battery_charging,
+        network_type: network_type(),
+        os: os_name(),
+        platform: std::env::consts::OS.to_string(),
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn battery_state() -> Option<(Option<u8>, Option<bool>)> {
+    let entries = fs::read_dir("/sys/class/power_supply").ok()?;
+    let read = |path: &std::path::Path, name: &str| {
+        fs::read_to_string(path.join(name))
+            .ok()
+            .map(|v| v.trim().to_string())
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if read(&path, "type").as_deref() != Some("Battery") {
+            continue;
+        }
+        if read(&path, "present").as_deref() == Some("0") {
+            continue;
+        }
+        let level = read(&path, "capacity")
+            .and_then(|v| v.parse::<u8>().ok())
+            .map(|v| v.min(100));
+        let charging = read(&path, "status").map(|v| matches!(v.as_str(), "Charging" | "Full"));
+        return Some((level, charging));
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn battery_state() -> Option<(Option<u8>, Option<bool>)> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn network_type() -> Option<String> {
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    let iface = routes.lines().skip(1).find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let iface = parts.next()?;
+        let destination = parts.next()?;
+        (destination == "00000000").then(|| iface.to_string())
+    });
+    let Some(iface) = iface else {
+        return Some("offline".to_string());
+    };
+    let wireless = std::path::Path::new("/sys/class/net")
+        .join(&iface)
+        .join("wireless")
+        .exists()
+        || fs::read_to_string("/proc/net/wireless")
+            .map(|v| {
+                v.lines()
+                    .any(|line| line.trim_start().starts_with(&format!("{}:", iface)))
+            })
+            .unwrap_or(false);
+    let kind = if wireless {
+        "wifi"
+    } else if iface.starts_with("ww") || iface.starts_with("rmnet") {
+        "cellular"
+    } else if iface.starts_with("tun") || iface.starts_with("wg") || iface.starts_with("ppp") {
+        "vpn"
+    } else {
+        "ethernet"
+    };
+    Some(kind.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn network_type() -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn os_name() -> Option<String> {
+    let release = fs::read_to_string("/etc/os-release").unwrap_or_default();
+    release
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|v| v.trim_matches('"').to_string())
+        .filter(|v| !v.is_empty())
+        .or_else(|| Some("Linux".to_string()))
+}
+
+#[cfg(windows)]
+fn os_name() -> Option<String> {
+    Some("Windows".to_string())
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn os_name() -> Option<String> {
+    None
+}
+
 #[cfg(windows)]
 fn idle_seconds() -> Option<u64> {
     use windows_sys::Win32::Foundation::BOOL;